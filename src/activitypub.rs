@@ -0,0 +1,759 @@
+//! A minimal ActivityPub server, so the bot can be followed without an account
+//! on a Mastodon instance
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use anyhow::{anyhow, bail, Context, Result};
+use async_trait::async_trait;
+use axum::body::Bytes;
+use axum::extract::{Path as UrlPath, Query, State};
+use axum::http::{header, HeaderMap, Method, StatusCode, Uri};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::Router;
+use chrono::{DateTime, Utc};
+use openssl::pkey::{PKey, Private};
+use openssl::rsa::Rsa;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tracing::{debug, error, info, warn};
+
+use crate::files;
+use crate::http_signature::{self, Signature};
+use crate::publisher::{escape_html, Publisher, TIMEOUT};
+use crate::register::write_private;
+
+/// Name of the ActivityPub publisher
+pub const ACTIVITYPUB: &str = "activitypub";
+const CONTENT_TYPE: &str = "application/activity+json";
+const PUBLIC: &str = "https://www.w3.org/ns/activitystreams#Public";
+/// Notes kept for the outbox
+const OUTBOX_SIZE: usize = 20;
+
+/// Serializes changes to the store between the server and the publisher
+static STORE_LOCK: tokio::sync::Mutex<()> = tokio::sync::Mutex::const_new(());
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ActivityPubConfig {
+    /// Public url of the server, e.g. "https://vader.example"
+    pub url: String,
+    /// Address to listen on
+    #[serde(default = "default_listen")]
+    pub listen: String,
+    #[serde(default = "default_username")]
+    pub username: String,
+    #[serde(default)]
+    pub display_name: Option<String>,
+    #[serde(default)]
+    pub summary: Option<String>,
+    /// Private key in PEM format, created if missing
+    #[serde(default = "default_key")]
+    pub key: PathBuf,
+    /// Followers and the latest notes
+    #[serde(default = "default_store")]
+    pub store: PathBuf,
+}
+
+fn default_listen() -> String {
+    "127.0.0.1:8080".to_string()
+}
+
+fn default_username() -> String {
+    "vader".to_string()
+}
+
+fn default_key() -> PathBuf {
+    PathBuf::from("activitypub.pem")
+}
+
+fn default_store() -> PathBuf {
+    PathBuf::from("activitypub.toml")
+}
+
+/// Followers and the latest notes
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Store {
+    /// Inbox of each follower, by actor id
+    #[serde(default)]
+    followers: BTreeMap<String, String>,
+    /// Newest last
+    #[serde(default)]
+    notes: Vec<Note>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Note {
+    id: String,
+    content: String,
+    published: DateTime<Utc>,
+}
+
+impl Store {
+    fn load(path: &Path) -> Result<Self> {
+        files::load_or_default(path)
+    }
+
+    /// Change the store, replacing the file atomically
+    async fn update<T>(path: &Path, f: impl FnOnce(&mut Self) -> T) -> Result<T> {
+        let _lock = STORE_LOCK.lock().await;
+        let mut store = Self::load(path)?;
+        let result = f(&mut store);
+        files::write_atomic(path, &toml::to_string(&store)?)?;
+        Ok(result)
+    }
+}
+
+/// The bot's actor
+pub struct ActivityPub {
+    config: ActivityPubConfig,
+    base: String,
+    key: PKey<Private>,
+    client: reqwest::Client,
+}
+
+impl ActivityPub {
+    pub fn new(config: &ActivityPubConfig) -> Result<Self> {
+        Ok(Self {
+            config: config.clone(),
+            base: config.url.trim_end_matches('/').to_string(),
+            key: load_or_create_key(&config.key)?,
+            client: reqwest::Client::builder().timeout(TIMEOUT).build()?,
+        })
+    }
+
+    fn actor_id(&self) -> String {
+        format!("{}/users/{}", self.base, self.config.username)
+    }
+
+    fn key_id(&self) -> String {
+        format!("{}#main-key", self.actor_id())
+    }
+
+    /// A new id under the server, e.g. for an activity
+    fn new_id(&self, kind: &str) -> String {
+        format!(
+            "{}/{}/{}",
+            self.base,
+            kind,
+            Utc::now().timestamp_nanos_opt().unwrap_or_default()
+        )
+    }
+
+    /// The host and port of the server, as in a WebFinger resource
+    fn host(&self) -> Result<String> {
+        let url = reqwest::Url::parse(&self.base)?;
+        host_header(&url)
+    }
+
+    fn actor(&self) -> Result<Value> {
+        let id = self.actor_id();
+        Ok(json!({
+            "@context": [
+                "https://www.w3.org/ns/activitystreams",
+                "https://w3id.org/security/v1",
+            ],
+            "id": id,
+            "type": "Service",
+            "preferredUsername": self.config.username,
+            "name": self.config.display_name.as_deref().unwrap_or(&self.config.username),
+            "summary": self.config.summary.as_deref().map(escape_html).unwrap_or_default(),
+            "inbox": format!("{}/inbox", id),
+            "outbox": format!("{}/outbox", id),
+            "followers": format!("{}/followers", id),
+            "endpoints": { "sharedInbox": format!("{}/inbox", self.base) },
+            "publicKey": {
+                "id": self.key_id(),
+                "owner": id,
+                "publicKeyPem": String::from_utf8(self.key.public_key_to_pem()?)?,
+            },
+        }))
+    }
+
+    fn note(&self, note: &Note) -> Value {
+        json!({
+            "id": note.id,
+            "type": "Note",
+            "attributedTo": self.actor_id(),
+            "content": note.content,
+            "published": note.published,
+            "to": [PUBLIC],
+            "cc": [format!("{}/followers", self.actor_id())],
+        })
+    }
+
+    fn create(&self, note: &Note) -> Value {
+        json!({
+            "@context": "https://www.w3.org/ns/activitystreams",
+            "id": format!("{}/activity", note.id),
+            "type": "Create",
+            "actor": self.actor_id(),
+            "published": note.published,
+            "to": [PUBLIC],
+            "cc": [format!("{}/followers", self.actor_id())],
+            "object": self.note(note),
+        })
+    }
+
+    /// Fetch an ActivityPub document, signed for servers requiring it
+    async fn fetch(&self, url: &str) -> Result<Value> {
+        let parsed = reqwest::Url::parse(url)?;
+        let date = http_signature::date(Utc::now());
+        let host = host_header(&parsed)?;
+        let signature = http_signature::sign(
+            &self.key,
+            &self.key_id(),
+            "get",
+            &request_target(&parsed),
+            &[("host", &host), ("date", &date)],
+        )?;
+        Ok(self
+            .client
+            .get(parsed)
+            .header("accept", CONTENT_TYPE)
+            .header("date", date)
+            .header("signature", signature)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?)
+    }
+
+    /// Post a signed activity to an inbox
+    async fn deliver(&self, inbox: &str, activity: &Value) -> Result<()> {
+        let url = reqwest::Url::parse(inbox)?;
+        let body = serde_json::to_vec(activity)?;
+        let date = http_signature::date(Utc::now());
+        let digest = http_signature::digest(&body);
+        let host = host_header(&url)?;
+        let signature = http_signature::sign(
+            &self.key,
+            &self.key_id(),
+            "post",
+            &request_target(&url),
+            &[("host", &host), ("date", &date), ("digest", &digest)],
+        )?;
+        self.client
+            .post(url)
+            .header("content-type", CONTENT_TYPE)
+            .header("date", date)
+            .header("digest", digest)
+            .header("signature", signature)
+            .body(body)
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+
+    /// The inbox of an actor, preferring the shared inbox of its server
+    fn inbox(actor: &Value) -> Option<String> {
+        actor["endpoints"]["sharedInbox"]
+            .as_str()
+            .or_else(|| actor["inbox"].as_str())
+            .map(str::to_string)
+    }
+
+    /// Ask to follow another actor, e.g. another instance of the bot
+    pub async fn follow(&self, actor: &str) -> Result<()> {
+        let target = self.fetch(actor).await?;
+        let inbox = Self::inbox(&target).ok_or_else(|| anyhow!("{} has no inbox", actor))?;
+        let activity = json!({
+            "@context": "https://www.w3.org/ns/activitystreams",
+            "id": self.new_id("follows"),
+            "type": "Follow",
+            "actor": self.actor_id(),
+            "object": target["id"].as_str().unwrap_or(actor),
+        });
+        self.deliver(&inbox, &activity).await
+    }
+
+    /// Check the signature of an incoming request, returning the signing actor
+    async fn verify_request(
+        &self,
+        method: &Method,
+        uri: &Uri,
+        headers: &HeaderMap,
+        body: &[u8],
+    ) -> Result<Value> {
+        let signature = headers
+            .get("signature")
+            .and_then(|s| s.to_str().ok())
+            .ok_or_else(|| anyhow!("The request isn't signed"))?;
+        let signature = Signature::parse(signature)?;
+        let actor_url = signature
+            .key_id
+            .split('#')
+            .next()
+            .unwrap_or(&signature.key_id);
+        let actor = self
+            .fetch(actor_url)
+            .await
+            .with_context(|| format!("Failed to fetch the key {}", signature.key_id))?;
+        // Otherwise anyone could sign with their own key as someone else
+        if actor["id"].as_str() != Some(actor_url) {
+            bail!("{} says it is {}", actor_url, actor["id"]);
+        }
+        if actor["publicKey"]["id"].as_str() != Some(signature.key_id.as_str())
+            || actor["publicKey"]["owner"].as_str() != Some(actor_url)
+        {
+            bail!("{} doesn't own the key {}", actor_url, signature.key_id);
+        }
+        let pem = actor["publicKey"]["publicKeyPem"]
+            .as_str()
+            .ok_or_else(|| anyhow!("{} has no public key", actor_url))?;
+        let key = PKey::public_key_from_pem(pem.as_bytes())?;
+        let path = uri
+            .path_and_query()
+            .map(|p| p.as_str())
+            .unwrap_or(uri.path());
+        signature.verify(&key, method.as_str(), path, body, |name| {
+            headers
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .map(str::to_string)
+        })?;
+        Ok(actor)
+    }
+
+    /// Handle an activity sent to the inbox by `actor`
+    async fn receive(self: Arc<Self>, actor: Value, activity: Value) -> Result<()> {
+        let actor_id = actor["id"].as_str().unwrap_or_default().to_string();
+        if activity["actor"].as_str() != Some(actor_id.as_str()) {
+            bail!("{} signed an activity by someone else", actor_id);
+        }
+        match activity["type"].as_str().unwrap_or_default() {
+            "Follow" => {
+                if object_id(&activity["object"]) != Some(self.actor_id().as_str()) {
+                    bail!("Follow of someone else");
+                }
+                let inbox =
+                    Self::inbox(&actor).ok_or_else(|| anyhow!("{} has no inbox", actor_id))?;
+                let follower = actor_id.clone();
+                Store::update(&self.config.store, |store| {
+                    store.followers.insert(follower, inbox.clone())
+                })
+                .await?;
+                info!("New follower {}", actor_id);
+                let accept = json!({
+                    "@context": "https://www.w3.org/ns/activitystreams",
+                    "id": self.new_id("accepts"),
+                    "type": "Accept",
+                    "actor": self.actor_id(),
+                    "object": activity,
+                });
+                self.deliver(&inbox, &accept).await?;
+            }
+            "Undo" if activity["object"]["type"].as_str() == Some("Follow") => {
+                Store::update(&self.config.store, |store| {
+                    store.followers.remove(&actor_id)
+                })
+                .await?;
+                info!("{} stopped following", actor_id);
+            }
+            "Delete" if object_id(&activity["object"]) == Some(actor_id.as_str()) => {
+                Store::update(&self.config.store, |store| {
+                    store.followers.remove(&actor_id)
+                })
+                .await?;
+                info!("{} was deleted", actor_id);
+            }
+            "Accept" => info!("{} accepted the follow", actor_id),
+            "Create" => info!(
+                "Post from {}: {}",
+                actor_id,
+                activity["object"]["content"].as_str().unwrap_or_default()
+            ),
+            other => debug!("Ignoring {} from {}", other, actor_id),
+        }
+        Ok(())
+    }
+
+    /// Serve the actor and its inbox until the task is aborted
+    pub async fn serve(self: Arc<Self>) -> Result<()> {
+        let listener = tokio::net::TcpListener::bind(&self.config.listen)
+            .await
+            .with_context(|| format!("Failed to listen on {}", self.config.listen))?;
+        info!("Serving ActivityPub on {}", self.config.listen);
+        axum::serve(listener, router(self)).await?;
+        Ok(())
+    }
+}
+
+/// The id of an object, which may be embedded or a link
+fn object_id(object: &Value) -> Option<&str> {
+    object.as_str().or_else(|| object["id"].as_str())
+}
+
+/// Host and port as sent in the Host header
+fn host_header(url: &reqwest::Url) -> Result<String> {
+    let host = url
+        .host_str()
+        .ok_or_else(|| anyhow!("{} has no host", url))?;
+    Ok(match url.port() {
+        Some(port) => format!("{}:{}", host, port),
+        None => host.to_string(),
+    })
+}
+
+fn request_target(url: &reqwest::Url) -> String {
+    match url.query() {
+        Some(query) => format!("{}?{}", url.path(), query),
+        None => url.path().to_string(),
+    }
+}
+
+fn load_or_create_key(path: &Path) -> Result<PKey<Private>> {
+    match std::fs::read(path) {
+        Ok(pem) => Ok(PKey::private_key_from_pem(&pem)?),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            info!("Creating the ActivityPub key {}", path.display());
+            let key = PKey::from_rsa(Rsa::generate(2048)?)?;
+            write_private(path, std::str::from_utf8(&key.private_key_to_pem_pkcs8()?)?)?;
+            Ok(key)
+        }
+        Err(e) => Err(anyhow!("Failed to read {}: {}", path.display(), e)),
+    }
+}
+
+#[async_trait]
+impl Publisher for ActivityPub {
+    fn name(&self) -> &str {
+        ACTIVITYPUB
+    }
+
+    async fn verify(&self) -> Result<()> {
+        reqwest::Url::parse(&self.base)?;
+        Store::load(&self.config.store)?;
+        Ok(())
+    }
+
+    /// Add a note to the outbox and deliver it to every follower
+    async fn publish(&self, post: &str) -> Result<()> {
+        let note = Note {
+            id: self.new_id("notes"),
+            content: format!("<p>{}</p>", escape_html(post).replace('\n', "<br>")),
+            published: Utc::now(),
+        };
+        let stored = note.clone();
+        let inboxes = Store::update(&self.config.store, |store| {
+            store.notes.push(stored);
+            let excess = store.notes.len().saturating_sub(OUTBOX_SIZE);
+            store.notes.drain(..excess);
+            store.followers.values().cloned().collect::<BTreeSet<_>>()
+        })
+        .await?;
+        let activity = self.create(&note);
+        let mut failed = 0;
+        for inbox in &inboxes {
+            if let Err(e) = self.deliver(inbox, &activity).await {
+                warn!("Failed to deliver to {}: {}", inbox, e);
+                failed += 1;
+            }
+        }
+        if failed > 0 && failed == inboxes.len() {
+            bail!("Failed to deliver to all {} inboxes", failed);
+        }
+        Ok(())
+    }
+}
+
+fn activity_json(value: Value) -> Response {
+    ([(header::CONTENT_TYPE, CONTENT_TYPE)], value.to_string()).into_response()
+}
+
+fn router(activitypub: Arc<ActivityPub>) -> Router {
+    Router::new()
+        .route("/.well-known/webfinger", get(webfinger))
+        .route("/users/{username}", get(actor))
+        .route("/users/{username}/outbox", get(outbox))
+        .route("/users/{username}/followers", get(followers))
+        .route("/users/{username}/inbox", post(inbox))
+        .route("/inbox", post(inbox))
+        .route("/notes/{id}", get(note))
+        .with_state(activitypub)
+}
+
+#[derive(Debug, Deserialize)]
+struct WebFingerQuery {
+    resource: String,
+}
+
+async fn webfinger(
+    State(activitypub): State<Arc<ActivityPub>>,
+    Query(query): Query<WebFingerQuery>,
+) -> Response {
+    let Ok(host) = activitypub.host() else {
+        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+    };
+    let acct = format!("acct:{}@{}", activitypub.config.username, host);
+    if query.resource != acct && query.resource != activitypub.actor_id() {
+        return StatusCode::NOT_FOUND.into_response();
+    }
+    (
+        [(header::CONTENT_TYPE, "application/jrd+json")],
+        json!({
+            "subject": acct,
+            "aliases": [activitypub.actor_id()],
+            "links": [{
+                "rel": "self",
+                "type": CONTENT_TYPE,
+                "href": activitypub.actor_id(),
+            }],
+        })
+        .to_string(),
+    )
+        .into_response()
+}
+
+async fn actor(
+    State(activitypub): State<Arc<ActivityPub>>,
+    UrlPath(username): UrlPath<String>,
+) -> Response {
+    if username != activitypub.config.username {
+        return StatusCode::NOT_FOUND.into_response();
+    }
+    match activitypub.actor() {
+        Ok(actor) => activity_json(actor),
+        Err(e) => {
+            error!("Failed to create the actor: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+async fn outbox(
+    State(activitypub): State<Arc<ActivityPub>>,
+    UrlPath(username): UrlPath<String>,
+) -> Response {
+    if username != activitypub.config.username {
+        return StatusCode::NOT_FOUND.into_response();
+    }
+    let Ok(store) = Store::load(&activitypub.config.store) else {
+        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+    };
+    let items: Vec<Value> = store
+        .notes
+        .iter()
+        .rev()
+        .map(|note| activitypub.create(note))
+        .collect();
+    activity_json(json!({
+        "@context": "https://www.w3.org/ns/activitystreams",
+        "id": format!("{}/outbox", activitypub.actor_id()),
+        "type": "OrderedCollection",
+        "totalItems": items.len(),
+        "orderedItems": items,
+    }))
+}
+
+async fn followers(
+    State(activitypub): State<Arc<ActivityPub>>,
+    UrlPath(username): UrlPath<String>,
+) -> Response {
+    if username != activitypub.config.username {
+        return StatusCode::NOT_FOUND.into_response();
+    }
+    let Ok(store) = Store::load(&activitypub.config.store) else {
+        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+    };
+    // Only the number, the followers themselves aren't public
+    activity_json(json!({
+        "@context": "https://www.w3.org/ns/activitystreams",
+        "id": format!("{}/followers", activitypub.actor_id()),
+        "type": "OrderedCollection",
+        "totalItems": store.followers.len(),
+    }))
+}
+
+async fn note(
+    State(activitypub): State<Arc<ActivityPub>>,
+    UrlPath(id): UrlPath<String>,
+) -> Response {
+    let Ok(store) = Store::load(&activitypub.config.store) else {
+        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+    };
+    let id = format!("{}/notes/{}", activitypub.base, id);
+    match store.notes.iter().find(|note| note.id == id) {
+        Some(note) => {
+            let mut note_json = activitypub.note(note);
+            note_json["@context"] = json!("https://www.w3.org/ns/activitystreams");
+            activity_json(note_json)
+        }
+        None => StatusCode::NOT_FOUND.into_response(),
+    }
+}
+
+async fn inbox(
+    State(activitypub): State<Arc<ActivityPub>>,
+    method: Method,
+    uri: Uri,
+    headers: HeaderMap,
+    body: Bytes,
+) -> StatusCode {
+    let actor = match activitypub
+        .verify_request(&method, &uri, &headers, &body)
+        .await
+    {
+        Ok(actor) => actor,
+        Err(e) => {
+            warn!("Rejected a request to the inbox: {}", e);
+            return StatusCode::UNAUTHORIZED;
+        }
+    };
+    let Ok(activity) = serde_json::from_slice::<Value>(&body) else {
+        return StatusCode::BAD_REQUEST;
+    };
+    // Answer right away, the sender doesn't wait for e.g. the Accept
+    tokio::spawn(async move {
+        if let Err(e) = activitypub.receive(actor, activity).await {
+            warn!("Failed to handle an activity: {}", e);
+        }
+    });
+    StatusCode::ACCEPTED
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stub::Stub;
+
+    /// An instance of the bot on an ephemeral port, with its files in a
+    /// directory of its own
+    struct Instance {
+        activitypub: Arc<ActivityPub>,
+        dir: PathBuf,
+    }
+
+    impl Instance {
+        async fn start(name: &str) -> Self {
+            let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let (activitypub, dir) =
+                actor(name, &format!("http://{}", listener.local_addr().unwrap()));
+            let app = router(activitypub.clone());
+            tokio::spawn(async move { axum::serve(listener, app).await });
+            Self { activitypub, dir }
+        }
+
+        fn followers(&self) -> BTreeMap<String, String> {
+            Store::load(&self.activitypub.config.store)
+                .unwrap()
+                .followers
+        }
+    }
+
+    impl Drop for Instance {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.dir);
+        }
+    }
+
+    /// An actor served at `url`, with a new key
+    fn actor(name: &str, url: &str) -> (Arc<ActivityPub>, PathBuf) {
+        let dir = std::env::temp_dir().join(format!(
+            "vader_bot-activitypub-{}-{}",
+            std::process::id(),
+            name
+        ));
+        std::fs::create_dir_all(&dir).unwrap();
+        let config = ActivityPubConfig {
+            url: url.to_string(),
+            listen: String::new(),
+            username: "vader".to_string(),
+            display_name: None,
+            summary: None,
+            key: dir.join("activitypub.pem"),
+            store: dir.join("activitypub.toml"),
+        };
+        (Arc::new(ActivityPub::new(&config).unwrap()), dir)
+    }
+
+    #[tokio::test]
+    async fn follows_and_delivers_between_instances() {
+        let lund = Instance::start("lund").await;
+        let kiruna = Instance::start("kiruna").await;
+        lund.activitypub
+            .follow(&kiruna.activitypub.actor_id())
+            .await
+            .unwrap();
+        // The follow is handled after answering
+        for _ in 0..50 {
+            if !kiruna.followers().is_empty() {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+        }
+        assert_eq!(
+            kiruna.followers(),
+            BTreeMap::from([(
+                lund.activitypub.actor_id(),
+                format!("{}/inbox", lund.activitypub.base)
+            )])
+        );
+        // Only succeeds if Lund accepts the signed Create
+        kiruna.activitypub.publish("Snö & <is>").await.unwrap();
+        let notes = Store::load(&kiruna.activitypub.config.store).unwrap().notes;
+        assert_eq!(notes.len(), 1);
+        assert_eq!(notes[0].content, "<p>Snö &amp; &lt;is&gt;</p>");
+    }
+
+    #[tokio::test]
+    async fn rejects_unsigned_requests() {
+        let lund = Instance::start("unsigned").await;
+        let response = reqwest::Client::new()
+            .post(format!("{}/inbox", lund.activitypub.base))
+            .body("{}")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn rejects_keys_signing_for_someone_else() {
+        let lund = Instance::start("victim").await;
+        let inbox = format!("{}/inbox", lund.activitypub.base);
+        let victim = "https://victim.example/users/admin";
+        let document = |id: &str, key_id: &str, owner: &str, pem: &str| {
+            json!({
+                "id": id,
+                "publicKey": { "id": key_id, "owner": owner, "publicKeyPem": pem },
+            })
+            .to_string()
+        };
+        // The key only exists once the impostor does, at the stub's url
+        let stub = Stub::start(&[]).await;
+        let (impostor, dir) = actor("impostor", &stub.url);
+        let (id, key_id) = (impostor.actor_id(), impostor.key_id());
+        let pem = String::from_utf8(impostor.key.public_key_to_pem().unwrap()).unwrap();
+        let documents = [
+            // Claiming to be someone else
+            document(victim, &key_id, victim, &pem),
+            // Pointing at a key owned by someone else
+            document(&id, &key_id, victim, &pem),
+            // Serving a key under another id
+            document(&id, &format!("{}#main-key", victim), &id, &pem),
+            // The real thing
+            document(&id, &key_id, &id, &pem),
+        ];
+        stub.respond(
+            &documents
+                .iter()
+                .map(|d| (200, d.as_str()))
+                .collect::<Vec<_>>(),
+        );
+        let follow =
+            json!({ "type": "Follow", "actor": victim, "object": lund.activitypub.actor_id() });
+        for _ in 0..3 {
+            let e = impostor.deliver(&inbox, &follow).await.unwrap_err();
+            assert!(e.to_string().contains("401"), "{}", e);
+        }
+        let follow =
+            json!({ "type": "Follow", "actor": id, "object": lund.activitypub.actor_id() });
+        impostor.deliver(&inbox, &follow).await.unwrap();
+        let requests = stub.requests();
+        assert!(requests[..4].iter().all(|r| r.path == "/users/vader"));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
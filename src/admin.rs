@@ -0,0 +1,312 @@
+//! An HTTP API for checking on the bot and posting by hand
+use anyhow::{Context, Result};
+use axum::extract::{Path, Query, State};
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use serde::{Deserialize, Serialize};
+use tokio::sync::{mpsc, oneshot, watch};
+use tracing::{info, warn};
+
+use crate::config::{Config, Location};
+use crate::metrics;
+use crate::status;
+use crate::vader::Day;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AdminConfig {
+    /// Address to listen on
+    #[serde(default = "default_listen")]
+    pub listen: String,
+    /// Bearer token needed to post, which is disabled without one
+    #[serde(default)]
+    pub token: Option<String>,
+}
+
+fn default_listen() -> String {
+    "127.0.0.1:8090".to_string()
+}
+
+/// A request to post immediately, handled by the scheduling loop
+pub struct PostNow {
+    pub location: Location,
+    pub day: Day,
+    pub done: oneshot::Sender<Result<()>>,
+}
+
+#[derive(Clone)]
+struct AppState {
+    config: watch::Receiver<Config>,
+    post_now: mpsc::Sender<PostNow>,
+    token: Option<String>,
+}
+
+impl AppState {
+    fn location(&self, name: &str) -> Option<Location> {
+        self.config
+            .borrow()
+            .locations()
+            .into_iter()
+            .find(|l| l.name.eq_ignore_ascii_case(name))
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct DayQuery {
+    #[serde(default = "default_day")]
+    day: Day,
+}
+
+fn default_day() -> Day {
+    Day::Tomorrow
+}
+
+/// Serve the API until the task is aborted
+pub async fn serve(
+    admin: AdminConfig,
+    config: watch::Receiver<Config>,
+    requests: mpsc::Sender<PostNow>,
+) -> Result<()> {
+    let listener = tokio::net::TcpListener::bind(&admin.listen)
+        .await
+        .with_context(|| format!("Failed to listen on {}", admin.listen))?;
+    info!("Serving the admin API on {}", admin.listen);
+    let state = AppState {
+        config,
+        post_now: requests,
+        token: admin.token,
+    };
+    axum::serve(listener, router(state)).await?;
+    Ok(())
+}
+
+fn router(state: AppState) -> Router {
+    Router::new()
+        .route("/healthz", get(|| async { "ok" }))
+        .route("/readyz", get(readyz))
+        .route("/status", get(status))
+        .route("/preview/{location}", get(preview))
+        .route("/post-now/{location}", post(post_now))
+        .route("/metrics", get(metrics))
+        .with_state(state)
+}
+
+async fn readyz() -> (StatusCode, &'static str) {
+    if status::get().ready {
+        (StatusCode::OK, "ready")
+    } else {
+        (StatusCode::SERVICE_UNAVAILABLE, "not ready")
+    }
+}
+
+async fn metrics() -> Response {
+    match metrics::render() {
+        Ok(metrics) => (
+            [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+            metrics,
+        )
+            .into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+}
+
+async fn status(State(state): State<AppState>, headers: HeaderMap) -> Response {
+    if !may_read(&state, &headers) {
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+    Json(status::get()).into_response()
+}
+
+async fn preview(
+    State(state): State<AppState>,
+    Path(location): Path<String>,
+    Query(query): Query<DayQuery>,
+    headers: HeaderMap,
+) -> Response {
+    if !may_read(&state, &headers) {
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+    let Some(location) = state.location(&location) else {
+        return (StatusCode::NOT_FOUND, "No such location").into_response();
+    };
+    let config = state.config.borrow().clone();
+    match crate::render_post(&config, &location, query.day).await {
+        Ok(post) => post.into_response(),
+        Err(e) => (StatusCode::BAD_GATEWAY, e.to_string()).into_response(),
+    }
+}
+
+/// Whether the status and previews may be read, by anyone unless there is a
+/// token since the errors in them can tell more than the health and metrics
+fn may_read(state: &AppState, headers: &HeaderMap) -> bool {
+    state
+        .token
+        .as_deref()
+        .is_none_or(|token| authorized(token, headers))
+}
+
+fn authorized(token: &str, headers: &HeaderMap) -> bool {
+    let given = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .unwrap_or_default();
+    given.len() == token.len() && openssl::memcmp::eq(given.as_bytes(), token.as_bytes())
+}
+
+async fn post_now(
+    State(state): State<AppState>,
+    Path(location): Path<String>,
+    Query(query): Query<DayQuery>,
+    headers: HeaderMap,
+) -> Response {
+    let Some(token) = state.token.as_deref() else {
+        return (StatusCode::FORBIDDEN, "Posting needs admin.token to be set").into_response();
+    };
+    if !authorized(token, &headers) {
+        warn!("Unauthorized request to post for {}", location);
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+    let Some(location) = state.location(&location) else {
+        return (StatusCode::NOT_FOUND, "No such location").into_response();
+    };
+    let (done, result) = oneshot::channel();
+    let request = PostNow {
+        location,
+        day: query.day,
+        done,
+    };
+    if state.post_now.send(request).await.is_err() {
+        return (StatusCode::SERVICE_UNAVAILABLE, "The bot is stopping").into_response();
+    }
+    match result.await {
+        Ok(Ok(())) => "Posted".into_response(),
+        Ok(Err(e)) => (StatusCode::BAD_GATEWAY, e.to_string()).into_response(),
+        Err(_) => (StatusCode::SERVICE_UNAVAILABLE, "The bot is stopping").into_response(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::HeaderValue;
+
+    use super::*;
+
+    fn headers(authorization: Option<&str>) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        if let Some(value) = authorization {
+            headers.insert(header::AUTHORIZATION, HeaderValue::from_str(value).unwrap());
+        }
+        headers
+    }
+
+    #[test]
+    fn checks_the_bearer_token() {
+        assert!(authorized("secret", &headers(Some("Bearer secret"))));
+        assert!(!authorized("secret", &headers(None)));
+        assert!(!authorized("secret", &headers(Some("secret"))));
+        assert!(!authorized("secret", &headers(Some("Basic secret"))));
+        assert!(!authorized("secret", &headers(Some("bearer secret"))));
+        assert!(!authorized("secret", &headers(Some("Bearer secre"))));
+        assert!(!authorized("secret", &headers(Some("Bearer secrets"))));
+        assert!(!authorized("secret", &headers(Some("Bearer public"))));
+    }
+
+    /// Serve the API for the location Lund on an ephemeral port, with the
+    /// requests to post
+    async fn start(token: Option<&str>) -> (String, mpsc::Receiver<PostNow>) {
+        let config: Config = toml::from_str("location = \"Lund\"").unwrap();
+        let (post_now, requests) = mpsc::channel(1);
+        let state = AppState {
+            config: watch::channel(config).1,
+            post_now,
+            token: token.map(str::to_string),
+        };
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, router(state)).await });
+        (url, requests)
+    }
+
+    async fn status_of(request: reqwest::RequestBuilder) -> u16 {
+        request.send().await.unwrap().status().as_u16()
+    }
+
+    #[tokio::test]
+    async fn refuses_to_post_without_a_token() {
+        let (url, _requests) = start(None).await;
+        let client = reqwest::Client::new();
+        let post = client
+            .post(format!("{}/post-now/Lund", url))
+            .bearer_auth("anything");
+        assert_eq!(status_of(post).await, 403);
+        // Without a token the rest is open
+        assert_eq!(status_of(client.get(format!("{}/status", url))).await, 200);
+        assert_eq!(status_of(client.get(format!("{}/healthz", url))).await, 200);
+    }
+
+    #[tokio::test]
+    async fn serves_the_metrics() {
+        let (url, _requests) = start(Some("secret")).await;
+        metrics::parse_failed("admin_test");
+        let response = reqwest::get(format!("{}/metrics", url)).await.unwrap();
+        assert_eq!(response.status().as_u16(), 200);
+        assert_eq!(
+            response.headers()[reqwest::header::CONTENT_TYPE],
+            "text/plain; version=0.0.4"
+        );
+        let metrics = response.text().await.unwrap();
+        assert!(
+            metrics.contains("vader_wttr_parse_failures_total{field=\"admin_test\"} 1"),
+            "{}",
+            metrics
+        );
+    }
+
+    #[tokio::test]
+    async fn needs_the_token_for_posts_status_and_previews() {
+        let (url, mut requests) = start(Some("secret")).await;
+        let client = reqwest::Client::new();
+        for request in [
+            client.post(format!("{}/post-now/Lund", url)),
+            client.get(format!("{}/status", url)),
+            client.get(format!("{}/preview/Lund", url)),
+        ] {
+            assert_eq!(status_of(request.try_clone().unwrap()).await, 401);
+            assert_eq!(status_of(request.bearer_auth("wrong")).await, 401);
+        }
+        assert_eq!(
+            status_of(client.get(format!("{}/status", url)).bearer_auth("secret")).await,
+            200
+        );
+        assert_eq!(status_of(client.get(format!("{}/healthz", url))).await, 200);
+        assert!(requests.try_recv().is_err(), "Nothing was posted");
+    }
+
+    #[tokio::test]
+    async fn posts_for_configured_locations_only() {
+        let (url, mut requests) = start(Some("secret")).await;
+        let client = reqwest::Client::new();
+        for request in [
+            client.post(format!("{}/post-now/Malmö", url)),
+            client.get(format!("{}/preview/Malmö", url)),
+        ] {
+            assert_eq!(status_of(request.bearer_auth("secret")).await, 404);
+        }
+        tokio::spawn(async move {
+            let request = requests.recv().await.unwrap();
+            assert_eq!(request.location.name, "Lund");
+            assert_eq!(request.day, Day::Today);
+            request.done.send(Ok(())).unwrap();
+        });
+        let response = client
+            .post(format!("{}/post-now/lund?day=today", url))
+            .bearer_auth("secret")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status().as_u16(), 200);
+        assert_eq!(response.text().await.unwrap(), "Posted");
+    }
+}
@@ -0,0 +1,280 @@
+//! Air quality from Open-Meteo, rated by the European Air Quality Index
+use anyhow::Result;
+use chrono::NaiveDate;
+
+use crate::open_meteo::{self, HourlyForecast};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Pollutant {
+    Pm2_5,
+    Pm10,
+    NitrogenDioxide,
+    Ozone,
+}
+
+const ALL_POLLUTANTS: [Pollutant; 4] = [
+    Pollutant::Pm2_5,
+    Pollutant::Pm10,
+    Pollutant::NitrogenDioxide,
+    Pollutant::Ozone,
+];
+
+impl Pollutant {
+    pub fn to_swedish(self) -> &'static str {
+        match self {
+            Pollutant::Pm2_5 => "partiklar (PM2.5)",
+            Pollutant::Pm10 => "partiklar (PM10)",
+            Pollutant::NitrogenDioxide => "kvävedioxid",
+            Pollutant::Ozone => "ozon",
+        }
+    }
+
+    fn open_meteo_variable(self) -> &'static str {
+        match self {
+            Pollutant::Pm2_5 => "pm2_5",
+            Pollutant::Pm10 => "pm10",
+            Pollutant::NitrogenDioxide => "nitrogen_dioxide",
+            Pollutant::Ozone => "ozone",
+        }
+    }
+
+    /// Upper bounds in µg/m³ of the index levels, from good to very poor
+    fn bands(self) -> [f32; 5] {
+        match self {
+            Pollutant::Pm2_5 => [10.0, 20.0, 25.0, 50.0, 75.0],
+            Pollutant::Pm10 => [20.0, 40.0, 50.0, 100.0, 150.0],
+            Pollutant::NitrogenDioxide => [40.0, 90.0, 120.0, 230.0, 340.0],
+            Pollutant::Ozone => [50.0, 100.0, 130.0, 240.0, 380.0],
+        }
+    }
+
+    /// The European AQI for a concentration, 0 to 20 is good and above 100 extremely poor
+    pub fn index(self, concentration: f32) -> f32 {
+        let bands = self.bands();
+        let mut lower = 0.0;
+        for (i, &upper) in bands.iter().enumerate() {
+            if concentration <= upper {
+                return 20.0 * (i as f32 + (concentration - lower) / (upper - lower));
+            }
+            lower = upper;
+        }
+        // Extremely poor, keep going at the rate of the last band
+        let width = bands[4] - bands[3];
+        100.0 + 20.0 * (concentration - bands[4]) / width
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum AqiLevel {
+    Good,
+    Fair,
+    Moderate,
+    Poor,
+    VeryPoor,
+    ExtremelyPoor,
+}
+
+impl AqiLevel {
+    pub fn from_index(index: f32) -> Self {
+        match index {
+            i if i <= 20.0 => AqiLevel::Good,
+            i if i <= 40.0 => AqiLevel::Fair,
+            i if i <= 60.0 => AqiLevel::Moderate,
+            i if i <= 80.0 => AqiLevel::Poor,
+            i if i <= 100.0 => AqiLevel::VeryPoor,
+            _ => AqiLevel::ExtremelyPoor,
+        }
+    }
+
+    pub fn to_swedish(self) -> &'static str {
+        match self {
+            AqiLevel::Good => "bra",
+            AqiLevel::Fair => "godtagbar",
+            AqiLevel::Moderate => "måttlig",
+            AqiLevel::Poor => "dålig",
+            AqiLevel::VeryPoor => "mycket dålig",
+            AqiLevel::ExtremelyPoor => "extremt dålig",
+        }
+    }
+}
+
+pub fn explain_aqi_level(level: AqiLevel) -> &'static str {
+    match level {
+        AqiLevel::Good | AqiLevel::Fair => "Luften är bra. Inga särskilda åtgärder behövs.",
+        AqiLevel::Moderate => "Känsliga personer kan märka av luftföroreningarna.",
+        AqiLevel::Poor => {
+            "Personer med astma eller hjärt- och lungsjukdomar bör undvika ansträngning utomhus."
+        }
+        AqiLevel::VeryPoor => {
+            "Undvik ansträngning utomhus, särskilt om du har astma eller hjärt- och lungsjukdomar."
+        }
+        AqiLevel::ExtremelyPoor => {
+            "Undvik att vistas utomhus längre stunder. Alla bör minska ansträngning utomhus."
+        }
+    }
+}
+
+/// The overall index for a day and the pollutant deciding it
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AirQuality {
+    pub index: f32,
+    pub pollutant: Pollutant,
+}
+
+impl AirQuality {
+    pub fn level(&self) -> AqiLevel {
+        AqiLevel::from_index(self.index)
+    }
+}
+
+/// The worst pollutant, by index, from the highest concentration of each during the date
+pub fn from_concentrations(concentrations: &[(Pollutant, f32)]) -> Option<AirQuality> {
+    concentrations
+        .iter()
+        .map(|&(pollutant, c)| AirQuality {
+            index: pollutant.index(c),
+            pollutant,
+        })
+        .max_by(|a, b| a.index.total_cmp(&b.index))
+}
+
+/// Highest concentration of each pollutant during the date, in µg/m³
+pub async fn fetch(lat: f64, lon: f64, date: NaiveDate) -> Result<Vec<(Pollutant, f32)>> {
+    let variables: Vec<_> = ALL_POLLUTANTS
+        .iter()
+        .map(|p| p.open_meteo_variable())
+        .collect();
+    let air_quality = open_meteo::fetch_air_quality(lat, lon, &variables).await?;
+    Ok(daily_max(&air_quality, date))
+}
+
+/// Highest concentration of each pollutant in the forecast during the date
+fn daily_max(air_quality: &HourlyForecast, date: NaiveDate) -> Vec<(Pollutant, f32)> {
+    ALL_POLLUTANTS
+        .iter()
+        .filter_map(|&p| {
+            Some((
+                p,
+                air_quality
+                    .hourly
+                    .daily_max(p.open_meteo_variable(), date)?,
+            ))
+        })
+        .collect()
+}
+
+/// E.g. "Luftkvalitet: dålig (index 72, främst ozon)" with advice, if the air is poor
+pub fn report(air_quality: &AirQuality) -> Option<String> {
+    let level = air_quality.level();
+    if level < AqiLevel::Poor {
+        return None;
+    }
+    Some(format!(
+        "Luftkvalitet: {} (index {}, främst {})\n{}",
+        level.to_swedish(),
+        air_quality.index.round() as i32,
+        air_quality.pollutant.to_swedish(),
+        explain_aqi_level(level)
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LUND: &str = include_str!("../tests/fixtures/air_quality/lund.json");
+
+    fn concentrations(date: &str) -> Vec<(Pollutant, f32)> {
+        let air_quality: HourlyForecast = serde_json::from_str(LUND).unwrap();
+        daily_max(&air_quality, date.parse().unwrap())
+    }
+
+    #[test]
+    fn index_at_the_band_edges() {
+        let edges = [
+            (Pollutant::Pm2_5, [10.0, 20.0, 25.0, 50.0, 75.0]),
+            (Pollutant::Pm10, [20.0, 40.0, 50.0, 100.0, 150.0]),
+            (
+                Pollutant::NitrogenDioxide,
+                [40.0, 90.0, 120.0, 230.0, 340.0],
+            ),
+            (Pollutant::Ozone, [50.0, 100.0, 130.0, 240.0, 380.0]),
+        ];
+        for (pollutant, bands) in edges {
+            assert_eq!(pollutant.index(0.0), 0.0);
+            for (i, upper) in bands.into_iter().enumerate() {
+                let index = 20.0 * (i as f32 + 1.0);
+                assert!((pollutant.index(upper) - index).abs() < 1e-3);
+                assert_eq!(AqiLevel::from_index(pollutant.index(upper)), level(i));
+                // Just above the edge is the next level
+                assert_eq!(
+                    AqiLevel::from_index(pollutant.index(upper + 0.1)),
+                    level(i + 1)
+                );
+            }
+        }
+    }
+
+    fn level(band: usize) -> AqiLevel {
+        [
+            AqiLevel::Good,
+            AqiLevel::Fair,
+            AqiLevel::Moderate,
+            AqiLevel::Poor,
+            AqiLevel::VeryPoor,
+            AqiLevel::ExtremelyPoor,
+        ][band]
+    }
+
+    #[test]
+    fn index_beyond_the_last_band() {
+        // 75 µg/m³ above the last band, which is 25 wide
+        assert!((Pollutant::Pm2_5.index(150.0) - 160.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn reads_the_daily_max_from_the_fixture() {
+        assert_eq!(
+            concentrations("2024-06-12"),
+            vec![
+                (Pollutant::Pm2_5, 8.4),
+                (Pollutant::Pm10, 15.2),
+                (Pollutant::NitrogenDioxide, 31.5),
+                (Pollutant::Ozone, 98.0),
+            ]
+        );
+        assert_eq!(concentrations("2024-06-14"), vec![]);
+    }
+
+    #[test]
+    fn worst_pollutant_decides() {
+        let fair = from_concentrations(&concentrations("2024-06-12")).unwrap();
+        assert_eq!(fair.pollutant, Pollutant::Ozone);
+        assert_eq!(fair.level(), AqiLevel::Fair);
+        let poor = from_concentrations(&concentrations("2024-06-13")).unwrap();
+        assert_eq!(poor.pollutant, Pollutant::Ozone);
+        assert_eq!(poor.level(), AqiLevel::Poor);
+        let pm = from_concentrations(&[(Pollutant::Ozone, 60.0), (Pollutant::Pm2_5, 30.0)]);
+        assert_eq!(pm.unwrap().pollutant, Pollutant::Pm2_5);
+        assert_eq!(from_concentrations(&[]), None);
+    }
+
+    #[test]
+    fn reports_only_poor_air() {
+        let fair = from_concentrations(&concentrations("2024-06-12")).unwrap();
+        assert_eq!(report(&fair), None);
+        let poor = from_concentrations(&concentrations("2024-06-13")).unwrap();
+        assert_eq!(
+            report(&poor).unwrap(),
+            "Luftkvalitet: dålig (index 62, främst ozon)\n\
+             Personer med astma eller hjärt- och lungsjukdomar bör undvika ansträngning utomhus."
+        );
+        let extreme = AirQuality {
+            index: 140.0,
+            pollutant: Pollutant::Pm10,
+        };
+        assert!(report(&extreme)
+            .unwrap()
+            .starts_with("Luftkvalitet: extremt dålig (index 140, främst partiklar (PM10))"));
+    }
+}
@@ -0,0 +1,188 @@
+//! How the temperature feels, wind chill in winter and heat index in summer
+use chrono::{Datelike, NaiveDate};
+
+use crate::vader::{Hourly, Weather};
+
+/// The apparent temperature worth mentioning for a day
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Apparent {
+    /// Coldest feels-like temperature
+    Cold(i32),
+    /// Highest heat index
+    Heat(i32),
+}
+
+/// Heat index from which it's worth a warning
+const HEAT_INDEX_THRESHOLD: i32 = 27;
+/// How much colder it has to feel than it is to be mentioned
+const WIND_CHILL_DIFFERENCE: i32 = 3;
+
+/// Hourly forecasts while most people are awake, 06 to 21
+fn waking_hours(weather: &Weather) -> impl Iterator<Item = &Hourly> {
+    weather.hourly.iter().filter(|h| {
+        let hour = h.time.parse::<u16>().unwrap_or_default() / 100;
+        (6..=21).contains(&hour)
+    })
+}
+
+/// Whether the date is in the colder half of the year, October to April
+fn is_winter(date: NaiveDate) -> bool {
+    !(5..=9).contains(&date.month())
+}
+
+/// The apparent temperature relevant for the season, if it is notable
+pub fn apparent(weather: &Weather) -> Option<Apparent> {
+    let date = NaiveDate::parse_from_str(&weather.date, "%Y-%m-%d").ok()?;
+    if is_winter(date) {
+        waking_hours(weather)
+            .filter_map(|h| {
+                Some((
+                    h.feels_like_c.parse::<i32>().ok()?,
+                    h.temp_c.parse::<i32>().ok()?,
+                ))
+            })
+            .filter(|&(feels_like, temp)| {
+                feels_like <= 0 && temp - feels_like >= WIND_CHILL_DIFFERENCE
+            })
+            .map(|(feels_like, _)| feels_like)
+            .min()
+            .map(Apparent::Cold)
+    } else {
+        waking_hours(weather)
+            .filter_map(|h| h.heat_index_c.parse::<i32>().ok())
+            .max()
+            .filter(|&heat| heat >= HEAT_INDEX_THRESHOLD)
+            .map(Apparent::Heat)
+    }
+}
+
+pub fn explain_wind_chill(temp: i32) -> &'static str {
+    match temp {
+        i32::MIN..=-40 => "Extrem kyla. Oskyddad hud kan förfrysa på några minuter.",
+        -39..=-28 => "Mycket kallt. Stor risk för köldskador, täck all hud.",
+        -27..=-10 => {
+            "Kallt i vinden. Risk för köldskador vid längre vistelse ute, skydda ansiktet."
+        }
+        _ => "Kyligt i vinden. Klä dig efter den upplevda temperaturen.",
+    }
+}
+
+pub fn explain_heat_index(temp: i32) -> &'static str {
+    match temp {
+        i32::MIN..=32 => "Varmt. Drick mycket vatten och ta pauser i skuggan.",
+        33..=39 => "Mycket varmt. Undvik ansträngning mitt på dagen och drick ofta.",
+        40..=51 => "Risk för värmeslag. Håll dig i skuggan under dagens varmaste timmar.",
+        _ => "Extrem värme. Undvik all ansträngning utomhus.",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vader::testing::weather;
+
+    /// A day with the temperature, feels-like temperature and heat index at
+    /// 03, 09, 15 and 21
+    fn day(date: &str, hours: [(&str, &str, &str); 4]) -> Weather {
+        let hours: Vec<[(&str, &str); 4]> = hours
+            .iter()
+            .zip(["300", "900", "1500", "2100"])
+            .map(|(&(temp, feels_like, heat_index), time)| {
+                [
+                    ("time", time),
+                    ("tempC", temp),
+                    ("FeelsLikeC", feels_like),
+                    ("HeatIndexC", heat_index),
+                ]
+            })
+            .collect();
+        let hours: Vec<&[(&str, &str)]> = hours.iter().map(|hour| &hour[..]).collect();
+        weather(date, &[], &hours)
+    }
+
+    const CHILLY: [(&str, &str, &str); 4] = [
+        ("-2", "-12", "-2"),
+        ("-1", "-4", "-1"),
+        ("1", "-2", "1"),
+        ("-3", "-9", "-3"),
+    ];
+    const HOT: [(&str, &str, &str); 4] = [
+        ("20", "20", "35"),
+        ("25", "26", "27"),
+        ("29", "31", "30"),
+        ("26", "27", "28"),
+    ];
+
+    #[test]
+    fn reports_wind_chill_in_winter() {
+        // Not at 03, when most are asleep
+        assert_eq!(
+            apparent(&day("2024-01-15", CHILLY)),
+            Some(Apparent::Cold(-9))
+        );
+        // From October to April
+        assert_eq!(
+            apparent(&day("2024-04-30", CHILLY)),
+            Some(Apparent::Cold(-9))
+        );
+        assert_eq!(
+            apparent(&day("2024-10-01", CHILLY)),
+            Some(Apparent::Cold(-9))
+        );
+        assert_eq!(apparent(&day("2024-05-01", CHILLY)), None);
+    }
+
+    #[test]
+    fn needs_a_notable_wind_chill() {
+        // Feeling at most 0°C and at least 3 degrees colder than it is, at 15
+        let mild = [
+            ("0", "-2", "0"),
+            ("4", "1", "4"),
+            ("3", "0", "3"),
+            ("2", "0", "2"),
+        ];
+        assert_eq!(apparent(&day("2024-01-15", mild)), Some(Apparent::Cold(0)));
+        // Only 2 degrees colder, or feeling above freezing
+        let milder = [
+            ("0", "-2", "0"),
+            ("4", "1", "4"),
+            ("2", "0", "2"),
+            ("1", "-1", "1"),
+        ];
+        assert_eq!(apparent(&day("2024-01-15", milder)), None);
+    }
+
+    #[test]
+    fn reports_the_heat_index_in_summer() {
+        // Not at 03
+        assert_eq!(apparent(&day("2024-07-15", HOT)), Some(Apparent::Heat(30)));
+        assert_eq!(apparent(&day("2024-05-01", HOT)), Some(Apparent::Heat(30)));
+        assert_eq!(apparent(&day("2024-09-30", HOT)), Some(Apparent::Heat(30)));
+        assert_eq!(apparent(&day("2024-10-01", HOT)), None);
+        let warm = [
+            ("20", "20", "35"),
+            ("24", "24", "26"),
+            ("25", "25", "26"),
+            ("22", "22", "24"),
+        ];
+        assert_eq!(apparent(&day("2024-07-15", warm)), None);
+    }
+
+    #[test]
+    fn explains_the_temperatures() {
+        assert_eq!(
+            explain_wind_chill(-40),
+            "Extrem kyla. Oskyddad hud kan förfrysa på några minuter."
+        );
+        assert_eq!(
+            explain_wind_chill(-28),
+            "Mycket kallt. Stor risk för köldskador, täck all hud."
+        );
+        assert!(explain_wind_chill(-10).starts_with("Kallt i vinden."));
+        assert!(explain_wind_chill(-9).starts_with("Kyligt i vinden."));
+        assert!(explain_heat_index(32).starts_with("Varmt."));
+        assert!(explain_heat_index(33).starts_with("Mycket varmt."));
+        assert!(explain_heat_index(51).starts_with("Risk för värmeslag."));
+        assert!(explain_heat_index(52).starts_with("Extrem värme."));
+    }
+}
@@ -0,0 +1,403 @@
+//! Sunrise, sunset, day length and moon phase
+use std::f64::consts::PI;
+
+use chrono::{DateTime, Datelike, Duration, NaiveDate, NaiveTime, TimeZone, Utc};
+use chrono_tz::Tz;
+
+use crate::vader::Astronomy;
+
+/// Above this latitude wttr.in can't be trusted with the sun, so we compute it ourselves
+const POLAR_LATITUDE: f64 = 66.0;
+
+/// Sunrise and sunset in local time
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SunTimes {
+    pub sunrise: NaiveTime,
+    pub sunset: NaiveTime,
+    /// Time between sunrise and sunset, which may be after midnight
+    pub day_length: Duration,
+}
+
+/// Whether and when the sun is up during a day
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Daylight {
+    /// The sun rises and sets
+    Normal(SunTimes),
+    /// The sun doesn't set
+    MidnightSun,
+    /// The sun doesn't rise
+    PolarNight,
+}
+
+/// Parse a wttr.in time, e.g. "09:41 PM"
+pub fn parse_time(time: &str) -> Option<NaiveTime> {
+    NaiveTime::parse_from_str(time.trim(), "%I:%M %p").ok()
+}
+
+/// Sun times reported by the provider. None if it reports e.g. "No sunset",
+/// since it doesn't tell us whether that means midnight sun or polar night.
+pub fn from_wttr(astronomy: &Astronomy) -> Option<Daylight> {
+    let sunrise = parse_time(&astronomy.sunrise)?;
+    let sunset = parse_time(&astronomy.sunset)?;
+    let mut day_length = sunset - sunrise;
+    if day_length < Duration::zero() {
+        day_length += Duration::hours(24);
+    }
+    Some(Daylight::Normal(SunTimes {
+        sunrise,
+        sunset,
+        day_length,
+    }))
+}
+
+/// Compute the sun times in the time zone with NOAA's general solar position algorithm
+pub fn compute(date: NaiveDate, lat: f64, lon: f64, tz: Tz) -> Option<Daylight> {
+    let gamma = 2.0 * PI / 365.0 * (date.ordinal0() as f64);
+    let eq_time = 229.18
+        * (0.000075 + 0.001868 * gamma.cos()
+            - 0.032077 * gamma.sin()
+            - 0.014615 * (2.0 * gamma).cos()
+            - 0.040849 * (2.0 * gamma).sin());
+    let declination = 0.006918 - 0.399912 * gamma.cos() + 0.070257 * gamma.sin()
+        - 0.006758 * (2.0 * gamma).cos()
+        + 0.000907 * (2.0 * gamma).sin()
+        - 0.002697 * (3.0 * gamma).cos()
+        + 0.00148 * (3.0 * gamma).sin();
+    let lat = lat.to_radians();
+    // The zenith of sunrise and sunset, accounting for refraction and the size of the sun
+    let cos_hour_angle = 90.833_f64.to_radians().cos() / (lat.cos() * declination.cos())
+        - lat.tan() * declination.tan();
+    if cos_hour_angle < -1.0 {
+        return Some(Daylight::MidnightSun);
+    }
+    if cos_hour_angle > 1.0 {
+        return Some(Daylight::PolarNight);
+    }
+    let hour_angle = cos_hour_angle.acos().to_degrees();
+    // Minutes after midnight UTC
+    let sunrise = 720.0 - 4.0 * (lon + hour_angle) - eq_time;
+    let sunset = 720.0 - 4.0 * (lon - hour_angle) - eq_time;
+    Some(Daylight::Normal(SunTimes {
+        sunrise: utc_minutes_to_local(date, sunrise, tz)?.time(),
+        sunset: utc_minutes_to_local(date, sunset, tz)?.time(),
+        day_length: Duration::seconds((8.0 * hour_angle * 60.0).round() as i64),
+    }))
+}
+
+fn utc_minutes_to_local(date: NaiveDate, minutes: f64, tz: Tz) -> Option<DateTime<Tz>> {
+    let midnight = Utc.from_utc_datetime(&date.and_hms_opt(0, 0, 0)?);
+    Some((midnight + Duration::seconds((minutes * 60.0).round() as i64)).with_timezone(&tz))
+}
+
+fn is_polar(coordinates: Option<(f64, f64)>) -> bool {
+    matches!(coordinates, Some((lat, _)) if lat.abs() >= POLAR_LATITUDE)
+}
+
+/// Sun times from the provider, or computed if it has none. Always computed
+/// close to the poles, where the provider only says "No sunset".
+pub fn daylight(
+    astronomy: Option<&Astronomy>,
+    date: NaiveDate,
+    coordinates: Option<(f64, f64)>,
+    tz: Tz,
+) -> Option<Daylight> {
+    let computed = || coordinates.and_then(|(lat, lon)| compute(date, lat, lon, tz));
+    if is_polar(coordinates) {
+        return computed();
+    }
+    astronomy.and_then(from_wttr).or_else(computed)
+}
+
+/// Change in day length since the day before. Both days come from the same
+/// source, since the provider and our computation differ by a few minutes.
+fn day_length_change(
+    date: NaiveDate,
+    astronomy: Option<&Astronomy>,
+    previous: Option<&Astronomy>,
+    coordinates: Option<(f64, f64)>,
+    tz: Tz,
+) -> Option<Duration> {
+    let from_provider = || {
+        if is_polar(coordinates) {
+            return None;
+        }
+        Some((
+            astronomy.and_then(from_wttr)?,
+            previous.and_then(from_wttr)?,
+        ))
+    };
+    let computed = || {
+        let (lat, lon) = coordinates?;
+        Some((
+            compute(date, lat, lon, tz)?,
+            compute(date.pred_opt()?, lat, lon, tz)?,
+        ))
+    };
+    match from_provider().or_else(computed)? {
+        (Daylight::Normal(today), Daylight::Normal(previous)) => {
+            Some(today.day_length - previous.day_length)
+        }
+        // Going from or to midnight sun or polar night
+        _ => None,
+    }
+}
+
+/// Length of a synodic month in days
+const SYNODIC_MONTH: f64 = 29.530588853;
+
+/// Days since the last new moon, counted from the new moon of 2000-01-06 18:14 UTC
+fn moon_age(date: NaiveDate) -> f64 {
+    let new_moon = Utc.with_ymd_and_hms(2000, 1, 6, 18, 14, 0).unwrap();
+    let noon = Utc.from_utc_datetime(&date.and_hms_opt(12, 0, 0).unwrap());
+    let days = (noon - new_moon).num_seconds() as f64 / 86400.0;
+    days.rem_euclid(SYNODIC_MONTH)
+}
+
+/// Moon phase as named by wttr.in, computed from the date
+pub fn compute_moon_phase(date: NaiveDate) -> (&'static str, u8) {
+    let age = moon_age(date);
+    let illumination = (1.0 - (2.0 * PI * age / SYNODIC_MONTH).cos()) / 2.0;
+    let phase = match (age / SYNODIC_MONTH * 8.0).round() as u8 % 8 {
+        0 => "New Moon",
+        1 => "Waxing Crescent",
+        2 => "First Quarter",
+        3 => "Waxing Gibbous",
+        4 => "Full Moon",
+        5 => "Waning Gibbous",
+        6 => "Last Quarter",
+        _ => "Waning Crescent",
+    };
+    (phase, (illumination * 100.0).round() as u8)
+}
+
+pub fn moon_phase_to_swedish(phase: &str) -> Option<&'static str> {
+    let s = match phase {
+        "New Moon" => "nymåne",
+        "Waxing Crescent" => "tilltagande månskära",
+        "First Quarter" => "första kvarteret",
+        "Waxing Gibbous" => "tilltagande måne",
+        "Full Moon" => "fullmåne",
+        "Waning Gibbous" => "avtagande måne",
+        "Last Quarter" => "sista kvarteret",
+        "Waning Crescent" => "avtagande månskära",
+        _ => return None,
+    };
+    Some(s)
+}
+
+fn format_duration(duration: Duration) -> String {
+    format!(
+        "{} h {} min",
+        duration.num_hours(),
+        duration.num_minutes() % 60
+    )
+}
+
+/// The astronomy section of a post, comparing the day length with the day before
+pub fn section(
+    date: NaiveDate,
+    astronomy: Option<&Astronomy>,
+    previous: Option<&Astronomy>,
+    coordinates: Option<(f64, f64)>,
+    tz: Tz,
+) -> String {
+    let mut lines = Vec::new();
+    match daylight(astronomy, date, coordinates, tz) {
+        Some(Daylight::MidnightSun) => lines.push("Midnattssol, solen går inte ner".to_string()),
+        Some(Daylight::PolarNight) => lines.push("Polarnatt, solen går inte upp".to_string()),
+        Some(Daylight::Normal(sun)) => {
+            lines.push(format!(
+                "Soluppgång: {}, solnedgång: {}",
+                sun.sunrise.format("%H:%M"),
+                sun.sunset.format("%H:%M")
+            ));
+            let mut day_length = format!("Dagens längd: {}", format_duration(sun.day_length));
+            let change = day_length_change(date, astronomy, previous, coordinates, tz);
+            match change.map(|c| c.num_minutes()) {
+                Some(c) if c > 0 => day_length.push_str(&format!(" ({} min längre)", c)),
+                Some(c) if c < 0 => day_length.push_str(&format!(" ({} min kortare)", -c)),
+                _ => (),
+            }
+            lines.push(day_length);
+        }
+        None => (),
+    }
+    let moon = astronomy
+        .and_then(|a| {
+            Some((
+                moon_phase_to_swedish(&a.moon_phase)?,
+                a.moon_illumination.parse::<u8>().ok()?,
+            ))
+        })
+        .or_else(|| {
+            let (phase, illumination) = compute_moon_phase(date);
+            Some((moon_phase_to_swedish(phase)?, illumination))
+        });
+    if let Some((phase, illumination)) = moon {
+        lines.push(format!("Månen: {} ({} %)", phase, illumination));
+    }
+    lines.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use super::*;
+
+    const ABISKO: (f64, f64) = (68.35, 18.83);
+    const KIRUNA: (f64, f64) = (67.86, 20.23);
+    const LUND: (f64, f64) = (55.70, 13.19);
+    const TZ: Tz = chrono_tz::Europe::Stockholm;
+
+    /// Astronomy by date as reported by wttr.in
+    fn fixture(name: &str) -> BTreeMap<NaiveDate, Astronomy> {
+        let json = match name {
+            "abisko" => include_str!("../tests/fixtures/astronomy/abisko.json"),
+            "kiruna" => include_str!("../tests/fixtures/astronomy/kiruna.json"),
+            "lund" => include_str!("../tests/fixtures/astronomy/lund.json"),
+            _ => unreachable!(),
+        };
+        serde_json::from_str(json).unwrap()
+    }
+
+    fn date(date: &str) -> NaiveDate {
+        date.parse().unwrap()
+    }
+
+    fn sun_times(daylight: Option<Daylight>) -> SunTimes {
+        match daylight {
+            Some(Daylight::Normal(sun)) => sun,
+            other => panic!("Expected sunrise and sunset, got {:?}", other),
+        }
+    }
+
+    fn hh_mm(time: NaiveTime) -> String {
+        time.format("%H:%M").to_string()
+    }
+
+    #[test]
+    fn midnight_sun_in_june() {
+        let june = date("2024-06-21");
+        for (name, (lat, lon)) in [("abisko", ABISKO), ("kiruna", KIRUNA)] {
+            assert_eq!(compute(june, lat, lon, TZ), Some(Daylight::MidnightSun));
+            let wttr = fixture(name);
+            assert_eq!(
+                daylight(wttr.get(&june), june, Some((lat, lon)), TZ),
+                Some(Daylight::MidnightSun)
+            );
+            let section = section(
+                june,
+                wttr.get(&june),
+                wttr.get(&date("2024-06-20")),
+                Some((lat, lon)),
+                TZ,
+            );
+            assert_eq!(
+                section,
+                "Midnattssol, solen går inte ner\nMånen: fullmåne (100 %)"
+            );
+        }
+    }
+
+    #[test]
+    fn polar_night_in_december() {
+        let december = date("2024-12-21");
+        for (name, (lat, lon)) in [("abisko", ABISKO), ("kiruna", KIRUNA)] {
+            assert_eq!(compute(december, lat, lon, TZ), Some(Daylight::PolarNight));
+            let wttr = fixture(name);
+            let section = section(
+                december,
+                wttr.get(&december),
+                wttr.get(&date("2024-12-20")),
+                Some((lat, lon)),
+                TZ,
+            );
+            assert_eq!(
+                section,
+                "Polarnatt, solen går inte upp\nMånen: avtagande måne (71 %)"
+            );
+        }
+    }
+
+    #[test]
+    fn normal_days_in_spring_and_autumn() {
+        let (lat, lon) = KIRUNA;
+        // Central European Time
+        let spring = sun_times(compute(date("2024-03-20"), lat, lon, TZ));
+        assert_eq!(hh_mm(spring.sunrise), "05:38");
+        assert_eq!(hh_mm(spring.sunset), "17:55");
+        assert_eq!(spring.day_length.num_minutes(), 12 * 60 + 16);
+        // Central European Summer Time
+        let autumn = sun_times(compute(date("2024-09-23"), lat, lon, TZ));
+        assert_eq!(hh_mm(autumn.sunrise), "06:23");
+        assert_eq!(hh_mm(autumn.sunset), "18:38");
+        assert_eq!(autumn.day_length.num_minutes(), 12 * 60 + 14);
+
+        let spring = section(date("2024-03-20"), None, None, Some(KIRUNA), TZ);
+        assert!(
+            spring.starts_with("Soluppgång: 05:38, solnedgång: 17:55\nDagens längd: 12 h 16 min (")
+        );
+        assert!(spring.contains("min längre)"));
+        let autumn = section(date("2024-09-23"), None, None, Some(KIRUNA), TZ);
+        assert!(autumn.contains("min kortare)"));
+    }
+
+    #[test]
+    fn in_another_time_zone() {
+        let (lat, lon) = KIRUNA;
+        let utc = sun_times(compute(date("2024-03-20"), lat, lon, chrono_tz::UTC));
+        assert_eq!(hh_mm(utc.sunrise), "04:38");
+    }
+
+    #[test]
+    fn wttr_without_sunrise_or_sunset() {
+        let wttr = fixture("kiruna");
+        let june = date("2024-06-21");
+        // Can't tell midnight sun from polar night
+        assert_eq!(from_wttr(&wttr[&june]), None);
+        assert_eq!(daylight(wttr.get(&june), june, None, TZ), None);
+        assert_eq!(
+            section(june, wttr.get(&june), None, None, TZ),
+            "Månen: fullmåne (100 %)"
+        );
+        // Computed instead when the coordinates are known, even outside the polar circle
+        let lund = sun_times(daylight(wttr.get(&june), june, Some(LUND), TZ));
+        assert_eq!(hh_mm(lund.sunrise), "04:22");
+        assert_eq!(hh_mm(lund.sunset), "21:55");
+    }
+
+    #[test]
+    fn lund_uses_the_provider() {
+        let wttr = fixture("lund");
+        let spring = date("2024-03-20");
+        let sun = sun_times(daylight(wttr.get(&spring), spring, Some(LUND), TZ));
+        assert_eq!(hh_mm(sun.sunrise), "06:09");
+        assert_eq!(hh_mm(sun.sunset), "18:20");
+        assert_eq!(
+            section(
+                spring,
+                wttr.get(&spring),
+                wttr.get(&date("2024-03-19")),
+                Some(LUND),
+                TZ
+            ),
+            "Soluppgång: 06:09, solnedgång: 18:20\n\
+             Dagens längd: 12 h 11 min (5 min längre)\n\
+             Månen: tilltagande måne (82 %)"
+        );
+        let autumn = date("2024-09-22");
+        assert_eq!(
+            section(
+                autumn,
+                wttr.get(&autumn),
+                wttr.get(&date("2024-09-21")),
+                Some(LUND),
+                TZ
+            ),
+            "Soluppgång: 06:52, solnedgång: 19:07\n\
+             Dagens längd: 12 h 15 min (4 min kortare)\n\
+             Månen: avtagande måne (79 %)"
+        );
+    }
+}
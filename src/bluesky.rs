@@ -0,0 +1,286 @@
+//! Posting to Bluesky, or any other AT Protocol service
+use std::fmt;
+
+use anyhow::{bail, Result};
+use async_trait::async_trait;
+use chrono::{SecondsFormat, Utc};
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+
+use crate::metrics;
+use crate::publisher::{check, Formatting, Publisher, TIMEOUT};
+
+pub struct Bluesky {
+    name: String,
+    formatting: Formatting,
+    client: reqwest::Client,
+    service: String,
+    identifier: String,
+    password: String,
+    /// Reused until the access token expires
+    session: Mutex<Option<Session>>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Session {
+    access_jwt: String,
+    did: String,
+}
+
+#[derive(Debug, Serialize)]
+struct CreateRecord<'a> {
+    repo: &'a str,
+    collection: &'a str,
+    record: Post<'a>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Post<'a> {
+    #[serde(rename = "$type")]
+    kind: &'a str,
+    text: &'a str,
+    created_at: String,
+    langs: [&'a str; 1],
+}
+
+/// Error returned by XRPC
+#[derive(Debug, Deserialize)]
+struct XrpcError {
+    error: String,
+    #[serde(default)]
+    message: Option<String>,
+}
+
+impl fmt::Display for XrpcError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} {}",
+            self.error,
+            self.message.as_deref().unwrap_or_default()
+        )
+    }
+}
+
+impl Bluesky {
+    pub fn new(
+        name: String,
+        formatting: Formatting,
+        service: &str,
+        identifier: &str,
+        password: &str,
+    ) -> Result<Self> {
+        Ok(Self {
+            name,
+            formatting,
+            client: reqwest::Client::builder().timeout(TIMEOUT).build()?,
+            service: service.trim_end_matches('/').to_string(),
+            identifier: identifier.to_string(),
+            password: password.to_string(),
+            session: Mutex::new(None),
+        })
+    }
+
+    fn url(&self, method: &str) -> String {
+        format!("{}/xrpc/{}", self.service, method)
+    }
+
+    async fn create_session(&self) -> Result<Session> {
+        let response = self
+            .client
+            .post(self.url("com.atproto.server.createSession"))
+            .json(&serde_json::json!({
+                "identifier": self.identifier,
+                "password": self.password,
+            }))
+            .send()
+            .await
+            .map_err(reqwest::Error::without_url)?;
+        Ok(check::<XrpcError>(response)
+            .await?
+            .json()
+            .await
+            .map_err(reqwest::Error::without_url)?)
+    }
+
+    async fn create_record(&self, session: &Session, text: &str) -> Result<reqwest::Response> {
+        Ok(self
+            .client
+            .post(self.url("com.atproto.repo.createRecord"))
+            .bearer_auth(&session.access_jwt)
+            .json(&CreateRecord {
+                repo: &session.did,
+                collection: "app.bsky.feed.post",
+                record: Post {
+                    kind: "app.bsky.feed.post",
+                    text,
+                    created_at: Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true),
+                    langs: ["sv"],
+                },
+            })
+            .send()
+            .await
+            .map_err(reqwest::Error::without_url)?)
+    }
+}
+
+#[async_trait]
+impl Publisher for Bluesky {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    async fn verify(&self) -> Result<()> {
+        let session = self.create_session().await?;
+        *self.session.lock().await = Some(session);
+        Ok(())
+    }
+
+    async fn publish(&self, post: &str) -> Result<()> {
+        let text = self.formatting.apply(post);
+        let mut session = self.session.lock().await;
+        if session.is_none() {
+            *session = Some(self.create_session().await?);
+        }
+        let current = session.clone().expect("just created");
+        let mut response = self.create_record(&current, &text).await?;
+        if response.status() == reqwest::StatusCode::BAD_REQUEST {
+            let status = response.status();
+            let e: XrpcError = response.json().await.map_err(reqwest::Error::without_url)?;
+            // Access tokens only last a couple of hours
+            if e.error != "ExpiredToken" && e.error != "InvalidToken" {
+                bail!("{}: {}", status, e);
+            }
+            metrics::retried(self.name());
+            let renewed = self.create_session().await?;
+            *session = Some(renewed.clone());
+            response = self.create_record(&renewed, &text).await?;
+        }
+        check::<XrpcError>(response).await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::publisher::{MarkdownFlavor, Markup};
+    use crate::stub::Stub;
+
+    const SESSION: &str = r#"{"accessJwt":"jwt-1","refreshJwt":"r","did":"did:plc:vader","handle":"vader.bsky.social"}"#;
+    const RENEWED: &str = r#"{"accessJwt":"jwt-2","refreshJwt":"r","did":"did:plc:vader","handle":"vader.bsky.social"}"#;
+    const CREATED: &str = r#"{"uri":"at://did:plc:vader/app.bsky.feed.post/1","cid":"c"}"#;
+
+    fn bluesky(stub: &Stub) -> Bluesky {
+        let formatting = Formatting {
+            markup: Markup::Plain,
+            max_chars: Some(300),
+            markdown: MarkdownFlavor::CommonMark,
+        };
+        Bluesky::new(
+            "bluesky".to_string(),
+            formatting,
+            &stub.url,
+            "vader.bsky.social",
+            "app-password",
+        )
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn logs_in_and_posts() {
+        let stub = Stub::start(&[(200, SESSION), (200, CREATED), (200, CREATED)]).await;
+        let bluesky = bluesky(&stub);
+        bluesky.verify().await.unwrap();
+        bluesky.publish("Sol").await.unwrap();
+        bluesky.publish("Regn").await.unwrap();
+        let requests = stub.requests();
+        assert_eq!(requests.len(), 3, "The session is reused");
+        assert_eq!(requests[0].path, "/xrpc/com.atproto.server.createSession");
+        assert_eq!(
+            requests[0].json(),
+            serde_json::json!({ "identifier": "vader.bsky.social", "password": "app-password" })
+        );
+        assert_eq!(requests[1].path, "/xrpc/com.atproto.repo.createRecord");
+        assert_eq!(requests[1].header("authorization"), Some("Bearer jwt-1"));
+        let body = requests[1].json();
+        assert_eq!(body["repo"], "did:plc:vader");
+        assert_eq!(body["collection"], "app.bsky.feed.post");
+        assert_eq!(body["record"]["$type"], "app.bsky.feed.post");
+        assert_eq!(body["record"]["text"], "Sol");
+        assert_eq!(body["record"]["langs"], serde_json::json!(["sv"]));
+        assert!(body["record"]["createdAt"].as_str().unwrap().ends_with('Z'));
+        assert_eq!(requests[2].json()["record"]["text"], "Regn");
+    }
+
+    #[tokio::test]
+    async fn logs_in_before_the_first_post() {
+        let stub = Stub::start(&[(200, SESSION), (200, CREATED)]).await;
+        bluesky(&stub).publish("Sol").await.unwrap();
+        let paths: Vec<_> = stub.requests().into_iter().map(|r| r.path).collect();
+        assert_eq!(
+            paths,
+            [
+                "/xrpc/com.atproto.server.createSession",
+                "/xrpc/com.atproto.repo.createRecord"
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn logs_in_again_when_the_token_expires() {
+        let stub = Stub::start(&[
+            (200, SESSION),
+            (
+                400,
+                r#"{"error":"ExpiredToken","message":"Token has expired"}"#,
+            ),
+            (200, RENEWED),
+            (200, CREATED),
+        ])
+        .await;
+        let bluesky = bluesky(&stub);
+        bluesky.verify().await.unwrap();
+        bluesky.publish("Sol").await.unwrap();
+        let requests = stub.requests();
+        assert_eq!(requests.len(), 4);
+        assert_eq!(requests[2].path, "/xrpc/com.atproto.server.createSession");
+        assert_eq!(requests[3].path, "/xrpc/com.atproto.repo.createRecord");
+        assert_eq!(requests[3].header("authorization"), Some("Bearer jwt-2"));
+        assert_eq!(requests[3].json()["record"]["text"], "Sol");
+    }
+
+    #[tokio::test]
+    async fn fails_on_other_errors_without_logging_in_again() {
+        let stub = Stub::start(&[
+            (200, SESSION),
+            (
+                400,
+                r#"{"error":"InvalidRequest","message":"Record too long"}"#,
+            ),
+        ])
+        .await;
+        let e = bluesky(&stub).publish("Sol").await.unwrap_err();
+        assert_eq!(
+            e.to_string(),
+            "400 Bad Request: InvalidRequest Record too long"
+        );
+        assert_eq!(stub.requests().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn fails_to_log_in_with_a_wrong_password() {
+        let stub = Stub::start(&[(
+            401,
+            r#"{"error":"AuthenticationRequired","message":"Invalid identifier or password"}"#,
+        )])
+        .await;
+        let e = bluesky(&stub).verify().await.unwrap_err();
+        assert_eq!(
+            e.to_string(),
+            "401 Unauthorized: AuthenticationRequired Invalid identifier or password"
+        );
+    }
+}
@@ -0,0 +1,55 @@
+//! Text charts of the temperature during a day, with alt texts describing them
+use crate::vader::Weather;
+
+const BARS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+
+/// Hour and temperature of each hourly forecast
+fn temperatures(weather: &Weather) -> Vec<(u16, i32)> {
+    weather
+        .hourly
+        .iter()
+        .filter_map(|h| {
+            Some((
+                h.time.parse::<u16>().ok()? / 100,
+                h.temp_c.parse::<i32>().ok()?,
+            ))
+        })
+        .collect()
+}
+
+/// A sparkline of the temperature, with the temperatures above and the hours below
+pub fn temperature_chart(weather: &Weather) -> String {
+    let temps = temperatures(weather);
+    let (Some(min), Some(max)) = (
+        temps.iter().map(|&(_, t)| t).min(),
+        temps.iter().map(|&(_, t)| t).max(),
+    ) else {
+        return String::new();
+    };
+    let range = (max - min).max(1) as f32;
+    let mut values = String::new();
+    let mut bars = String::new();
+    let mut hours = String::new();
+    for &(hour, temp) in &temps {
+        let bar = ((temp - min) as f32 / range * (BARS.len() - 1) as f32).round() as usize;
+        values.push_str(&format!("{:>3}", temp));
+        bars.push_str(&format!("{:>3}", BARS[bar].to_string().repeat(2)));
+        hours.push_str(&format!(" {:02}", hour));
+    }
+    format!("Temperatur (°C)\n{}\n{}\n{}", values, bars, hours)
+}
+
+/// Describes the chart for those who can't see it
+pub fn alt_text(weather: &Weather) -> String {
+    let temps = temperatures(weather);
+    let (Some(&(min_hour, min)), Some(&(max_hour, max))) = (
+        temps.iter().min_by_key(|&&(_, t)| t),
+        temps.iter().max_by_key(|&&(_, t)| t),
+    ) else {
+        return String::new();
+    };
+    format!(
+        "Diagram över temperaturen den {}. Som kallast {} °C klockan {:02} och som varmast {} °C klockan {:02}.",
+        weather.date, min, min_hour, max, max_hour
+    )
+}
@@ -0,0 +1,90 @@
+use std::path::PathBuf;
+
+use clap::{Parser, Subcommand, ValueEnum};
+
+use crate::vader::Day;
+
+/// A bot which posts the weather forecast to Mastodon
+#[derive(Debug, Parser)]
+#[command(version)]
+pub struct Cli {
+    /// Config file, instead of looking for config.toml
+    #[arg(long, global = true)]
+    pub config: Option<PathBuf>,
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Post on schedule, the default. Reloads the config on SIGHUP or when it changes.
+    Run,
+    /// Print what would be posted, without posting it
+    Preview {
+        /// Name of the location, doesn't have to be configured
+        #[arg(long)]
+        location: String,
+        #[arg(long, value_enum, default_value_t = Day::Tomorrow)]
+        day: Day,
+    },
+    /// Post the forecast immediately
+    PostNow {
+        /// Only post for this location, instead of all configured locations
+        #[arg(long)]
+        location: Option<String>,
+        #[arg(long, value_enum, default_value_t = Day::Tomorrow)]
+        day: Day,
+    },
+    /// Validate config.toml and the Mastodon credentials
+    CheckConfig,
+    /// Print the post, alt text and chart for a saved forecast, without the network
+    Render {
+        /// Forecast saved with `fetch --save`
+        #[arg(long)]
+        input: PathBuf,
+        #[arg(long, value_enum, default_value_t = Format::Wttr)]
+        format: Format,
+        /// Defaults to the location in the forecast
+        #[arg(long)]
+        location: Option<String>,
+        #[arg(long, value_enum, default_value_t = Day::Tomorrow)]
+        day: Day,
+    },
+    /// Register the bot on a Mastodon instance and save the credentials
+    Register {
+        /// Url of the instance, e.g. https://mastodon.social
+        #[arg(long)]
+        instance: String,
+        /// Save the credentials in config.toml instead of secrets.toml
+        #[arg(long)]
+        in_config: bool,
+        /// Replace existing credentials
+        #[arg(long)]
+        force: bool,
+    },
+    /// Follow an ActivityPub actor from the bot's own actor, e.g. another bot
+    Follow {
+        /// Url of the actor, e.g. https://vader.example/users/vader
+        #[arg(long)]
+        actor: String,
+    },
+    /// Fetch a forecast as is
+    Fetch {
+        #[arg(long)]
+        location: String,
+        #[arg(long, value_enum, default_value_t = Format::Wttr)]
+        format: Format,
+        /// Save to this file instead of printing it
+        #[arg(long)]
+        save: Option<PathBuf>,
+    },
+}
+
+/// The format of a forecast
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Format {
+    /// JSON from wttr.in
+    Wttr,
+    /// Daily JSON forecast from Open-Meteo
+    OpenMeteo,
+}
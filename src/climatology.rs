@@ -0,0 +1,375 @@
+//! Comparisons with the normal temperature and the bot's own history
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+
+use anyhow::{anyhow, Context, Result};
+use chrono::{Datelike, NaiveDate};
+use serde::{Deserialize, Serialize};
+
+use crate::vader::{Day, Weather};
+
+/// How many degrees from the normal before it's worth mentioning
+const NOTABLE_DIFFERENCE: f32 = 3.0;
+
+/// Normal temperatures for a day of the year
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Normal {
+    pub avg_temp_c: f32,
+    pub max_temp_c: f32,
+}
+
+/// Normals keyed by month and day
+#[derive(Debug, Default)]
+pub struct Normals(HashMap<(u32, u32), Normal>);
+
+impl Normals {
+    /// Load normals from a CSV file with the columns `month,day,avg_temp_c,max_temp_c`,
+    /// e.g. exported from SMHI's normals for 1991–2020. Empty lines, lines starting
+    /// with `#` and a header line are skipped.
+    pub fn load(path: &Path) -> Result<Self> {
+        let csv = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read normals from {}", path.display()))?;
+        let mut normals = HashMap::new();
+        for (i, line) in csv.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') || line.starts_with("month") {
+                continue;
+            }
+            let parse = || -> Option<((u32, u32), Normal)> {
+                let mut columns = line.split(',').map(str::trim);
+                let month = columns.next()?.parse().ok()?;
+                let day = columns.next()?.parse().ok()?;
+                let avg_temp_c = columns.next()?.parse().ok()?;
+                let max_temp_c = columns.next()?.parse().ok()?;
+                Some((
+                    (month, day),
+                    Normal {
+                        avg_temp_c,
+                        max_temp_c,
+                    },
+                ))
+            };
+            let (day, normal) = parse()
+                .ok_or_else(|| anyhow!("Invalid normal on line {} of {}", i + 1, path.display()))?;
+            normals.insert(day, normal);
+        }
+        Ok(Self(normals))
+    }
+
+    /// The normal for a date, using the 28th for leap days if there's no normal for them
+    pub fn get(&self, date: NaiveDate) -> Option<&Normal> {
+        self.0.get(&(date.month(), date.day())).or_else(|| {
+            (date.month() == 2 && date.day() == 29)
+                .then(|| self.0.get(&(2, 28)))
+                .flatten()
+        })
+    }
+}
+
+/// What the bot forecasted for a day
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct DayRecord {
+    pub avg_temp_c: f32,
+    pub max_temp_c: f32,
+}
+
+impl DayRecord {
+    pub fn from_weather(weather: &Weather) -> Option<(NaiveDate, Self)> {
+        Some((
+            NaiveDate::parse_from_str(&weather.date, "%Y-%m-%d").ok()?,
+            DayRecord {
+                avg_temp_c: weather.avg_temp_c.parse().ok()?,
+                max_temp_c: weather.max_temp_c.parse().ok()?,
+            },
+        ))
+    }
+}
+
+fn degrees(diff: f32) -> String {
+    let diff = diff.abs().round() as i32;
+    if diff == 1 {
+        "1 grad".to_string()
+    } else {
+        format!("{} grader", diff)
+    }
+}
+
+fn warmer_or_colder(diff: f32) -> &'static str {
+    if diff > 0.0 {
+        "varmare"
+    } else {
+        "kallare"
+    }
+}
+
+/// E.g. "5 grader varmare än normalt för årstiden", if the difference is notable
+pub fn compare_normal(normal: &Normal, record: &DayRecord, day: Day) -> Option<String> {
+    let diff = record.avg_temp_c - normal.avg_temp_c;
+    let max_diff = record.max_temp_c - normal.max_temp_c;
+    // Use whichever differs the most, so a warm afternoon isn't hidden by a cold night
+    if diff.abs() >= max_diff.abs() && diff.abs() >= NOTABLE_DIFFERENCE {
+        Some(format!(
+            "{} {} än normalt för årstiden",
+            degrees(diff),
+            warmer_or_colder(diff)
+        ))
+    } else if max_diff.abs() >= NOTABLE_DIFFERENCE {
+        let whose = match day {
+            Day::Today => "Dagens",
+            Day::Tomorrow => "Morgondagens",
+        };
+        Some(format!(
+            "{} högsta temperatur blir {} {} än normalt för årstiden",
+            whose,
+            degrees(max_diff),
+            warmer_or_colder(max_diff)
+        ))
+    } else {
+        None
+    }
+}
+
+const MONTHS: [&str; 12] = [
+    "januari",
+    "februari",
+    "mars",
+    "april",
+    "maj",
+    "juni",
+    "juli",
+    "augusti",
+    "september",
+    "oktober",
+    "november",
+    "december",
+];
+
+/// Earlier years needed before a day can be the warmest or coldest, since
+/// with fewer almost any day would be
+const MIN_RECORD_YEARS: usize = 3;
+
+/// Compare with the same day in earlier years, e.g. "Varmaste 14 juni sedan
+/// boten började spara prognoser 2021". The history holds the bot's own
+/// forecasts, not measurements.
+pub fn compare_history(
+    history: &BTreeMap<NaiveDate, DayRecord>,
+    date: NaiveDate,
+    day: &DayRecord,
+) -> Option<String> {
+    let earlier: Vec<_> = history
+        .iter()
+        .filter(|(d, _)| {
+            d.year() < date.year() && d.month() == date.month() && d.day() == date.day()
+        })
+        .collect();
+    if let Some((first, _)) = earlier
+        .first()
+        .filter(|_| earlier.len() >= MIN_RECORD_YEARS)
+    {
+        let name = format!("{} {}", date.day(), MONTHS[date.month0() as usize]);
+        if earlier.iter().all(|(_, r)| day.max_temp_c > r.max_temp_c) {
+            return Some(format!(
+                "Varmaste {} sedan boten började spara prognoser {}",
+                name,
+                first.year()
+            ));
+        }
+        if earlier.iter().all(|(_, r)| day.max_temp_c < r.max_temp_c) {
+            return Some(format!(
+                "Kallaste {} sedan boten började spara prognoser {}",
+                name,
+                first.year()
+            ));
+        }
+    }
+    let last_year = date.with_year(date.year() - 1)?;
+    let last_year = history.get(&last_year)?;
+    let diff = day.avg_temp_c - last_year.avg_temp_c;
+    if diff.abs() < NOTABLE_DIFFERENCE {
+        return None;
+    }
+    Some(format!(
+        "{} {} än samma dag förra året",
+        degrees(diff),
+        warmer_or_colder(diff)
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(date: &str) -> NaiveDate {
+        date.parse().unwrap()
+    }
+
+    /// Load normals from `csv` written to a file of its own
+    fn load(name: &str, csv: &str) -> Result<Normals> {
+        let path = std::env::temp_dir().join(format!(
+            "vader_bot-normals-{}-{}.csv",
+            name,
+            std::process::id()
+        ));
+        std::fs::write(&path, csv).unwrap();
+        let normals = Normals::load(&path);
+        std::fs::remove_file(&path).unwrap();
+        normals
+    }
+
+    fn record(avg_temp_c: f32, max_temp_c: f32) -> DayRecord {
+        DayRecord {
+            avg_temp_c,
+            max_temp_c,
+        }
+    }
+
+    #[test]
+    fn loads_normals_skipping_the_header_and_comments() {
+        let normals = load(
+            "valid",
+            "month,day,avg_temp_c,max_temp_c\n\
+             # SMHI 1991-2020, Lund\n\
+             \n\
+             2, 28, -0.5, 2.1\n\
+             6,14,14.2,19.8\n",
+        )
+        .unwrap();
+        assert_eq!(
+            normals.get(date("2024-06-14")),
+            Some(&Normal {
+                avg_temp_c: 14.2,
+                max_temp_c: 19.8
+            })
+        );
+        assert_eq!(normals.get(date("2024-06-15")), None);
+    }
+
+    #[test]
+    fn uses_the_28th_for_leap_days() {
+        let normals = load("leap", "2,28,-0.5,2.1\n3,1,0.0,3.0\n").unwrap();
+        assert_eq!(
+            normals.get(date("2024-02-29")),
+            normals.get(date("2023-02-28"))
+        );
+        let with_leap_day = load("with-leap", "2,28,-0.5,2.1\n2,29,-0.4,2.2\n").unwrap();
+        assert_eq!(
+            with_leap_day.get(date("2024-02-29")).unwrap().avg_temp_c,
+            -0.4
+        );
+    }
+
+    #[test]
+    fn reports_the_line_of_invalid_normals() {
+        for (csv, line) in [
+            (
+                "month,day,avg_temp_c,max_temp_c\n6,14,14.2,19.8\n6,15,warm,20\n",
+                3,
+            ),
+            ("# Lund\n\n6,14,14.2\n", 3),
+            ("6,x,14.2,19.8\n", 1),
+        ] {
+            let e = load("invalid", csv).unwrap_err().to_string();
+            assert!(
+                e.starts_with(&format!("Invalid normal on line {} of ", line)),
+                "{}",
+                e
+            );
+        }
+        let e = Normals::load(Path::new("/nonexistent/normals.csv")).unwrap_err();
+        assert_eq!(
+            e.to_string(),
+            "Failed to read normals from /nonexistent/normals.csv"
+        );
+    }
+
+    #[test]
+    fn compares_with_the_normal() {
+        let normal = Normal {
+            avg_temp_c: 14.0,
+            max_temp_c: 19.0,
+        };
+        for (day, record, expected) in [
+            (Day::Tomorrow, record(16.9, 21.9), None),
+            (
+                Day::Tomorrow,
+                record(17.0, 20.0),
+                Some("3 grader varmare än normalt för årstiden"),
+            ),
+            (
+                Day::Today,
+                record(9.0, 18.0),
+                Some("5 grader kallare än normalt för årstiden"),
+            ),
+            (
+                Day::Tomorrow,
+                record(15.0, 25.0),
+                Some(
+                    "Morgondagens högsta temperatur blir 6 grader varmare än normalt för årstiden",
+                ),
+            ),
+            (
+                Day::Today,
+                record(13.0, 15.0),
+                Some("Dagens högsta temperatur blir 4 grader kallare än normalt för årstiden"),
+            ),
+        ] {
+            assert_eq!(
+                compare_normal(&normal, &record, day).as_deref(),
+                expected,
+                "{:?}",
+                record
+            );
+        }
+    }
+
+    #[test]
+    fn compares_with_the_history() {
+        let mut history = BTreeMap::from([
+            (date("2021-06-14"), record(13.0, 19.0)),
+            (date("2022-06-14"), record(12.0, 18.0)),
+            (date("2023-06-14"), record(14.0, 22.0)),
+            // Other days don't count
+            (date("2023-06-15"), record(30.0, 35.0)),
+        ]);
+        let june = date("2024-06-14");
+        for (record, expected) in [
+            (
+                record(15.0, 23.0),
+                Some("Varmaste 14 juni sedan boten började spara prognoser 2021"),
+            ),
+            (
+                record(10.0, 17.0),
+                Some("Kallaste 14 juni sedan boten började spara prognoser 2021"),
+            ),
+            (
+                record(10.0, 20.0),
+                Some("4 grader kallare än samma dag förra året"),
+            ),
+            // Only a degree warmer than last year
+            (record(15.0, 20.0), None),
+            (
+                record(17.4, 20.0),
+                Some("3 grader varmare än samma dag förra året"),
+            ),
+        ] {
+            assert_eq!(
+                compare_history(&history, june, &record).as_deref(),
+                expected,
+                "{:?}",
+                record
+            );
+        }
+        // Nothing to compare with the first year
+        assert_eq!(
+            compare_history(&history, date("2021-06-14"), &record(30.0, 35.0)),
+            None
+        );
+        // Two earlier years are too few for a record, but not to compare with last year
+        history.remove(&date("2021-06-14"));
+        assert_eq!(compare_history(&history, june, &record(15.0, 23.0)), None);
+        assert_eq!(
+            compare_history(&history, june, &record(10.0, 17.0)).as_deref(),
+            Some("4 grader kallare än samma dag förra året")
+        );
+    }
+}
@@ -1,19 +1,513 @@
-use anyhow::Result;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Result};
+use chrono::{NaiveDate, NaiveTime, Utc, Weekday};
+use chrono_tz::Tz;
 use elefren::data::Data;
+use itertools::Itertools;
 use serde::{Deserialize, Serialize};
 
-const CONFIG_FILE: &str = "config.toml";
+use crate::activitypub::{self, ActivityPubConfig};
+use crate::admin::AdminConfig;
+use crate::climatology::Normals;
+use crate::feed::FeedConfig;
+use crate::loader::{self, Problem};
+use crate::marine::MarineConfig;
+use crate::mastodon;
+use crate::open_meteo;
+use crate::outlook::{OutlookConfig, OutlookKind};
+use crate::pollen::{PollenConfig, PollenProvider};
+use crate::publisher::{self, Formatting, PublisherConfig};
+use crate::schedule::{self, Schedule};
+use crate::secrets;
+use crate::smhi;
+
+/// Name of the config file, see [`loader`] for where it is looked for
+pub const CONFIG_FILE: &str = "config.toml";
+/// Credentials saved by `register` next to the config file, used if there are
+/// none in the config. See [`secrets`] for how the environment overrides them.
+pub const SECRETS_FILE: &str = "secrets.toml";
 
 /// Mastodon config
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Config {
-    pub mastodon: Data,
-    pub location: String,
+    #[serde(default)]
+    pub mastodon: Option<MastodonConfig>,
+    /// Single location, shorthand for a `[[locations]]` entry with only a name
+    #[serde(default)]
+    pub location: Option<String>,
+    #[serde(default)]
+    pub locations: Vec<Location>,
+    /// Official SMHI warnings, disabled if missing
+    #[serde(default)]
+    pub warnings: Option<WarningsConfig>,
+    /// Tomorrow's forecast, posted in the evening
+    #[serde(default = "default_forecast")]
+    pub forecast: PostConfig,
+    /// Today's forecast, posted in the morning
+    #[serde(default)]
+    pub morning: Option<PostConfig>,
+    /// Posts summarising the coming days
+    #[serde(default)]
+    pub outlooks: Vec<OutlookConfig>,
+    /// Pollen forecasts, disabled if missing
+    #[serde(default)]
+    pub pollen: Option<PollenConfig>,
+    /// Where to post besides Mastodon
+    #[serde(default)]
+    pub publishers: Vec<PublisherConfig>,
+    /// Act as an ActivityPub server of its own, disabled if missing
+    #[serde(default)]
+    pub activitypub: Option<ActivityPubConfig>,
+    /// Atom and JSON Feed files of the forecasts, disabled if missing
+    #[serde(default)]
+    pub feed: Option<FeedConfig>,
+    /// HTTP API for the status and posting by hand, disabled if missing
+    #[serde(default)]
+    pub admin: Option<AdminConfig>,
+}
+
+/// The Mastodon account to post to
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(try_from = "RawMastodonConfig", into = "toml::Table")]
+pub struct MastodonConfig {
+    /// Missing from the config if they are only in the environment or the
+    /// secrets file, see [`secrets`]
+    pub credentials: Option<Data>,
+    /// Overrides the limit of 500 characters, for instances allowing longer posts
+    pub max_chars: Option<usize>,
+}
+
+impl MastodonConfig {
+    pub fn formatting(&self) -> Formatting {
+        Formatting {
+            max_chars: self.max_chars.or(mastodon::FORMATTING.max_chars),
+            ..mastodon::FORMATTING
+        }
+    }
+}
+
+/// `[mastodon]` as written, the fields of `Data` next to the settings, which
+/// `#[serde(flatten)]` can't leave out when the credentials are elsewhere
+#[derive(Deserialize)]
+struct RawMastodonConfig {
+    base: Option<String>,
+    client_id: Option<String>,
+    client_secret: Option<String>,
+    redirect: Option<String>,
+    token: Option<String>,
+    #[serde(default)]
+    max_chars: Option<usize>,
+}
+
+impl TryFrom<RawMastodonConfig> for MastodonConfig {
+    type Error = String;
+
+    fn try_from(raw: RawMastodonConfig) -> std::result::Result<Self, Self::Error> {
+        let fields = [
+            ("base", raw.base),
+            ("client_id", raw.client_id),
+            ("client_secret", raw.client_secret),
+            ("redirect", raw.redirect),
+            ("token", raw.token),
+        ];
+        let credentials = if fields.iter().all(|(_, value)| value.is_none()) {
+            None
+        } else {
+            let [base, client_id, client_secret, redirect, token] = fields
+                .map(|(field, value)| value.ok_or_else(|| format!("missing field `{}`", field)));
+            Some(Data {
+                base: base?.into(),
+                client_id: client_id?.into(),
+                client_secret: client_secret?.into(),
+                redirect: redirect?.into(),
+                token: token?.into(),
+            })
+        };
+        Ok(Self {
+            credentials,
+            max_chars: raw.max_chars,
+        })
+    }
+}
+
+impl From<MastodonConfig> for toml::Table {
+    fn from(config: MastodonConfig) -> Self {
+        let mut table = match config.credentials.map(toml::Value::try_from) {
+            Some(Ok(toml::Value::Table(table))) => table,
+            _ => toml::Table::new(),
+        };
+        if let Some(max_chars) = config.max_chars {
+            table.insert(
+                "max_chars".to_string(),
+                toml::Value::Integer(max_chars as i64),
+            );
+        }
+        table
+    }
+}
+
+/// A location to post the weather for
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Location {
+    /// Name used when querying wttr.in
+    pub name: String,
+    /// Overrides the latitude reported by wttr.in
+    #[serde(default)]
+    pub latitude: Option<f64>,
+    /// Overrides the longitude reported by wttr.in
+    #[serde(default)]
+    pub longitude: Option<f64>,
+    /// Post a notice for the season's first night frost
+    #[serde(default)]
+    pub first_frost_notice: bool,
+    /// Post a notice for the season's first snow
+    #[serde(default)]
+    pub first_snow_notice: bool,
+    /// Mention the air quality when it is poor
+    #[serde(default)]
+    pub air_quality: bool,
+    /// Coastal point to report the water temperature for
+    #[serde(default)]
+    pub marine: Option<MarineConfig>,
+    /// CSV file with normal temperatures for each day of the year
+    #[serde(default)]
+    pub normals: Option<PathBuf>,
+    /// Names of the publishers to post to, all of them if empty
+    #[serde(default)]
+    pub publishers: Vec<String>,
+    /// Time zone for the sun times, Europe/Stockholm if missing
+    #[serde(default)]
+    pub time_zone: Option<Tz>,
+}
+
+impl Location {
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            ..Default::default()
+        }
+    }
+
+    pub fn time_zone(&self) -> Tz {
+        self.time_zone.unwrap_or(chrono_tz::Europe::Stockholm)
+    }
+
+    /// The date at the location now
+    pub fn today(&self) -> NaiveDate {
+        Utc::now().with_timezone(&self.time_zone()).date_naive()
+    }
+
+    /// The location without any sources which need the network
+    pub fn offline(&self) -> Self {
+        Self {
+            air_quality: false,
+            marine: None,
+            ..self.clone()
+        }
+    }
+}
+
+/// Config for fetching SMHI warnings
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WarningsConfig {
+    /// URL to a CAP XML document, either a single alert or a list of alerts
+    #[serde(default = "default_warnings_url")]
+    pub url: String,
+    #[serde(default)]
+    pub mode: WarningsMode,
+    /// How often to poll for new warnings when posting them separately
+    #[serde(default = "default_poll_interval")]
+    pub poll_interval_minutes: u64,
+}
+
+/// How warnings are published
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WarningsMode {
+    /// Append active warnings to the daily forecast
+    #[default]
+    Append,
+    /// Post new warnings as soon as they are issued
+    Post,
+}
+
+fn default_warnings_url() -> String {
+    smhi::CAP_URL.to_string()
+}
+
+fn default_poll_interval() -> u64 {
+    15
+}
+
+/// A scheduled post
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(from = "RawPostConfig")]
+pub struct PostConfig {
+    #[serde(flatten)]
+    pub schedule: Schedule,
+    /// Overrides the default template of the post
+    #[serde(default)]
+    pub template: Option<String>,
+}
+
+/// A scheduled post as written. Errors inside `#[serde(flatten)]` lose their
+/// path, so the schedule's fields are deserialized here instead.
+#[derive(Deserialize)]
+struct RawPostConfig {
+    #[serde(with = "schedule::hh_mm")]
+    time: NaiveTime,
+    #[serde(default)]
+    weekdays: Vec<Weekday>,
+    #[serde(default)]
+    template: Option<String>,
+}
+
+impl From<RawPostConfig> for PostConfig {
+    fn from(raw: RawPostConfig) -> Self {
+        Self {
+            schedule: Schedule {
+                time: raw.time,
+                weekdays: raw.weekdays,
+            },
+            template: raw.template,
+        }
+    }
+}
+
+fn default_forecast() -> PostConfig {
+    PostConfig {
+        schedule: Schedule::daily(20, 0),
+        template: None,
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        toml::from_str("").expect("All fields have defaults")
+    }
 }
 
 impl Config {
-    /// Create a new config
-    pub fn new() -> Result<Self> {
-        Ok(toml::from_str(&std::fs::read_to_string(CONFIG_FILE)?)?)
+    /// Load the config from `path`, or the first config file found, failing
+    /// if there are any problems with it
+    pub fn load(path: Option<&Path>) -> Result<Self> {
+        let path = loader::find(path)?;
+        let (config, problems) = loader::load(&path)?;
+        match config {
+            Some(config) if problems.is_empty() => Ok(config),
+            _ => bail!(
+                "Found {} problems in the config:\n{}",
+                problems.len(),
+                problems.iter().join("\n")
+            ),
+        }
+    }
+
+    /// The config with the Mastodon credentials from the environment and the
+    /// secrets file next to the config file at `path`
+    pub fn with_secrets(self, path: &Path) -> Result<Self> {
+        let MastodonConfig {
+            credentials,
+            max_chars,
+        } = self.mastodon.unwrap_or_default();
+        let credentials = secrets::mastodon(credentials, &secrets_path(path))?;
+        Ok(Self {
+            mastodon: (credentials.is_some() || max_chars.is_some()).then_some(MastodonConfig {
+                credentials,
+                max_chars,
+            }),
+            ..self
+        })
     }
+
+    /// Problems with the config which parsing doesn't catch
+    pub fn problems(&self) -> Vec<Problem> {
+        let mut problems = Vec::new();
+        match self.mastodon.as_ref().map(|m| m.credentials.as_ref()) {
+            None => (),
+            Some(None) => problems.push(Problem::at(
+                "mastodon",
+                "There are no Mastodon credentials in the config, the secrets file or the environment",
+            )),
+            Some(Some(mastodon)) if mastodon.base.is_empty() || mastodon.token.is_empty() => {
+                problems.push(Problem::at(
+                    "mastodon",
+                    "The Mastodon credentials need both a base url and a token",
+                ));
+            }
+            Some(Some(mastodon)) => {
+                if let Err(e) = reqwest::Url::parse(&mastodon.base) {
+                    problems.push(Problem::at(
+                        "mastodon.base",
+                        format!("Invalid instance url {}: {}", mastodon.base, e),
+                    ));
+                }
+            }
+        }
+        if let Some(activitypub) = self.activitypub.as_ref() {
+            match reqwest::Url::parse(&activitypub.url) {
+                Ok(url) if url.host_str().is_some() => (),
+                Ok(_) => problems.push(Problem::at(
+                    "activitypub.url",
+                    format!("The url {} has no host", activitypub.url),
+                )),
+                Err(e) => problems.push(Problem::at(
+                    "activitypub.url",
+                    format!("Invalid url {}: {}", activitypub.url, e),
+                )),
+            }
+            if activitypub.username.is_empty() {
+                problems.push(Problem::at("activitypub.username", "Empty username"));
+            }
+        }
+        if let Some(feed) = self.feed.as_ref() {
+            if feed.atom.is_none() && feed.json.is_none() {
+                problems.push(Problem::at(
+                    "feed",
+                    "The feed needs a file to write, atom or json",
+                ));
+            }
+            if let Some(Err(e)) = feed.url.as_deref().map(reqwest::Url::parse) {
+                problems.push(Problem::at(
+                    "feed.url",
+                    format!(
+                        "Invalid url {}: {}",
+                        feed.url.as_deref().unwrap_or_default(),
+                        e
+                    ),
+                ));
+            }
+            if feed.max_entries == 0 {
+                problems.push(Problem::at(
+                    "feed.max_entries",
+                    "The feed needs room for entries",
+                ));
+            }
+        }
+        let mut names = Vec::new();
+        if self.mastodon.is_some() {
+            names.push(publisher::MASTODON);
+        }
+        if self.activitypub.is_some() {
+            names.push(activitypub::ACTIVITYPUB);
+        }
+        for (i, publisher) in self.publishers.iter().enumerate() {
+            let path = format!("publishers.{}", i);
+            if names.contains(&publisher.name.as_str()) {
+                problems.push(Problem::at(
+                    format!("{}.name", path),
+                    format!("There already is a publisher named {}", publisher.name),
+                ));
+            }
+            names.push(&publisher.name);
+            for (key, url) in publisher.kind.urls() {
+                if let Err(e) = reqwest::Url::parse(url) {
+                    problems.push(Problem::at(
+                        format!("{}.{}", path, key),
+                        format!("Invalid url {}: {}", url, e),
+                    ));
+                }
+            }
+        }
+        if self.location.is_none() && self.locations.is_empty() {
+            problems.push(Problem::at("locations", "No locations configured"));
+        }
+        let locations = self
+            .location
+            .iter()
+            .map(|name| ("location".to_string(), Location::new(name)))
+            .chain(
+                self.locations
+                    .iter()
+                    .enumerate()
+                    .map(|(i, location)| (format!("locations.{}", i), location.clone())),
+            );
+        for (path, location) in locations {
+            if location.name.trim().is_empty() {
+                problems.push(Problem::at(path, "Location with an empty name"));
+                continue;
+            }
+            if location.latitude.is_some() != location.longitude.is_some() {
+                problems.push(Problem::at(
+                    &path,
+                    format!("{}: both latitude and longitude must be set", location.name),
+                ));
+            }
+            for (i, name) in location.publishers.iter().enumerate() {
+                if !names.contains(&name.as_str()) {
+                    problems.push(Problem::at(
+                        format!("{}.publishers.{}", path, i),
+                        format!("{}: there is no publisher named {}", location.name, name),
+                    ));
+                }
+            }
+            if let Some(normals) = location.normals.as_ref() {
+                if let Err(e) = Normals::load(normals) {
+                    problems.push(Problem::at(
+                        format!("{}.normals", path),
+                        format!("{}: {}", location.name, e),
+                    ));
+                }
+            }
+        }
+        if let Some(warnings) = self.warnings.as_ref() {
+            if let Err(e) = reqwest::Url::parse(&warnings.url) {
+                problems.push(Problem::at(
+                    "warnings.url",
+                    format!("Invalid warnings url {}: {}", warnings.url, e),
+                ));
+            }
+        }
+        if let Some(pollen) = self.pollen.as_ref() {
+            if pollen.provider == PollenProvider::Json && pollen.url.is_none() {
+                problems.push(Problem::at(
+                    "pollen",
+                    "The JSON pollen provider needs a url",
+                ));
+            }
+        }
+        for (i, outlook) in self.outlooks.iter().enumerate() {
+            if outlook.kind == OutlookKind::Weekly
+                && !(1..open_meteo::MAX_DAYS).contains(&outlook.days)
+            {
+                problems.push(Problem::at(
+                    format!("outlooks.{}.days", i),
+                    format!(
+                        "Weekly outlooks can be 1 to {} days, not {}",
+                        open_meteo::MAX_DAYS - 1,
+                        outlook.days
+                    ),
+                ));
+            }
+        }
+        problems
+    }
+
+    /// The config without any sources which need the network, except wttr.in
+    pub fn offline(&self) -> Self {
+        Self {
+            warnings: None,
+            pollen: None,
+            locations: self.locations.iter().map(Location::offline).collect(),
+            ..self.clone()
+        }
+    }
+
+    /// All configured locations, including the `location` shorthand
+    pub fn locations(&self) -> Vec<Location> {
+        self.location
+            .iter()
+            .map(|name| Location::new(name))
+            .chain(self.locations.iter().cloned())
+            .collect()
+    }
+}
+
+/// The secrets file next to the config file at `path`
+pub fn secrets_path(config: &Path) -> PathBuf {
+    config
+        .parent()
+        .unwrap_or_else(|| Path::new(""))
+        .join(SECRETS_FILE)
 }
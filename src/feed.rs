@@ -0,0 +1,399 @@
+//! Atom and JSON Feed files of the posted forecasts, for feed readers
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+use chrono::{DateTime, NaiveDate, Utc};
+use itertools::Itertools;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::files;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FeedConfig {
+    #[serde(default = "default_title")]
+    pub title: String,
+    /// Where the feeds are published, e.g. "https://vader.example/"
+    #[serde(default)]
+    pub url: Option<String>,
+    /// Atom file to write
+    #[serde(default)]
+    pub atom: Option<PathBuf>,
+    /// JSON Feed file to write
+    #[serde(default)]
+    pub json: Option<PathBuf>,
+    /// The entries in the feeds
+    #[serde(default = "default_store")]
+    pub store: PathBuf,
+    #[serde(default = "default_max_entries")]
+    pub max_entries: usize,
+}
+
+fn default_title() -> String {
+    "Vädret".to_string()
+}
+
+fn default_store() -> PathBuf {
+    PathBuf::from("feed.toml")
+}
+
+fn default_max_entries() -> usize {
+    50
+}
+
+/// A posted forecast
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Entry {
+    /// The same for every post of the same kind, location and date
+    pub id: String,
+    pub title: String,
+    pub location: String,
+    pub content: String,
+    pub published: DateTime<Utc>,
+    pub updated: DateTime<Utc>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Store {
+    /// Newest first
+    #[serde(default)]
+    entries: Vec<Entry>,
+}
+
+impl FeedConfig {
+    /// Stable id of a post, `kind` telling e.g. the evening and morning posts apart
+    pub fn entry_id(&self, kind: &str, location: &str, date: NaiveDate) -> String {
+        let authority = self
+            .url
+            .as_deref()
+            .and_then(|url| reqwest::Url::parse(url).ok())
+            .and_then(|url| url.host_str().map(str::to_string))
+            .unwrap_or_else(|| "vader-bot".to_string());
+        let location = location.to_lowercase().split_whitespace().join("-");
+        format!("tag:{},{}:{}/{}", authority, date, location, kind)
+    }
+
+    /// Add a post to the feeds, replacing an earlier one with the same id
+    pub fn add(&self, entry: Entry) -> Result<()> {
+        let mut store: Store = files::load_or_default(&self.store)?;
+        let entry = match store.entries.iter().position(|e| e.id == entry.id) {
+            Some(i) => Entry {
+                published: store.entries.remove(i).published,
+                ..entry
+            },
+            None => entry,
+        };
+        store.entries.insert(0, entry);
+        store.entries.truncate(self.max_entries);
+        files::write_atomic(&self.store, &toml::to_string(&store)?)?;
+        if let Some(path) = self.atom.as_ref() {
+            files::write_atomic(path, &self.atom(&store.entries)?)?;
+        }
+        if let Some(path) = self.json.as_ref() {
+            files::write_atomic(path, &self.json_feed(&store.entries)?)?;
+        }
+        Ok(())
+    }
+
+    /// The url of a published feed file
+    fn feed_url(&self, path: &Path) -> Option<String> {
+        let base = reqwest::Url::parse(self.url.as_deref()?).ok()?;
+        let name = path.file_name()?.to_str()?;
+        Some(base.join(name).ok()?.to_string())
+    }
+
+    /// The url of the Atom feed, or of the file if it isn't published
+    fn atom_id(&self) -> String {
+        let path = self.atom.as_deref().unwrap_or(Path::new("feed.atom"));
+        self.feed_url(path)
+            .or_else(|| {
+                let path = std::path::absolute(path).ok()?;
+                Some(reqwest::Url::from_file_path(path).ok()?.to_string())
+            })
+            .unwrap_or_else(|| path.display().to_string())
+    }
+
+    fn atom(&self, entries: &[Entry]) -> Result<String> {
+        let feed = Atom {
+            xmlns: "http://www.w3.org/2005/Atom",
+            id: self.atom_id(),
+            title: &self.title,
+            updated: entries
+                .iter()
+                .map(|e| e.updated)
+                .max()
+                .unwrap_or_else(Utc::now)
+                .to_rfc3339(),
+            author: AtomAuthor { name: &self.title },
+            link: self
+                .url
+                .iter()
+                .map(|url| AtomLink {
+                    rel: "alternate",
+                    href: url.clone(),
+                })
+                .chain(
+                    self.atom
+                        .as_deref()
+                        .and_then(|path| self.feed_url(path))
+                        .map(|href| AtomLink { rel: "self", href }),
+                )
+                .collect(),
+            entry: entries
+                .iter()
+                .map(|e| AtomEntry {
+                    id: &e.id,
+                    title: &e.title,
+                    published: e.published.to_rfc3339(),
+                    updated: e.updated.to_rfc3339(),
+                    category: AtomCategory { term: &e.location },
+                    content: AtomContent {
+                        kind: "text",
+                        text: &e.content,
+                    },
+                })
+                .collect(),
+        };
+        Ok(format!(
+            "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n{}\n",
+            quick_xml::se::to_string_with_root("feed", &feed)?
+        ))
+    }
+
+    fn json_feed(&self, entries: &[Entry]) -> Result<String> {
+        let mut feed = json!({
+            "version": "https://jsonfeed.org/version/1.1",
+            "title": self.title,
+            "language": "sv",
+            "items": entries
+                .iter()
+                .map(|e| json!({
+                    "id": e.id,
+                    "title": e.title,
+                    "content_text": e.content,
+                    "date_published": e.published.to_rfc3339(),
+                    "date_modified": e.updated.to_rfc3339(),
+                    "tags": [e.location],
+                }))
+                .collect::<Vec<_>>(),
+        });
+        if let Some(url) = self.url.as_ref() {
+            feed["home_page_url"] = json!(url);
+        }
+        if let Some(url) = self.json.as_deref().and_then(|path| self.feed_url(path)) {
+            feed["feed_url"] = json!(url);
+        }
+        Ok(serde_json::to_string_pretty(&feed)?)
+    }
+}
+
+#[derive(Serialize)]
+struct Atom<'a> {
+    #[serde(rename = "@xmlns")]
+    xmlns: &'static str,
+    id: String,
+    title: &'a str,
+    updated: String,
+    author: AtomAuthor<'a>,
+    link: Vec<AtomLink>,
+    entry: Vec<AtomEntry<'a>>,
+}
+
+#[derive(Serialize)]
+struct AtomAuthor<'a> {
+    name: &'a str,
+}
+
+#[derive(Serialize)]
+struct AtomLink {
+    #[serde(rename = "@rel")]
+    rel: &'static str,
+    #[serde(rename = "@href")]
+    href: String,
+}
+
+#[derive(Serialize)]
+struct AtomEntry<'a> {
+    id: &'a str,
+    title: &'a str,
+    published: String,
+    updated: String,
+    category: AtomCategory<'a>,
+    content: AtomContent<'a>,
+}
+
+#[derive(Serialize)]
+struct AtomCategory<'a> {
+    #[serde(rename = "@term")]
+    term: &'a str,
+}
+
+#[derive(Serialize)]
+struct AtomContent<'a> {
+    #[serde(rename = "@type")]
+    kind: &'static str,
+    #[serde(rename = "$text")]
+    text: &'a str,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(dir: &Path, url: Option<&str>, max_entries: usize) -> FeedConfig {
+        FeedConfig {
+            title: "Vädret".to_string(),
+            url: url.map(str::to_string),
+            atom: Some(dir.join("feed.atom")),
+            json: Some(dir.join("feed.json")),
+            store: dir.join("feed.toml"),
+            max_entries,
+        }
+    }
+
+    /// A directory of its own for each test, since they run in parallel
+    fn dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("vader_bot-feed-{}-{}", name, std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn entry(config: &FeedConfig, location: &str, day: u32, content: &str, at: &str) -> Entry {
+        let date = NaiveDate::from_ymd_opt(2023, 6, day).unwrap();
+        let at = DateTime::parse_from_rfc3339(at).unwrap().to_utc();
+        Entry {
+            id: config.entry_id("forecast", location, date),
+            title: format!("{} {}", location, date),
+            location: location.to_string(),
+            content: content.to_string(),
+            published: at,
+            updated: at,
+        }
+    }
+
+    fn stored(config: &FeedConfig) -> Vec<Entry> {
+        files::load_or_default::<Store>(&config.store)
+            .unwrap()
+            .entries
+    }
+
+    #[test]
+    fn gives_posts_stable_ids() {
+        let date = NaiveDate::from_ymd_opt(2023, 6, 12).unwrap();
+        let published = config(Path::new("."), Some("https://vader.example/feeds/"), 50);
+        assert_eq!(
+            published.entry_id("forecast", "Lilla Edet", date),
+            "tag:vader.example,2023-06-12:lilla-edet/forecast"
+        );
+        assert_eq!(
+            published.entry_id("morning", "Lund", date),
+            "tag:vader.example,2023-06-12:lund/morning"
+        );
+        let local = config(Path::new("."), None, 50);
+        assert_eq!(
+            local.entry_id("forecast", "Lund", date),
+            "tag:vader-bot,2023-06-12:lund/forecast"
+        );
+    }
+
+    #[test]
+    fn replaces_a_post_keeping_when_it_was_published() {
+        let dir = dir("replace");
+        let config = config(&dir, None, 50);
+        config
+            .add(entry(&config, "Lund", 12, "Sol", "2023-06-11T20:00:00Z"))
+            .unwrap();
+        config
+            .add(entry(&config, "Kiruna", 12, "Snö", "2023-06-11T20:00:01Z"))
+            .unwrap();
+        config
+            .add(entry(&config, "Lund", 12, "Regn", "2023-06-11T21:00:00Z"))
+            .unwrap();
+        let entries = stored(&config);
+        std::fs::remove_dir_all(&dir).unwrap();
+        let summary: Vec<_> = entries
+            .iter()
+            .map(|e| (e.location.as_str(), e.content.as_str()))
+            .collect();
+        assert_eq!(summary, [("Lund", "Regn"), ("Kiruna", "Snö")]);
+        assert_eq!(
+            entries[0].published.to_rfc3339(),
+            "2023-06-11T20:00:00+00:00"
+        );
+        assert_eq!(entries[0].updated.to_rfc3339(), "2023-06-11T21:00:00+00:00");
+    }
+
+    #[test]
+    fn keeps_the_newest_entries() {
+        let dir = dir("truncate");
+        let config = config(&dir, None, 2);
+        for day in 10..14 {
+            config
+                .add(entry(&config, "Lund", day, "Sol", "2023-06-09T20:00:00Z"))
+                .unwrap();
+        }
+        let entries = stored(&config);
+        std::fs::remove_dir_all(&dir).unwrap();
+        let titles: Vec<_> = entries.iter().map(|e| e.title.as_str()).collect();
+        assert_eq!(titles, ["Lund 2023-06-13", "Lund 2023-06-12"]);
+    }
+
+    #[test]
+    fn writes_atom() {
+        let config = config(Path::new("/srv"), Some("https://vader.example/"), 50);
+        let entries = [entry(
+            &config,
+            "Lund",
+            12,
+            "Sol & <moln>",
+            "2023-06-11T20:00:00Z",
+        )];
+        assert_eq!(
+            config.atom(&entries).unwrap(),
+            "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n\
+             <feed xmlns=\"http://www.w3.org/2005/Atom\">\
+             <id>https://vader.example/feed.atom</id>\
+             <title>Vädret</title>\
+             <updated>2023-06-11T20:00:00+00:00</updated>\
+             <author><name>Vädret</name></author>\
+             <link rel=\"alternate\" href=\"https://vader.example/\"/>\
+             <link rel=\"self\" href=\"https://vader.example/feed.atom\"/>\
+             <entry>\
+             <id>tag:vader.example,2023-06-12:lund/forecast</id>\
+             <title>Lund 2023-06-12</title>\
+             <published>2023-06-11T20:00:00+00:00</published>\
+             <updated>2023-06-11T20:00:00+00:00</updated>\
+             <category term=\"Lund\"/>\
+             <content type=\"text\">Sol &amp; &lt;moln&gt;</content>\
+             </entry>\
+             </feed>\n"
+        );
+    }
+
+    #[test]
+    fn writes_json_feed() {
+        let config = config(Path::new("/srv"), Some("https://vader.example/"), 50);
+        let entries = [entry(&config, "Lund", 12, "Sol", "2023-06-11T20:00:00Z")];
+        let feed: serde_json::Value =
+            serde_json::from_str(&config.json_feed(&entries).unwrap()).unwrap();
+        assert_eq!(
+            feed,
+            json!({
+                "version": "https://jsonfeed.org/version/1.1",
+                "title": "Vädret",
+                "language": "sv",
+                "home_page_url": "https://vader.example/",
+                "feed_url": "https://vader.example/feed.json",
+                "items": [{
+                    "id": "tag:vader.example,2023-06-12:lund/forecast",
+                    "title": "Lund 2023-06-12",
+                    "content_text": "Sol",
+                    "date_published": "2023-06-11T20:00:00+00:00",
+                    "date_modified": "2023-06-11T20:00:00+00:00",
+                    "tags": ["Lund"],
+                }],
+            })
+        );
+    }
+}
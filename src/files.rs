@@ -0,0 +1,23 @@
+//! Reading and writing the files the bot keeps its state in
+use std::path::Path;
+
+use anyhow::Result;
+use serde::de::DeserializeOwned;
+
+/// Parse a TOML file, or start from the default if there is none
+pub fn load_or_default<T: DeserializeOwned + Default>(path: &Path) -> Result<T> {
+    match std::fs::read_to_string(path) {
+        Ok(s) => Ok(toml::from_str(&s)?),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(T::default()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Replace a file atomically, so that it's never read half written
+pub fn write_atomic(path: &Path, contents: &str) -> Result<()> {
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    std::fs::write(&tmp, contents)?;
+    std::fs::rename(tmp, path)?;
+    Ok(())
+}
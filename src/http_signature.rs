@@ -0,0 +1,141 @@
+//! HTTP signatures as used between ActivityPub servers, following
+//! draft-cavage-http-signatures with rsa-sha256
+use anyhow::{anyhow, bail, Context, Result};
+use chrono::{DateTime, Duration, Utc};
+use openssl::base64;
+use openssl::hash::MessageDigest;
+use openssl::pkey::{PKey, Private, Public};
+use openssl::sign::{Signer, Verifier};
+
+/// How far the date of a signed request may be from now
+const MAX_CLOCK_SKEW: Duration = Duration::hours(12);
+
+/// Value of the Digest header of a body
+pub fn digest(body: &[u8]) -> String {
+    format!(
+        "SHA-256={}",
+        base64::encode_block(&openssl::sha::sha256(body))
+    )
+}
+
+/// Value of the Date header, e.g. "Sun, 06 Nov 1994 08:49:37 GMT"
+pub fn date(time: DateTime<Utc>) -> String {
+    time.format("%a, %d %b %Y %H:%M:%S GMT").to_string()
+}
+
+/// The string which is signed, from the request line and the named headers
+fn signing_string(
+    method: &str,
+    path: &str,
+    names: &[String],
+    header: impl Fn(&str) -> Option<String>,
+) -> Result<String> {
+    names
+        .iter()
+        .map(|name| match name.as_str() {
+            "(request-target)" => Ok(format!(
+                "(request-target): {} {}",
+                method.to_lowercase(),
+                path
+            )),
+            name => header(name)
+                .map(|value| format!("{}: {}", name, value))
+                .ok_or_else(|| anyhow!("Missing signed header {}", name)),
+        })
+        .collect::<Result<Vec<_>>>()
+        .map(|lines| lines.join("\n"))
+}
+
+/// The Signature header for a request with the given headers, which are all signed
+pub fn sign(
+    key: &PKey<Private>,
+    key_id: &str,
+    method: &str,
+    path: &str,
+    headers: &[(&str, &str)],
+) -> Result<String> {
+    let mut names = vec!["(request-target)".to_string()];
+    names.extend(headers.iter().map(|(name, _)| name.to_lowercase()));
+    let string = signing_string(method, path, &names, |name| {
+        headers
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.to_string())
+    })?;
+    let mut signer = Signer::new(MessageDigest::sha256(), key)?;
+    signer.update(string.as_bytes())?;
+    Ok(format!(
+        "keyId=\"{}\",algorithm=\"rsa-sha256\",headers=\"{}\",signature=\"{}\"",
+        key_id,
+        names.join(" "),
+        base64::encode_block(&signer.sign_to_vec()?)
+    ))
+}
+
+/// A parsed Signature header
+#[derive(Debug, Clone)]
+pub struct Signature {
+    pub key_id: String,
+    headers: Vec<String>,
+    signature: Vec<u8>,
+}
+
+impl Signature {
+    pub fn parse(header: &str) -> Result<Self> {
+        let mut key_id = None;
+        let mut headers = vec!["date".to_string()];
+        let mut signature = None;
+        for part in header.split(',') {
+            let (name, value) = part
+                .split_once('=')
+                .ok_or_else(|| anyhow!("Malformed signature {}", header))?;
+            let value = value.trim().trim_matches('"');
+            match name.trim() {
+                "keyId" => key_id = Some(value.to_string()),
+                "headers" => headers = value.split_whitespace().map(str::to_lowercase).collect(),
+                "signature" => signature = Some(base64::decode_block(value)?),
+                "algorithm" if value != "rsa-sha256" && value != "hs2019" => {
+                    bail!("Unsupported signature algorithm {}", value)
+                }
+                _ => (),
+            }
+        }
+        Ok(Self {
+            key_id: key_id.ok_or_else(|| anyhow!("Signature without keyId"))?,
+            headers,
+            signature: signature.ok_or_else(|| anyhow!("Signature without signature"))?,
+        })
+    }
+
+    /// Check the signature of a request with a body, including its digest and date
+    pub fn verify(
+        &self,
+        key: &PKey<Public>,
+        method: &str,
+        path: &str,
+        body: &[u8],
+        header: impl Fn(&str) -> Option<String>,
+    ) -> Result<()> {
+        for required in ["(request-target)", "host", "date", "digest"] {
+            if !self.headers.iter().any(|h| h == required) {
+                bail!("The signature doesn't cover {}", required);
+            }
+        }
+        if header("digest").as_deref() != Some(digest(body).as_str()) {
+            bail!("The digest doesn't match the body");
+        }
+        let date = header("date").unwrap_or_default();
+        let date = DateTime::parse_from_rfc2822(&date)
+            .with_context(|| format!("Invalid date {}", date))?;
+        if (Utc::now() - date.with_timezone(&Utc)).abs() > MAX_CLOCK_SKEW {
+            bail!("The request is too old or too far in the future");
+        }
+        let string = signing_string(method, path, &self.headers, header)?;
+        let mut verifier = Verifier::new(MessageDigest::sha256(), key)?;
+        verifier.update(string.as_bytes())?;
+        if !verifier.verify(&self.signature)? {
+            bail!("Invalid signature");
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,870 @@
+//! Finding, merging and validating the config files
+//!
+//! The config file is the one given with `--config`, else `$VADER_CONFIG`, else
+//! the first of `./config.toml`, `$XDG_CONFIG_HOME/vader_bot/config.toml` and
+//! `$XDG_CONFIG_DIRS/vader_bot/config.toml` which exists. The `*.toml` files in
+//! `conf.d` next to it are merged on top in alphabetical order, and then the
+//! `VADER_*` environment variables.
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, bail, Result};
+use itertools::Itertools;
+use serde::de::{
+    self, DeserializeSeed, Deserializer, IntoDeserializer, MapAccess, SeqAccess, Visitor,
+};
+use toml::{Table, Value};
+use toml_edit::{ImDocument, Item, TableLike};
+
+use crate::config::{Config, CONFIG_FILE};
+
+/// Directory in the XDG config directories
+const APP_DIR: &str = "vader_bot";
+/// Directory next to the config file with drop-in files
+const DROP_IN_DIR: &str = "conf.d";
+/// Environment variable with the path of the config file
+const CONFIG_ENV: &str = "VADER_CONFIG";
+/// Prefix of the environment variables overriding config values, with `__`
+/// between nested keys, e.g. `VADER_FORECAST__TIME=07:00`
+const ENV_PREFIX: &str = "VADER_";
+
+/// Where a config value was set
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Origin {
+    File {
+        path: PathBuf,
+        line: usize,
+        column: usize,
+    },
+    Env(String),
+}
+
+impl fmt::Display for Origin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Origin::File { path, line, column } => {
+                write!(f, "{}:{}:{}", path.display(), line, column)
+            }
+            Origin::Env(var) => write!(f, "${}", var),
+        }
+    }
+}
+
+/// A problem with the config
+#[derive(Debug, Clone)]
+pub struct Problem {
+    /// Dotted path of the value, e.g. "locations.0.name"
+    pub path: Option<String>,
+    pub origin: Option<Origin>,
+    pub message: String,
+}
+
+impl Problem {
+    pub fn new(message: impl Into<String>) -> Self {
+        Self {
+            path: None,
+            origin: None,
+            message: message.into(),
+        }
+    }
+
+    /// A problem with the value at `path`
+    pub fn at(path: impl Into<String>, message: impl Into<String>) -> Self {
+        Self {
+            path: Some(path.into()),
+            ..Self::new(message)
+        }
+    }
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (&self.origin, &self.path) {
+            (Some(origin), Some(path)) => write!(f, "{}: {}: {}", origin, path, self.message),
+            (Some(origin), None) => write!(f, "{}: {}", origin, self.message),
+            (None, Some(path)) => write!(f, "{}: {}", path, self.message),
+            (None, None) => write!(f, "{}", self.message),
+        }
+    }
+}
+
+/// The files searched for the config, in order
+pub fn search_paths() -> Vec<PathBuf> {
+    let mut paths = vec![PathBuf::from(CONFIG_FILE)];
+    let config_home = std::env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".config")));
+    paths.extend(config_home.map(|dir| dir.join(APP_DIR).join(CONFIG_FILE)));
+    let config_dirs = std::env::var("XDG_CONFIG_DIRS")
+        .ok()
+        .filter(|dirs| !dirs.is_empty())
+        .unwrap_or_else(|| "/etc/xdg".to_string());
+    paths.extend(
+        config_dirs
+            .split(':')
+            .filter(|dir| !dir.is_empty())
+            .map(|dir| Path::new(dir).join(APP_DIR).join(CONFIG_FILE)),
+    );
+    paths
+}
+
+/// The config file to use, `path` if given
+pub fn find(path: Option<&Path>) -> Result<PathBuf> {
+    if let Some(path) = path {
+        return Ok(path.to_path_buf());
+    }
+    if let Some(path) = std::env::var_os(CONFIG_ENV).filter(|path| !path.is_empty()) {
+        return Ok(path.into());
+    }
+    let paths = search_paths();
+    match paths.iter().find(|path| path.exists()) {
+        Some(path) => Ok(path.clone()),
+        None => bail!(
+            "No config file found, looked for {}",
+            paths.iter().map(|path| path.display()).join(", ")
+        ),
+    }
+}
+
+/// The drop-in files for the config file, in the order they are merged
+pub fn drop_ins(config: &Path) -> Result<Vec<PathBuf>> {
+    let dir = config
+        .parent()
+        .unwrap_or_else(|| Path::new(""))
+        .join(DROP_IN_DIR);
+    let entries = match std::fs::read_dir(&dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(anyhow!("Failed to read {}: {}", dir.display(), e)),
+    };
+    let mut files = Vec::new();
+    for entry in entries {
+        let path = entry?.path();
+        if path.extension().is_some_and(|ext| ext == "toml") {
+            files.push(path);
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// Where each value was set, by dotted path
+#[derive(Debug, Default)]
+struct Origins(HashMap<String, Origin>);
+
+impl Origins {
+    /// The origin of the value at `path`, or of the closest parent which has
+    /// one, or of any value under it
+    fn find(&self, path: &str) -> Option<&Origin> {
+        let mut parent = path;
+        loop {
+            if let Some(origin) = self.0.get(parent) {
+                return Some(origin);
+            }
+            match parent.rfind('.') {
+                Some(i) => parent = &parent[..i],
+                None => break,
+            }
+        }
+        let prefix = format!("{}.", path);
+        self.0
+            .iter()
+            .filter(|(key, _)| key.starts_with(&prefix))
+            .min_by_key(|(key, _)| key.as_str())
+            .map(|(_, origin)| origin)
+    }
+}
+
+/// A parsed config file
+struct File {
+    path: PathBuf,
+    text: String,
+    table: Table,
+    /// Span of each key and array element, by dotted path
+    spans: HashMap<String, Range<usize>>,
+}
+
+impl File {
+    fn parse(path: &Path) -> Result<std::result::Result<Self, Problem>> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| anyhow!("Failed to read {}: {}", path.display(), e))?;
+        let document = match ImDocument::parse(text.as_str()) {
+            Ok(document) => document,
+            Err(e) => {
+                return Ok(Err(Problem {
+                    path: None,
+                    origin: Some(origin(path, &text, e.span().unwrap_or(0..0))),
+                    message: e.message().trim().replace('\n', ", "),
+                }))
+            }
+        };
+        let mut spans = HashMap::new();
+        table_spans(document.as_table(), "", &mut spans);
+        let table = toml::from_str(&text)?;
+        Ok(Ok(Self {
+            path: path.to_path_buf(),
+            text,
+            table,
+            spans,
+        }))
+    }
+
+    /// Record where the values under `local` in this file ended up under `merged`
+    fn record(&self, local: &str, merged: &str, origins: &mut Origins) {
+        for (path, span) in &self.spans {
+            let rest = match path.strip_prefix(local) {
+                Some(rest) if rest.is_empty() || rest.starts_with('.') => rest,
+                _ => continue,
+            };
+            origins.0.insert(
+                format!("{}{}", merged, rest),
+                origin(&self.path, &self.text, span.clone()),
+            );
+        }
+    }
+}
+
+fn origin(path: &Path, text: &str, span: Range<usize>) -> Origin {
+    let before = &text[..span.start.min(text.len())];
+    let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+    Origin::File {
+        path: path.to_path_buf(),
+        line: before.matches('\n').count() + 1,
+        column: before[line_start..].chars().count() + 1,
+    }
+}
+
+fn join(path: &str, key: &str) -> String {
+    if path.is_empty() {
+        key.to_string()
+    } else {
+        format!("{}.{}", path, key)
+    }
+}
+
+fn table_spans(table: &dyn TableLike, path: &str, spans: &mut HashMap<String, Range<usize>>) {
+    for (name, item) in table.iter() {
+        let path = join(path, name);
+        if let Some(span) = table.get_key_value(name).and_then(|(key, _)| key.span()) {
+            spans.insert(path.clone(), span);
+        }
+        item_spans(item, &path, spans);
+    }
+}
+
+fn item_spans(item: &Item, path: &str, spans: &mut HashMap<String, Range<usize>>) {
+    if let Some(table) = item.as_table_like() {
+        table_spans(table, path, spans);
+    } else if let Some(tables) = item.as_array_of_tables() {
+        for (i, table) in tables.iter().enumerate() {
+            let path = join(path, &i.to_string());
+            if let Some(span) = table.span() {
+                spans.insert(path.clone(), span);
+            }
+            table_spans(table, &path, spans);
+        }
+    } else if let Some(array) = item.as_array() {
+        for (i, value) in array.iter().enumerate() {
+            let path = join(path, &i.to_string());
+            if let Some(span) = value.span() {
+                spans.insert(path.clone(), span);
+            }
+            if let Some(table) = value.as_inline_table() {
+                table_spans(table, &path, spans);
+            }
+        }
+    }
+}
+
+/// Merge `overlay` into `base`: tables are merged, arrays appended and other
+/// values replaced
+fn merge(
+    base: &mut Table,
+    overlay: Table,
+    merged: &str,
+    local: &str,
+    file: &File,
+    origins: &mut Origins,
+) {
+    for (key, value) in overlay {
+        let merged = join(merged, &key);
+        let local = join(local, &key);
+        match (base.get_mut(&key), value) {
+            (Some(Value::Table(base)), Value::Table(overlay)) => {
+                if let Some(span) = file.spans.get(&local) {
+                    origins
+                        .0
+                        .insert(merged.clone(), origin(&file.path, &file.text, span.clone()));
+                }
+                merge(base, overlay, &merged, &local, file, origins);
+            }
+            (Some(Value::Array(base)), Value::Array(overlay)) => {
+                for (i, value) in overlay.into_iter().enumerate() {
+                    file.record(
+                        &join(&local, &i.to_string()),
+                        &join(&merged, &base.len().to_string()),
+                        origins,
+                    );
+                    base.push(value);
+                }
+            }
+            (_, value) => {
+                file.record(&local, &merged, origins);
+                base.insert(key, value);
+            }
+        }
+    }
+}
+
+/// Parse a value from the environment as a TOML value, e.g. `true`, `15` or
+/// `["Mon", "Tue"]`, for where the config doesn't want a string
+fn env_value(value: &str) -> Option<Value> {
+    toml::from_str::<Table>(&format!("value = {}", value))
+        .ok()
+        .and_then(|mut table| table.remove("value"))
+}
+
+/// Set the value at `path`, creating tables as needed
+fn set(value: &mut Value, path: &[String], new: Value) -> std::result::Result<(), String> {
+    let Some((key, rest)) = path.split_first() else {
+        *value = new;
+        return Ok(());
+    };
+    let next = match value {
+        Value::Table(table) if rest.is_empty() => {
+            table.insert(key.clone(), new);
+            return Ok(());
+        }
+        Value::Table(table) => table
+            .entry(key.clone())
+            .or_insert_with(|| Value::Table(Table::new())),
+        Value::Array(array) => key
+            .parse::<usize>()
+            .ok()
+            .and_then(|i| array.get_mut(i))
+            .ok_or_else(|| format!("there is no element {}", key))?,
+        _ => return Err(format!("can't set `{}` in a {}", key, value.type_str())),
+    };
+    set(next, rest, new)
+}
+
+/// Apply the `VADER_*` environment variables in `vars`, all as strings,
+/// returning the paths they were set at
+fn apply_env(
+    table: &mut Table,
+    vars: impl IntoIterator<Item = (String, String)>,
+    origins: &mut Origins,
+    problems: &mut Vec<Problem>,
+) -> HashSet<String> {
+    let mut from_env = HashSet::new();
+    let mut root = Value::Table(std::mem::take(table));
+    for (var, value) in vars.into_iter().sorted() {
+        let Some(name) = var.strip_prefix(ENV_PREFIX) else {
+            continue;
+        };
+        // The credentials, `VADER_MASTODON_<FIELD>`, are handled by `secrets`
+        if var == CONFIG_ENV || (name.starts_with("MASTODON_") && !name.starts_with("MASTODON__")) {
+            continue;
+        }
+        let path: Vec<String> = name.split("__").map(str::to_lowercase).collect();
+        let origin = Origin::Env(var.clone());
+        if let Err(e) = set(&mut root, &path, Value::String(value)) {
+            problems.push(Problem {
+                path: None,
+                origin: Some(origin),
+                message: e,
+            });
+            continue;
+        }
+        origins.0.insert(path.join("."), origin);
+        from_env.insert(path.join("."));
+    }
+    if let Value::Table(root) = root {
+        *table = root;
+    }
+    from_env
+}
+
+/// The merged config as a deserializer. The values from the environment are
+/// strings, which are parsed as TOML where the config wants something else,
+/// e.g. `VADER_FEED__MAX_ENTRIES=20`, but kept as they are where it wants a
+/// string, e.g. `VADER_ADMIN__TOKEN=123456`.
+struct Input<'a> {
+    value: Value,
+    path: String,
+    from_env: &'a HashSet<String>,
+}
+
+impl<'a> Input<'a> {
+    /// The input with a value from the environment parsed, if it can be
+    fn typed(self) -> Self {
+        match &self.value {
+            Value::String(value) if self.from_env.contains(&self.path) => Self {
+                value: env_value(value).unwrap_or(self.value),
+                ..self
+            },
+            _ => self,
+        }
+    }
+}
+
+macro_rules! typed {
+    ($($method:ident)*) => {
+        $(
+            fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+                self.typed().deserialize_any(visitor)
+            }
+        )*
+    };
+}
+
+impl<'de> Deserializer<'de> for Input<'_> {
+    type Error = toml::de::Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        match self.value {
+            Value::Table(table) => visitor.visit_map(TableInput {
+                entries: table.into_iter(),
+                value: None,
+                path: self.path,
+                from_env: self.from_env,
+            }),
+            Value::Array(array) => visitor.visit_seq(ArrayInput {
+                elements: array.into_iter().enumerate(),
+                path: self.path,
+                from_env: self.from_env,
+            }),
+            value => value.deserialize_any(visitor),
+        }
+    }
+
+    typed! {
+        deserialize_bool deserialize_i8 deserialize_i16 deserialize_i32 deserialize_i64
+        deserialize_u8 deserialize_u16 deserialize_u32 deserialize_u64
+        deserialize_f32 deserialize_f64 deserialize_seq deserialize_map
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        visitor.visit_some(self)
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        self.typed().deserialize_any(visitor)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        self.value.deserialize_enum(name, variants, visitor)
+    }
+
+    serde::forward_to_deserialize_any! {
+        char str string bytes byte_buf unit unit_struct tuple tuple_struct identifier
+        ignored_any
+    }
+}
+
+struct TableInput<'a> {
+    entries: toml::map::IntoIter,
+    value: Option<Input<'a>>,
+    path: String,
+    from_env: &'a HashSet<String>,
+}
+
+impl<'de> MapAccess<'de> for TableInput<'_> {
+    type Error = toml::de::Error;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(
+        &mut self,
+        seed: K,
+    ) -> Result<Option<K::Value>, Self::Error> {
+        let Some((key, value)) = self.entries.next() else {
+            return Ok(None);
+        };
+        self.value = Some(Input {
+            value,
+            path: join(&self.path, &key),
+            from_env: self.from_env,
+        });
+        seed.deserialize(key.into_deserializer()).map(Some)
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(
+        &mut self,
+        seed: V,
+    ) -> Result<V::Value, Self::Error> {
+        match self.value.take() {
+            Some(value) => seed.deserialize(value),
+            None => Err(de::Error::custom("value is missing")),
+        }
+    }
+}
+
+struct ArrayInput<'a> {
+    elements: std::iter::Enumerate<std::vec::IntoIter<Value>>,
+    path: String,
+    from_env: &'a HashSet<String>,
+}
+
+impl<'de> SeqAccess<'de> for ArrayInput<'_> {
+    type Error = toml::de::Error;
+
+    fn next_element_seed<T: DeserializeSeed<'de>>(
+        &mut self,
+        seed: T,
+    ) -> Result<Option<T::Value>, Self::Error> {
+        match self.elements.next() {
+            Some((i, value)) => seed
+                .deserialize(Input {
+                    value,
+                    path: join(&self.path, &i.to_string()),
+                    from_env: self.from_env,
+                })
+                .map(Some),
+            None => Ok(None),
+        }
+    }
+}
+
+/// Dotted path of a deserialization error
+fn error_path(path: &serde_path_to_error::Path) -> String {
+    path.iter()
+        .filter_map(|segment| match segment {
+            serde_path_to_error::Segment::Seq { index } => Some(index.to_string()),
+            serde_path_to_error::Segment::Map { key } => Some(key.clone()),
+            serde_path_to_error::Segment::Enum { variant } => Some(variant.clone()),
+            serde_path_to_error::Segment::Unknown => None,
+        })
+        .join(".")
+}
+
+/// Dotted path of a key which was ignored when deserializing
+fn ignored_path(path: &serde_ignored::Path) -> String {
+    match path {
+        serde_ignored::Path::Root => String::new(),
+        serde_ignored::Path::Seq { parent, index } => {
+            join(&ignored_path(parent), &index.to_string())
+        }
+        serde_ignored::Path::Map { parent, key } => join(&ignored_path(parent), key),
+        serde_ignored::Path::Some { parent }
+        | serde_ignored::Path::NewtypeStruct { parent }
+        | serde_ignored::Path::NewtypeVariant { parent } => ignored_path(parent),
+    }
+}
+
+/// Deserialize `value`, with the paths of the unknown keys
+fn deserialize_input(
+    value: Value,
+    from_env: &HashSet<String>,
+) -> std::result::Result<(Config, Vec<String>), serde_path_to_error::Error<toml::de::Error>> {
+    let input = Input {
+        value,
+        path: String::new(),
+        from_env,
+    };
+    let mut unknown = Vec::new();
+    let mut track = serde_path_to_error::Track::new();
+    let config = serde_ignored::deserialize(
+        serde_path_to_error::Deserializer::new(input, &mut track),
+        |path| unknown.push(ignored_path(&path)),
+    )
+    .map_err(|e| serde_path_to_error::Error::new(track.path(), e))?;
+    Ok((config, unknown))
+}
+
+/// Deserialize the config, checking each top level key on its own so that a
+/// mistake in one section doesn't hide those in the others. Sections which
+/// can't be deserialized are left out of the config.
+fn deserialize(
+    mut table: Table,
+    from_env: &HashSet<String>,
+    problems: &mut Vec<Problem>,
+) -> (Config, Vec<String>) {
+    let mut invalid = Vec::new();
+    for (key, value) in &table {
+        let section = Value::Table(Table::from_iter([(key.clone(), value.clone())]));
+        match deserialize_input(section, from_env) {
+            Ok((_, unknown)) => problems.extend(
+                unknown
+                    .into_iter()
+                    .map(|path| Problem::at(path, "unknown key")),
+            ),
+            Err(e) => {
+                let path = error_path(e.path());
+                let message = e.into_inner().message().trim().to_string();
+                problems.push(Problem::at(
+                    if path.is_empty() { key.clone() } else { path },
+                    message,
+                ));
+                invalid.push(key.clone());
+            }
+        }
+    }
+    for key in &invalid {
+        table.remove(key);
+    }
+    let (config, _) =
+        deserialize_input(Value::Table(table), from_env).expect("All sections are valid");
+    (config, invalid)
+}
+
+/// Load the config from `path` and its drop-ins, with every problem found. The
+/// config is missing if a file can't be parsed, and otherwise leaves out any
+/// invalid sections.
+pub fn load(path: &Path) -> Result<(Option<Config>, Vec<Problem>)> {
+    let mut problems = Vec::new();
+    let mut table = Table::new();
+    let mut origins = Origins::default();
+    for path in std::iter::once(path.to_path_buf()).chain(drop_ins(path)?) {
+        match File::parse(&path)? {
+            Ok(mut file) => {
+                let overlay = std::mem::take(&mut file.table);
+                merge(&mut table, overlay, "", "", &file, &mut origins);
+            }
+            Err(problem) => problems.push(problem),
+        }
+    }
+    // Without a file the rest of the checks would only be confusing
+    if !problems.is_empty() {
+        return Ok((None, problems));
+    }
+    let from_env = apply_env(&mut table, std::env::vars(), &mut origins, &mut problems);
+    let (config, invalid) = deserialize(table, &from_env, &mut problems);
+    let config = match config.clone().with_secrets(path) {
+        Ok(config) => config,
+        Err(e) => {
+            problems.push(Problem::new(format!("{:#}", e)));
+            config
+        }
+    };
+    // Don't complain about what is missing because it was invalid
+    problems.extend(config.problems().into_iter().filter(|problem| {
+        let section = problem
+            .path
+            .as_deref()
+            .and_then(|path| path.split('.').next());
+        !matches!(section, Some(section) if invalid.iter().any(|key| key == section))
+    }));
+    for problem in &mut problems {
+        if problem.origin.is_none() {
+            problem.origin = problem
+                .path
+                .as_deref()
+                .and_then(|path| origins.find(path))
+                .cloned();
+        }
+    }
+    Ok((Some(config), problems))
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{NaiveTime, Weekday};
+
+    use super::*;
+    use crate::config::SECRETS_FILE;
+    use crate::schedule::Schedule;
+
+    #[test]
+    fn reports_invalid_times_at_the_key() {
+        let dir = std::env::temp_dir().join(format!("vader_bot-loader-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(CONFIG_FILE);
+        std::fs::write(
+            &path,
+            "[[locations]]\nname = \"Lund\"\n\n[forecast]\ntime = \"25:00\"\n\n\
+             [[outlooks]]\nkind = \"weekly\"\n  time = \"8:6x\"\n",
+        )
+        .unwrap();
+        let (_, problems) = load(&path).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        let problems: Vec<_> = problems
+            .iter()
+            .filter(|p| p.message.starts_with("invalid time"))
+            .map(|p| {
+                (
+                    p.path.as_deref().unwrap(),
+                    p.origin.as_ref().unwrap().to_string(),
+                )
+            })
+            .collect();
+        let at = |line_column: &str| format!("{}:{}", path.display(), line_column);
+        assert_eq!(
+            problems,
+            vec![("forecast.time", at("5:1")), ("outlooks.0.time", at("9:3")),]
+        );
+    }
+
+    #[test]
+    fn takes_mastodon_settings_without_the_credentials() {
+        let dir = std::env::temp_dir().join(format!("vader_bot-mastodon-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(CONFIG_FILE);
+        std::fs::write(
+            &path,
+            "location = \"Lund\"\n\n[mastodon]\nmax_chars = 1000\n",
+        )
+        .unwrap();
+        std::fs::write(
+            dir.join(SECRETS_FILE),
+            "[mastodon]\nbase = \"https://mastodon.example\"\nclient_id = \"id\"\n\
+             client_secret = \"secret\"\nredirect = \"urn:ietf:wg:oauth:2.0:oob\"\n\
+             token = \"token\"\n",
+        )
+        .unwrap();
+        let (config, problems) = load(&path).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(problems.is_empty(), "{:?}", problems);
+        let mastodon = config.unwrap().mastodon.unwrap();
+        assert_eq!(mastodon.credentials.as_ref().unwrap().token, "token");
+        assert_eq!(mastodon.formatting().max_chars, Some(1000));
+    }
+
+    /// The config from `toml` with the environment variables in `vars`
+    fn with_env(toml: &str, vars: &[(&str, &str)]) -> (Config, Vec<String>, Vec<Problem>) {
+        let mut table: Table = toml::from_str(toml).unwrap();
+        let mut problems = Vec::new();
+        let vars = vars
+            .iter()
+            .map(|(var, value)| (var.to_string(), value.to_string()));
+        let from_env = apply_env(&mut table, vars, &mut Origins::default(), &mut problems);
+        let (config, invalid) = deserialize(table, &from_env, &mut problems);
+        (config, invalid, problems)
+    }
+
+    #[test]
+    fn converts_environment_values_to_the_type_of_the_field() {
+        let (config, invalid, problems) = with_env(
+            "[[locations]]\nname = \"Lund\"\n\
+             [[publishers]]\nname = \"t\"\nkind = \"telegram\"\nbot_token = \"x\"\nchat_id = \"@c\"\n",
+            &[
+                ("VADER_ADMIN__TOKEN", "123456"),
+                ("VADER_FORECAST__TIME", "07:00"),
+                ("VADER_FORECAST__WEEKDAYS", "[\"Sat\", \"Sun\"]"),
+                ("VADER_LOCATIONS__0__NAME", "Malmö"),
+                ("VADER_LOCATIONS__0__LATITUDE", "55.6"),
+                ("VADER_LOCATIONS__0__AIR_QUALITY", "true"),
+                ("VADER_PUBLISHERS__0__CHAT_ID", "-100123"),
+                ("VADER_PUBLISHERS__0__MAX_CHARS", "1000"),
+            ],
+        );
+        assert!(problems.is_empty(), "{:?}", problems);
+        assert!(invalid.is_empty());
+        assert_eq!(config.admin.unwrap().token.as_deref(), Some("123456"));
+        assert_eq!(
+            config.forecast.schedule,
+            Schedule {
+                time: NaiveTime::from_hms_opt(7, 0, 0).unwrap(),
+                weekdays: vec![Weekday::Sat, Weekday::Sun],
+            }
+        );
+        let location = &config.locations[0];
+        assert_eq!(location.name, "Malmö");
+        assert_eq!(location.latitude, Some(55.6));
+        assert!(location.air_quality);
+        let publisher = &config.publishers[0];
+        assert!(matches!(
+            &publisher.kind,
+            crate::publisher::PublisherKind::Telegram { chat_id, .. } if chat_id == "-100123"
+        ));
+        assert_eq!(publisher.max_chars, Some(1000));
+    }
+
+    #[test]
+    fn reports_environment_values_of_the_wrong_type() {
+        let (_, invalid, problems) = with_env(
+            "location = \"Lund\"\n",
+            &[("VADER_FEED__MAX_ENTRIES", "many")],
+        );
+        assert_eq!(invalid, ["feed"]);
+        assert_eq!(problems[0].path.as_deref(), Some("feed.max_entries"));
+
+        // Values from the files keep their types
+        let (_, invalid, problems) =
+            with_env("location = \"Lund\"\n[admin]\ntoken = 123456\n", &[]);
+        assert_eq!(invalid, ["admin"]);
+        assert_eq!(problems[0].path.as_deref(), Some("admin.token"));
+    }
+
+    #[test]
+    fn takes_mastodon_settings_but_not_the_credentials_from_the_environment() {
+        let (config, _, problems) = with_env(
+            "location = \"Lund\"\n",
+            &[
+                ("VADER_MASTODON__MAX_CHARS", "1000"),
+                ("VADER_MASTODON_TOKEN", "token"),
+            ],
+        );
+        assert!(problems.is_empty(), "{:?}", problems);
+        let mastodon = config.mastodon.unwrap();
+        assert!(mastodon.credentials.is_none());
+        assert_eq!(mastodon.max_chars, Some(1000));
+    }
+
+    #[test]
+    fn reports_unknown_keys() {
+        let (_, invalid, problems) = with_env(
+            "location = \"Lund\"\nlocaton = \"Malmö\"\n\
+             [mastodon]\nmax_char = 1000\n\
+             [[publishers]]\nname = \"w\"\nkind = \"webhook\"\nurl = \"https://example.com\"\nhedaers = {}\n\
+             [[outlooks]]\ntime = \"18:00\"\ndys = 5\n",
+            &[("VADER_ADMIN__TOKN", "x")],
+        );
+        assert!(invalid.is_empty());
+        let unknown: Vec<_> = problems
+            .iter()
+            .filter(|p| p.message == "unknown key")
+            .map(|p| p.path.as_deref().unwrap())
+            .sorted()
+            .collect();
+        assert_eq!(
+            unknown,
+            [
+                "admin.tokn",
+                "locaton",
+                "mastodon.max_char",
+                "outlooks.0.dys",
+                "publishers.0.hedaers"
+            ]
+        );
+    }
+
+    #[test]
+    fn reports_a_broken_secrets_file_with_the_other_problems() {
+        let dir = std::env::temp_dir().join(format!("vader_bot-secrets-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(CONFIG_FILE);
+        std::fs::write(&path, "[[locations]]\nname = \"\"\n").unwrap();
+        std::fs::write(dir.join(SECRETS_FILE), "[mastodon\n").unwrap();
+        let (config, problems) = load(&path).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(config.is_some());
+        let messages: Vec<_> = problems.iter().map(|p| p.to_string()).collect();
+        assert_eq!(messages.len(), 2, "{:?}", messages);
+        assert!(messages[0].contains(SECRETS_FILE), "{:?}", messages);
+        assert!(
+            messages[1].ends_with("locations.0: Location with an empty name"),
+            "{:?}",
+            messages
+        );
+    }
+}
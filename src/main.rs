@@ -1,94 +1,1080 @@
 //! A bot which posts the weather forcast for tomorrow
 
 use anyhow::{bail, Result};
-use chrono::Local;
-use elefren::{status_builder::Visibility, Language, Mastodon, MastodonClient, StatusBuilder};
-use tracing::{debug, info};
+use chrono::{Duration, Local, NaiveDate};
+use itertools::Itertools;
+use smhi::Warning;
+use std::collections::BTreeSet;
+use std::path::Path;
+use std::sync::Arc;
+use tokio::sync::{mpsc, watch};
+use tokio::task::JoinHandle;
+use tracing::{debug, error, info, warn};
 
+mod activitypub;
+mod admin;
+mod air_quality;
+mod apparent;
+mod astronomy;
+mod bluesky;
+mod chart;
+mod cli;
+mod climatology;
 mod config;
+mod feed;
+mod files;
+mod http_signature;
+mod loader;
+mod marine;
+mod mastodon;
+mod matrix;
+mod metrics;
+mod open_meteo;
+mod outlook;
+mod pollen;
+mod publisher;
+mod register;
+mod reload;
+mod schedule;
+mod season;
+mod secrets;
+mod shutdown;
+mod smhi;
+mod state;
+mod status;
+#[cfg(test)]
+mod stub;
+mod telegram;
+mod template;
 mod vader;
-use config::Config;
-use vader::Wttr;
-
-// TODO: Make location configurable
-async fn fetch_tomorrows_weather(location: &str) -> Result<String> {
-    reqwest::Client::new()
-        .get(format!("https://wttr.in/{}?format=j1", location))
-        .header("Accept-Language", "sv-SE")
-        .send()
-        .await?
-        .json::<Wttr>()
-        .await
-        .map_err(|e| e.into())
-        .map(|wttr| {
-            debug!("Fetched weather for: {:?}", wttr.weather[1].date);
-            wttr
+mod webhook;
+mod wind;
+use activitypub::ActivityPub;
+use admin::PostNow;
+use clap::Parser;
+use cli::{Cli, Command, Format};
+use climatology::{DayRecord, Normals};
+use config::{Config, Location, PostConfig, WarningsConfig, WarningsMode};
+use feed::FeedConfig;
+use loader::Problem;
+use outlook::{OutlookConfig, OutlookKind};
+use publisher::Publishers;
+use reload::Reloads;
+use schedule::Schedule;
+use shutdown::{Shutdown, Signal};
+use state::{LocationState, State};
+use vader::{Day, Weather, Wttr};
+
+async fn fetch_weather(location: &str) -> Result<Wttr> {
+    let json = fetch_weather_json(location).await?;
+    let wttr: Wttr =
+        serde_path_to_error::deserialize(&mut serde_json::Deserializer::from_str(&json))
+            .inspect_err(|e| {
+                // Without the indices, e.g. "weather.hourly.weatherDesc.value"
+                let field = e
+                    .path()
+                    .iter()
+                    .filter(|segment| !matches!(segment, serde_path_to_error::Segment::Seq { .. }))
+                    .join(".");
+                metrics::parse_failed(&field);
+            })?;
+    for field in wttr.unparsable_fields() {
+        metrics::parse_failed(field);
+    }
+    status::fetched(location);
+    Ok(wttr)
+}
+
+/// Fetch the weather as is, e.g. to save it for later
+async fn fetch_weather_json(location: &str) -> Result<String> {
+    metrics::fetch("wttr", async {
+        Ok(metrics::CLIENT
+            .get(format!("https://wttr.in/{}?format=j1", location))
+            .header("Accept-Language", "sv-SE")
+            .send()
+            .await?
+            .error_for_status()?
+            .text()
+            .await?)
+    })
+    .await
+}
+
+/// Default template for tomorrow's forecast. Warnings come first after the
+/// forecast, since posts are shortened from the end.
+const FORECAST_TEMPLATE: &str =
+    "{forecast}\n{warnings}\n{climate}\n{marine}\n{astronomy}\n{pollen}\n{air_quality}";
+/// Default template for today's forecast
+const MORNING_TEMPLATE: &str =
+    "God morgon {location}! {now}\n{forecast}\n{warnings}\n{climate}\n{marine}\n{astronomy}\n{pollen}\n{air_quality}";
+
+/// Active warnings as lines to append to a post, empty unless appending is enabled
+async fn warning_lines(
+    location: &Location,
+    wttr: &Wttr,
+    warnings: Option<&WarningsConfig>,
+) -> String {
+    let Some(warnings) = warnings.filter(|w| w.mode == WarningsMode::Append) else {
+        return String::new();
+    };
+    match fetch_warnings(location, wttr, warnings).await {
+        Ok(warnings) => warnings.iter().map(|w| w.to_string()).join("\n"),
+        Err(e) => {
+            warn!("Failed to fetch warnings for {}: {}", location.name, e);
+            String::new()
+        }
+    }
+}
+
+/// Pollen levels worth mentioning, empty out of season
+async fn pollen_line(location: &Location, wttr: &Wttr, date: NaiveDate, config: &Config) -> String {
+    let Some(pollen) = config.pollen.as_ref().filter(|p| p.in_season(date)) else {
+        return String::new();
+    };
+    let levels = match coordinates(location, wttr) {
+        Ok((lat, lon)) => pollen::fetch(pollen, lat, lon, date).await,
+        Err(e) => Err(e),
+    };
+    match levels {
+        Ok(levels) => pollen::report(&levels).unwrap_or_default(),
+        Err(e) => {
+            warn!("Failed to fetch pollen for {}: {}", location.name, e);
+            String::new()
+        }
+    }
+}
+
+/// Advice if the air quality is poor, empty unless enabled for the location
+async fn air_quality_line(location: &Location, wttr: &Wttr, date: NaiveDate) -> String {
+    if !location.air_quality {
+        return String::new();
+    }
+    let concentrations = match coordinates(location, wttr) {
+        Ok((lat, lon)) => air_quality::fetch(lat, lon, date).await,
+        Err(e) => Err(e),
+    };
+    match concentrations {
+        Ok(concentrations) => air_quality::from_concentrations(&concentrations)
+            .and_then(|aq| air_quality::report(&aq))
+            .unwrap_or_default(),
+        Err(e) => {
+            warn!("Failed to fetch air quality for {}: {}", location.name, e);
+            String::new()
+        }
+    }
+}
+
+/// Water temperature at the coast, empty out of season
+async fn marine_line(location: &Location, date: NaiveDate) -> String {
+    let Some(marine) = location.marine.as_ref().filter(|m| m.in_season(date)) else {
+        return String::new();
+    };
+    match marine::fetch(marine, date).await {
+        Ok(marine) => marine.map(|m| marine::report(&m)).unwrap_or_default(),
+        Err(e) => {
+            warn!("Failed to fetch marine data for {}: {}", location.name, e);
+            String::new()
+        }
+    }
+}
+
+/// Comparisons with the normal and earlier years
+fn climate_lines(
+    location: &Location,
+    weather: &Weather,
+    day: Day,
+    state: &LocationState,
+) -> String {
+    let Some((date, record)) = DayRecord::from_weather(weather) else {
+        return String::new();
+    };
+    let mut lines = Vec::new();
+    if let Some(path) = location.normals.as_ref() {
+        match Normals::load(path) {
+            Ok(normals) => lines.extend(
+                normals
+                    .get(date)
+                    .and_then(|normal| climatology::compare_normal(normal, &record, day)),
+            ),
+            Err(e) => warn!("Failed to load normals for {}: {}", location.name, e),
+        }
+    }
+    lines.extend(climatology::compare_history(&state.history, date, &record));
+    lines.join("\n")
+}
+
+/// Template values shared by the forecasts for the day
+async fn sections(
+    location: &Location,
+    wttr: &Wttr,
+    day: Day,
+    config: &Config,
+    state: &LocationState,
+) -> Result<Vec<(&'static str, String)>> {
+    let weather = wttr.day(day)?;
+    debug!("Fetched weather for: {:?}", weather.date);
+    let mut sections = vec![
+        ("location", location.name.clone()),
+        ("climate", climate_lines(location, weather, day, state)),
+    ];
+    if let Ok(date) = NaiveDate::parse_from_str(&weather.date, "%Y-%m-%d") {
+        let previous = day
+            .index()
+            .checked_sub(1)
+            .and_then(|previous| wttr.weather.get(previous)?.astronomy.first());
+        sections.push((
+            "astronomy",
+            astronomy::section(
+                date,
+                weather.astronomy.first(),
+                previous,
+                coordinates(location, wttr).ok(),
+                location.time_zone(),
+            ),
+        ));
+        sections.push(("marine", marine_line(location, date).await));
+        sections.push(("pollen", pollen_line(location, wttr, date, config).await));
+        sections.push(("air_quality", air_quality_line(location, wttr, date).await));
+    }
+    sections.push((
+        "warnings",
+        warning_lines(location, wttr, config.warnings.as_ref()).await,
+    ));
+    Ok(sections)
+}
+
+fn render(template: &str, sections: &[(&str, String)]) -> String {
+    let values: Vec<(&str, &str)> = sections
+        .iter()
+        .map(|(name, value)| (*name, value.as_str()))
+        .collect();
+    template::render(template, &values)
+}
+
+async fn tomorrows_weather(
+    location: &Location,
+    wttr: &Wttr,
+    config: &Config,
+    state: &LocationState,
+) -> Result<String> {
+    let mut sections = sections(location, wttr, Day::Tomorrow, config, state).await?;
+    sections.push(("forecast", wttr.day(Day::Tomorrow)?.to_string()));
+    let template = config
+        .forecast
+        .template
+        .as_deref()
+        .unwrap_or(FORECAST_TEMPLATE);
+    Ok(render(template, &sections))
+}
+
+async fn todays_weather(
+    location: &Location,
+    wttr: &Wttr,
+    config: &Config,
+    morning: &PostConfig,
+    state: &LocationState,
+) -> Result<String> {
+    let now = wttr
+        .current_condition
+        .first()
+        .map(|c| c.describe())
+        .ok_or_else(|| anyhow::anyhow!("No current condition for {}", location.name))?;
+    let mut sections = sections(location, wttr, Day::Today, config, state).await?;
+    sections.push(("now", now));
+    sections.push(("forecast", wttr.day(Day::Today)?.summary(Day::Today)));
+    let template = morning.template.as_deref().unwrap_or(MORNING_TEMPLATE);
+    Ok(render(template, &sections))
+}
+
+/// Outlook for the coming days
+async fn outlook(location: &Location, wttr: &Wttr, config: &OutlookConfig) -> Result<String> {
+    let (title, days) = match config.kind {
+        OutlookKind::ThreeDay => (
+            format!("De kommande dagarna i {}:", location.name),
+            outlook::from_wttr(wttr, location.today()),
+        ),
+        OutlookKind::Weekly => {
+            let (lat, lon) = coordinates(location, wttr)?;
+            let today = location.today();
+            let days =
+                open_meteo::fetch_daily(lat, lon, (config.days + 1).min(open_meteo::MAX_DAYS))
+                    .await?
+                    .outlook()
+                    .into_iter()
+                    .filter(|day| day.date > today)
+                    .take(config.days.into())
+                    .collect::<Vec<_>>();
+            (
+                format!(
+                    "Vädret de kommande {} dagarna i {}:",
+                    days.len(),
+                    location.name
+                ),
+                days,
+            )
+        }
+    };
+    if days.is_empty() {
+        bail!("No days in outlook for {}", location.name);
+    }
+    Ok(outlook::render(&title, &days))
+}
+
+/// Coordinates of a location, from the config or as reported by wttr.in
+fn coordinates(location: &Location, wttr: &Wttr) -> Result<(f64, f64)> {
+    let area = wttr.nearest_area.first();
+    let lat = match (location.latitude, area) {
+        (Some(lat), _) => lat,
+        (None, Some(area)) => area.latitude.parse()?,
+        (None, None) => bail!("No latitude for {}", location.name),
+    };
+    let lon = match (location.longitude, area) {
+        (Some(lon), _) => lon,
+        (None, Some(area)) => area.longitude.parse()?,
+        (None, None) => bail!("No longitude for {}", location.name),
+    };
+    Ok((lat, lon))
+}
+
+/// Active warnings covering the location
+async fn fetch_warnings(
+    location: &Location,
+    wttr: &Wttr,
+    config: &WarningsConfig,
+) -> Result<Vec<Warning>> {
+    let (lat, lon) = coordinates(location, wttr)?;
+    fetch_warnings_at(lat, lon, config).await
+}
+
+/// Active warnings covering a point
+async fn fetch_warnings_at(lat: f64, lon: f64, config: &WarningsConfig) -> Result<Vec<Warning>> {
+    let alerts = smhi::fetch_alerts(&config.url).await?;
+    Ok(smhi::warnings_for(
+        &alerts,
+        lat,
+        lon,
+        Local::now().fixed_offset(),
+    ))
+}
+
+/// The warnings posted for a location which are still active, sent to the
+/// scheduling loop to be saved in the state
+type PostedWarnings = (String, BTreeSet<String>);
+
+/// Poll for warnings and post new ones as they are issued, starting from the
+/// ones `posted` before, until told to `stop`
+async fn post_new_warnings(
+    publishers: Publishers,
+    location: Location,
+    config: WarningsConfig,
+    mut posted: BTreeSet<String>,
+    updates: mpsc::UnboundedSender<PostedWarnings>,
+    mut stop: watch::Receiver<bool>,
+) {
+    let mut interval = tokio::time::interval(std::time::Duration::from_secs(
+        config.poll_interval_minutes * 60,
+    ));
+    // From the config, or else looked up on wttr.in once
+    let mut at = location.latitude.zip(location.longitude);
+    loop {
+        tokio::select! {
+            _ = interval.tick() => (),
+            _ = stop.wait_for(|&stop| stop) => return,
+        }
+        let (lat, lon) = match at {
+            Some(at) => at,
+            None => match fetch_weather(&location.name)
+                .await
+                .and_then(|wttr| coordinates(&location, &wttr))
+            {
+                Ok(found) => *at.insert(found),
+                Err(e) => {
+                    warn!("Failed to find the coordinates of {}: {}", location.name, e);
+                    continue;
+                }
+            },
+        };
+        let warnings = match fetch_warnings_at(lat, lon, &config).await {
+            Ok(warnings) => warnings,
+            Err(e) => {
+                warn!("Failed to fetch warnings for {}: {}", location.name, e);
+                continue;
+            }
+        };
+        // Forget the warnings which have expired or been cancelled
+        let mut still_posted: BTreeSet<String> = warnings
+            .iter()
+            .map(|w| w.identifier.clone())
+            .filter(|identifier| posted.contains(identifier))
+            .collect();
+        for warning in warnings {
+            if still_posted.contains(&warning.identifier) {
+                continue;
+            }
+            info!("New warning for {}: {}", location.name, warning.identifier);
+            let status = format!("{}: {}", location.name, warning);
+            match publishers.publish(&location, &status).await {
+                Ok(()) => {
+                    still_posted.insert(warning.identifier);
+                }
+                // Tried again at the next poll
+                Err(e) => error!("Failed to post warning: {}", e),
+            }
+        }
+        if still_posted != posted {
+            posted = still_posted;
+            if updates
+                .send((location.name.clone(), posted.clone()))
+                .is_err()
+            {
+                return;
+            }
+        }
+    }
+}
+
+/// Something to post on a schedule
+enum Job<'a> {
+    /// Tomorrow's forecast
+    Forecast,
+    /// Today's forecast
+    Morning(&'a PostConfig),
+    Outlook(&'a OutlookConfig),
+}
+
+impl Job<'_> {
+    fn schedule<'a>(&'a self, config: &'a Config) -> &'a Schedule {
+        match self {
+            Job::Forecast => &config.forecast.schedule,
+            Job::Morning(morning) => &morning.schedule,
+            Job::Outlook(outlook) => &outlook.schedule,
+        }
+    }
+
+    /// The post for a location, without posting it
+    async fn render(
+        &self,
+        config: &Config,
+        location: &Location,
+        wttr: &Wttr,
+        state: &LocationState,
+    ) -> Result<String> {
+        match self {
+            Job::Forecast => tomorrows_weather(location, wttr, config, state).await,
+            Job::Morning(morning) => todays_weather(location, wttr, config, morning, state).await,
+            Job::Outlook(outlook_config) => outlook(location, wttr, outlook_config).await,
+        }
+    }
+
+    /// Name telling the posts of the job apart in the feed
+    fn kind(&self) -> String {
+        match self {
+            Job::Forecast => "forecast".to_string(),
+            Job::Morning(_) => "morning".to_string(),
+            Job::Outlook(outlook) => match outlook.kind {
+                OutlookKind::ThreeDay => "outlook-three-day".to_string(),
+                OutlookKind::Weekly => format!("outlook-{}-days", outlook.days),
+            },
+        }
+    }
+
+    fn add_to_feed(
+        &self,
+        feed: &FeedConfig,
+        location: &Location,
+        wttr: &Wttr,
+        post: &str,
+    ) -> Result<()> {
+        let day = wttr.day(match self {
+            Job::Forecast => Day::Tomorrow,
+            Job::Morning(_) | Job::Outlook(_) => Day::Today,
+        })?;
+        let date: NaiveDate = day.date.parse()?;
+        let title = match self {
+            Job::Forecast => format!("Prognos för {} {}", location.name, date),
+            Job::Morning(_) => format!("Dagens väder i {} {}", location.name, date),
+            Job::Outlook(_) => format!("Utsikter för {} från {}", location.name, date),
+        };
+        let now = chrono::Utc::now();
+        feed.add(feed::Entry {
+            id: feed.entry_id(&self.kind(), &location.name, date),
+            title,
+            location: location.name.clone(),
+            content: post.to_string(),
+            published: now,
+            updated: now,
         })
-        .map(|wttr| wttr.weather[1].to_string())
-}
-
-async fn wait_unil_8pm() -> Result<()> {
-    let current_time = Local::now();
-    debug!("The current time is: {}", current_time);
-    #[allow(deprecated)]
-    let today_eight_pm = Local::now()
-        .date() // Deprecated, but we'll use it since we want to keep the timezone
-        .and_hms_opt(20, 0, 0)
-        .ok_or_else(|| anyhow::anyhow!("Failed to create today's 8 PM time"))?;
-
-    // We've already passed 8 PM today
-    if current_time > today_eight_pm {
-        debug!("It's already past 8 PM, waiting until tomorrow");
-        tokio::time::sleep(std::time::Duration::from_secs(60 * 60 * 12)).await
-    }
-    let duration_until_eight_pm = today_eight_pm
-        .signed_duration_since(current_time)
-        .to_std()?;
-    // Wait until 8 PM
-    debug!("Waiting for {} seconds", duration_until_eight_pm.as_secs());
-    tokio::time::sleep(duration_until_eight_pm).await;
+    }
+
+    async fn run(
+        &self,
+        publishers: &Publishers,
+        config: &Config,
+        location: &Location,
+        state: &mut State,
+    ) -> Result<()> {
+        let result = self.post(publishers, config, location, state).await;
+        status::posted(&location.name, self.kind(), &result);
+        result
+    }
+
+    async fn post(
+        &self,
+        publishers: &Publishers,
+        config: &Config,
+        location: &Location,
+        state: &mut State,
+    ) -> Result<()> {
+        let wttr = fetch_weather(&location.name).await?;
+        let state = state.location(&location.name);
+        let post = self.render(config, location, &wttr, state).await?;
+        publishers.publish(location, &post).await?;
+        if let Some(feed) = config.feed.as_ref() {
+            if let Err(e) = self.add_to_feed(feed, location, &wttr, &post) {
+                error!(
+                    "Failed to add the post for {} to the feed: {}",
+                    location.name, e
+                );
+            }
+        }
+        if let Job::Forecast = self {
+            let (today, tomorrow) = (wttr.day(Day::Today)?, wttr.day(Day::Tomorrow)?);
+            if let Some((date, day)) = DayRecord::from_weather(tomorrow) {
+                state.history.insert(date, day);
+            }
+            // The forecast is already out, so a failed notice doesn't fail the job
+            for notice in season::notices(location, today, tomorrow, state) {
+                match publishers.publish(location, &notice.text).await {
+                    Ok(()) => notice.posted(state),
+                    Err(e) => error!("Failed to post the notice for {}: {}", location.name, e),
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+async fn login(config: &Config) -> Result<Publishers> {
+    Publishers::connect(config).await
+}
+
+/// The configured location with the name, or an unconfigured one
+fn find_location(config: &Config, name: &str) -> Location {
+    config
+        .locations()
+        .into_iter()
+        .find(|l| l.name.eq_ignore_ascii_case(name))
+        .unwrap_or_else(|| Location::new(name))
+}
+
+/// The job posting the forecast for the day
+fn forecast_job(config: &Config, day: Day) -> Job<'_> {
+    match (day, config.morning.as_ref()) {
+        (Day::Tomorrow, _) => Job::Forecast,
+        (Day::Today, Some(morning)) => Job::Morning(morning),
+        (Day::Today, None) => Job::Morning(&DEFAULT_MORNING),
+    }
+}
+
+/// Used for today's forecast when there's no morning post configured
+static DEFAULT_MORNING: std::sync::LazyLock<PostConfig> = std::sync::LazyLock::new(|| PostConfig {
+    schedule: Schedule::daily(7, 0),
+    template: None,
+});
+
+/// The post for the day, without posting it
+async fn render_post(config: &Config, location: &Location, day: Day) -> Result<String> {
+    let mut state = State::load()?;
+    let wttr = fetch_weather(&location.name).await?;
+    forecast_job(config, day)
+        .render(config, location, &wttr, state.location(&location.name))
+        .await
+}
+
+async fn preview(config: &Config, location: &str, day: Day) -> Result<()> {
+    let location = find_location(config, location);
+    println!("{}", render_post(config, &location, day).await?);
+    Ok(())
+}
+
+async fn post_now(config: &Config, location: Option<&str>, day: Day) -> Result<()> {
+    let publishers = login(config).await?;
+    let mut state = State::load()?;
+    let locations = match location {
+        Some(name) => vec![find_location(config, name)],
+        None => config.locations(),
+    };
+    let job = forecast_job(config, day);
+    for location in &locations {
+        job.run(&publishers, config, location, &mut state).await?;
+    }
+    state.save()
+}
+
+/// Print the post for a saved forecast, using the config if there is one
+async fn render_saved(
+    config: Option<&Path>,
+    input: &Path,
+    format: Format,
+    location: Option<&str>,
+    day: Day,
+) -> Result<()> {
+    let json = std::fs::read_to_string(input)?;
+    let config = match loader::find(config) {
+        Ok(path) => Config::load(Some(&path))?,
+        Err(_) => Config::default(),
+    }
+    .offline();
+    match format {
+        Format::Wttr => {
+            let wttr: Wttr = serde_json::from_str(&json)?;
+            let name = match location {
+                Some(name) => name.to_string(),
+                None => wttr
+                    .nearest_area
+                    .first()
+                    .and_then(|area| area.area_name.first())
+                    .map(|name| name.value.clone())
+                    .ok_or_else(|| anyhow::anyhow!("No location in the forecast"))?,
+            };
+            let location = find_location(&config, &name);
+            let weather = wttr.day(day)?;
+            let mut state = State::load()?;
+            let post = forecast_job(&config, day)
+                .render(&config, &location, &wttr, state.location(&location.name))
+                .await?;
+            println!("{}\n", post);
+            println!("Alt-text: {}\n", chart::alt_text(weather));
+            println!("{}", chart::temperature_chart(weather));
+        }
+        Format::OpenMeteo => {
+            let forecast: open_meteo::Forecast = serde_json::from_str(&json)?;
+            let title = format!("Vädret i {}:", location.unwrap_or("okänd plats"));
+            println!("{}", outlook::render(&title, &forecast.outlook()));
+        }
+    }
     Ok(())
 }
 
-/// Post a toot
-fn toot(masto: &Mastodon, status_msg: String) -> Result<()> {
-    debug!("Posting new weather update");
-    let status = StatusBuilder::new()
-        .status(status_msg)
-        .visibility(Visibility::Unlisted)
-        .language(Language::Swe)
-        .content_type("text/html")
-        .build();
-    let status = match status {
-        Ok(status) => status,
-        Err(e) => bail!("Error building status: {}", e),
+/// Print or save a forecast as is
+async fn fetch(location: &str, format: Format, save: Option<&Path>) -> Result<()> {
+    let json = match format {
+        Format::Wttr => fetch_weather_json(location).await?,
+        Format::OpenMeteo => {
+            let location = Location::new(location);
+            let wttr = fetch_weather(&location.name).await?;
+            let (lat, lon) = coordinates(&location, &wttr)?;
+            open_meteo::fetch_daily_json(lat, lon, 7).await?
+        }
+    };
+    match save {
+        Some(path) => {
+            std::fs::write(path, json)?;
+            info!("Saved the forecast to {}", path.display());
+        }
+        None => println!("{}", json),
+    }
+    Ok(())
+}
+
+/// Ask to follow an actor. The Accept arrives at the running bot's inbox.
+async fn follow(config: &Config, actor: &str) -> Result<()> {
+    let Some(activitypub) = config.activitypub.as_ref() else {
+        bail!("ActivityPub isn't enabled, add [activitypub] to the config");
+    };
+    ActivityPub::new(activitypub)?.follow(actor).await?;
+    info!("Asked to follow {}", actor);
+    Ok(())
+}
+
+async fn check_config(path: Option<&Path>) -> Result<()> {
+    let path = loader::find(path)?;
+    let (config, mut problems) = loader::load(&path)?;
+    if let Some(config) = config.as_ref() {
+        if let Err(e) = login(config).await {
+            problems.push(Problem::new(e.to_string()));
+        }
+    }
+    if !problems.is_empty() {
+        for problem in &problems {
+            eprintln!("{}", problem);
+        }
+        bail!("Found {} problems in the config", problems.len());
+    }
+    println!("The config {} is valid", path.display());
+    Ok(())
+}
+
+/// The scheduled jobs of the config
+fn jobs(config: &Config) -> Vec<Job<'_>> {
+    let mut jobs = vec![Job::Forecast];
+    jobs.extend(config.morning.iter().map(Job::Morning));
+    jobs.extend(config.outlooks.iter().map(Job::Outlook));
+    jobs
+}
+
+/// The tasks posting warnings as they are issued
+struct WarningTasks {
+    stop: watch::Sender<bool>,
+    tasks: Vec<JoinHandle<()>>,
+}
+
+impl WarningTasks {
+    fn spawn(
+        publishers: &Publishers,
+        config: &Config,
+        state: &mut State,
+        updates: &mpsc::UnboundedSender<PostedWarnings>,
+    ) -> Self {
+        let stop = watch::Sender::new(false);
+        let Some(warnings) = config
+            .warnings
+            .as_ref()
+            .filter(|w| w.mode == WarningsMode::Post)
+        else {
+            return Self {
+                stop,
+                tasks: Vec::new(),
+            };
+        };
+        let tasks = config
+            .locations()
+            .into_iter()
+            .map(|location| {
+                let posted = state.location(&location.name).posted_warnings.clone();
+                tokio::spawn(post_new_warnings(
+                    publishers.clone(),
+                    location,
+                    warnings.clone(),
+                    posted,
+                    updates.clone(),
+                    stop.subscribe(),
+                ))
+            })
+            .collect();
+        Self { stop, tasks }
+    }
+
+    /// Stop polling, giving the posts in progress the grace period to finish
+    async fn stop(self) {
+        self.stop.send_replace(true);
+        let deadline = tokio::time::Instant::now() + shutdown::GRACE_PERIOD;
+        for mut task in self.tasks {
+            if tokio::time::timeout_at(deadline, &mut task).await.is_err() {
+                warn!(
+                    "Gave up on posting warnings after {:?}",
+                    shutdown::GRACE_PERIOD
+                );
+                task.abort();
+            }
+        }
+    }
+}
+
+/// Serve the ActivityPub actor in the background, if enabled
+fn spawn_activitypub(config: &Config) -> Result<Option<JoinHandle<()>>> {
+    let Some(activitypub) = config.activitypub.as_ref() else {
+        return Ok(None);
+    };
+    let server = Arc::new(ActivityPub::new(activitypub)?);
+    Ok(Some(tokio::spawn(async move {
+        if let Err(e) = server.serve().await {
+            error!("The ActivityPub server stopped: {}", e);
+        }
+    })))
+}
+
+/// Serve the admin API in the background, if enabled
+fn spawn_admin(
+    config: &Config,
+    configs: &watch::Sender<Config>,
+    post_now: &mpsc::Sender<PostNow>,
+) -> Option<JoinHandle<()>> {
+    let admin = config.admin.clone()?;
+    let configs = configs.subscribe();
+    let post_now = post_now.clone();
+    Some(tokio::spawn(async move {
+        if let Err(e) = admin::serve(admin, configs, post_now).await {
+            error!("The admin API stopped: {}", e);
+        }
+    }))
+}
+
+/// Stop a server, waiting until it lets go of its address
+async fn stop_server(server: Option<JoinHandle<()>>) {
+    if let Some(server) = server {
+        server.abort();
+        let _ = server.await;
+    }
+}
+
+/// Load the config at `path` again, or None to keep the old one
+async fn reload(path: &Path, old: &Config) -> Option<(Config, Publishers)> {
+    let config = match Config::load(Some(path)) {
+        Ok(config) => config,
+        Err(e) => {
+            error!("Keeping the old config: {}", e);
+            return None;
+        }
+    };
+    let publishers = match login(&config).await {
+        Ok(publishers) => publishers,
+        Err(e) => {
+            error!("Keeping the old config: {}", e);
+            return None;
+        }
     };
-    match masto.new_status(status) {
-        Ok(_) => Ok(()),
-        Err(e) => bail!("Error posting status: {}", e),
+    let schedules = |config| {
+        jobs(config)
+            .iter()
+            .map(|job| job.schedule(config).clone())
+            .collect::<Vec<_>>()
+    };
+    if schedules(old) != schedules(&config) {
+        info!("The schedule changed");
+    }
+    info!("Reloaded the config from {}", path.display());
+    Some((config, publishers))
+}
+
+/// Post on schedule until asked to stop, returning the signal which stopped it
+async fn run(path: &Path) -> Result<Signal> {
+    info!("Starting bot");
+    let mut shutdown = Shutdown::listen()?;
+    let mut config = Config::load(Some(path))?;
+    let configs = watch::Sender::new(config.clone());
+    let (post_now, mut post_now_requests) = mpsc::channel(8);
+    // Up before logging in, for the health checks
+    let mut admin = spawn_admin(&config, &configs, &post_now);
+    let mut publishers = login(&config).await?;
+    let mut state = State::load()?;
+    if config.locations().is_empty() {
+        bail!("No locations configured");
+    }
+    let mut reloads = Reloads::watch(path)?;
+    let (warning_updates, mut posted_warnings) = mpsc::unbounded_channel();
+    let mut warning_tasks = WarningTasks::spawn(&publishers, &config, &mut state, &warning_updates);
+    let mut activitypub = spawn_activitypub(&config)?;
+    status::set_ready(true);
+
+    let mut now = Local::now();
+    loop {
+        status::set_next(
+            jobs(&config)
+                .iter()
+                .map(|job| status::NextPost {
+                    job: job.kind(),
+                    time: job.schedule(&config).next_after(now),
+                })
+                .collect(),
+        );
+        let next = jobs(&config)
+            .iter()
+            .map(|job| job.schedule(&config).next_after(now))
+            .min()
+            .expect("There is always a forecast job");
+        tokio::select! {
+            _ = schedule::sleep_until(next) => (),
+            _ = reloads.next() => {
+                let reloaded = tokio::select! {
+                    reloaded = reload(path, &config) => reloaded,
+                    signal = shutdown.recv() => {
+                        return stop(signal, state, warning_tasks, posted_warnings).await;
+                    }
+                };
+                if let Some((new_config, new_publishers)) = reloaded {
+                    if new_config.activitypub != config.activitypub {
+                        stop_server(activitypub.take()).await;
+                        activitypub = spawn_activitypub(&new_config).unwrap_or_else(|e| {
+                            error!("Failed to start the ActivityPub server: {}", e);
+                            None
+                        });
+                    }
+                    if new_config.admin != config.admin {
+                        stop_server(admin.take()).await;
+                        admin = spawn_admin(&new_config, &configs, &post_now);
+                    }
+                    configs.send_replace(new_config.clone());
+                    config = new_config;
+                    publishers = new_publishers;
+                    warning_tasks.stop().await;
+                    // Start the new tasks from what the old ones had posted
+                    receive_posted_warnings(&mut state, &mut posted_warnings);
+                    warning_tasks =
+                        WarningTasks::spawn(&publishers, &config, &mut state, &warning_updates);
+                }
+                // Schedule the jobs again, with the new config if it was valid
+                now = now.max(Local::now());
+                continue;
+            }
+            Some((location, posted)) = posted_warnings.recv() => {
+                state.location(&location).posted_warnings = posted;
+                state.save()?;
+                continue;
+            }
+            Some(request) = post_now_requests.recv() => {
+                info!("Posting for {} on request", request.location.name);
+                let job = forecast_job(&config, request.day);
+                let post = job.run(&publishers, &config, &request.location, &mut state);
+                let (result, stopping) = finish_post(post, &mut shutdown, &request.location).await;
+                let _ = request.done.send(result);
+                if let Some(signal) = stopping {
+                    return stop(signal, state, warning_tasks, posted_warnings).await;
+                }
+                state.save()?;
+                continue;
+            }
+            signal = shutdown.recv() => {
+                return stop(signal, state, warning_tasks, posted_warnings).await;
+            }
+        }
+        now = next;
+        let locations = config.locations();
+        let due = jobs(&config).into_iter().filter(|job| {
+            job.schedule(&config)
+                .next_after(next - Duration::seconds(1))
+                == next
+        });
+        let mut stopping = None;
+        'posting: for job in due {
+            metrics::schedule_lag(&job.kind(), next);
+            for location in &locations {
+                let post = job.run(&publishers, &config, location, &mut state);
+                let result;
+                (result, stopping) = finish_post(post, &mut shutdown, location).await;
+                if let Err(e) = result {
+                    error!("Failed to post for {}: {}", location.name, e);
+                }
+                if stopping.is_some() {
+                    break 'posting;
+                }
+            }
+        }
+        if let Some(signal) = stopping {
+            return stop(signal, state, warning_tasks, posted_warnings).await;
+        }
+        state.save()?;
+    }
+}
+
+/// Wait for a post, giving it the grace period to finish if asked to stop
+/// meanwhile, and return the signal if so
+async fn finish_post(
+    post: impl std::future::Future<Output = Result<()>>,
+    shutdown: &mut Shutdown,
+    location: &Location,
+) -> (Result<()>, Option<Signal>) {
+    tokio::pin!(post);
+    tokio::select! {
+        result = &mut post => (result, None),
+        signal = shutdown.recv() => {
+            info!("Finishing the post for {} before stopping", location.name);
+            let result = match tokio::time::timeout(shutdown::GRACE_PERIOD, &mut post).await {
+                Ok(result) => result,
+                Err(_) => Err(anyhow::anyhow!("Gave up after {:?}", shutdown::GRACE_PERIOD)),
+            };
+            (result, Some(signal))
+        }
+    }
+}
+
+/// Store the warnings the polling tasks have posted since last time
+fn receive_posted_warnings(
+    state: &mut State,
+    updates: &mut mpsc::UnboundedReceiver<PostedWarnings>,
+) {
+    while let Ok((location, posted)) = updates.try_recv() {
+        state.location(&location).posted_warnings = posted;
     }
 }
 
+/// Let the warning tasks finish and save the state before stopping on `signal`
+async fn stop(
+    signal: Signal,
+    mut state: State,
+    warning_tasks: WarningTasks,
+    mut posted_warnings: mpsc::UnboundedReceiver<PostedWarnings>,
+) -> Result<Signal> {
+    info!("Stopping on {:?}", signal);
+    status::set_ready(false);
+    warning_tasks.stop().await;
+    receive_posted_warnings(&mut state, &mut posted_warnings);
+    state.save()?;
+    Ok(signal)
+}
+
 #[tokio::main]
 async fn main() -> Result<()> {
     tracing_subscriber::fmt()
         .pretty()
+        .with_writer(std::io::stderr)
         .with_env_filter(
             tracing_subscriber::EnvFilter::from_default_env()
                 .add_directive("vader_bot=debug".parse().unwrap()),
         )
         .init();
-    info!("Starting bot");
-    let config: Config = config::Config::new()?;
-    let masto = Mastodon::from(config.mastodon.clone());
-    match masto.verify_credentials() {
-        Ok(_) => (),
-        Err(e) => bail!("Error verifying credentials: {}", e),
-    };
+    let cli = Cli::parse();
+    let config = cli.config.as_deref();
+    match cli.command.unwrap_or(Command::Run) {
+        Command::Run => std::process::exit(run(&loader::find(config)?).await?.exit_code()),
+        Command::Preview { location, day } => preview(&Config::load(config)?, &location, day).await,
+        Command::PostNow { location, day } => {
+            post_now(&Config::load(config)?, location.as_deref(), day).await
+        }
+        Command::CheckConfig => check_config(config).await,
+        Command::Render {
+            input,
+            format,
+            location,
+            day,
+        } => render_saved(config, &input, format, location.as_deref(), day).await,
+        Command::Register {
+            instance,
+            in_config,
+            force,
+        } => register::register(config, &instance, in_config, force),
+        Command::Fetch {
+            location,
+            format,
+            save,
+        } => fetch(&location, format, save.as_deref()).await,
+        Command::Follow { actor } => follow(&Config::load(config)?, &actor).await,
+    }
+}
 
-    loop {
-        wait_unil_8pm().await?;
-        let weather = fetch_tomorrows_weather(&config.location).await?;
-        toot(&masto, weather)?;
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_the_morning_post() {
+        let now = vader::testing::current_condition(&[
+            ("temp_C", "3"),
+            ("FeelsLikeC", "-1"),
+            ("windspeedKmph", "18"),
+            ("winddir16Point", "W"),
+        ])
+        .describe();
+        let sections: Vec<(&str, String)> = [
+            ("location", "Lund"),
+            ("now", &now),
+            ("forecast", "Idag blir det mest sol."),
+            ("warnings", ""),
+            ("climate", ""),
+            ("marine", ""),
+            ("astronomy", "Soluppgång 05:12, solnedgång 21:48"),
+            ("pollen", "Pollen: björk {warnings}"),
+            ("air_quality", ""),
+        ]
+        .into_iter()
+        .map(|(name, value)| (name, value.to_string()))
+        .collect();
+        assert_eq!(
+            render(MORNING_TEMPLATE, &sections),
+            "God morgon Lund! Just nu 3 °C, känns som -1 °C och måttlig västlig vind, 5 m/s.\n\
+             Idag blir det mest sol.\n\
+             Soluppgång 05:12, solnedgång 21:48\n\
+             Pollen: björk {warnings}"
+        );
     }
 }
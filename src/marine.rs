@@ -0,0 +1,123 @@
+//! Water temperature and waves at a coastal point, from Open-Meteo
+use anyhow::Result;
+use chrono::{Datelike, NaiveDate};
+use serde::{Deserialize, Serialize};
+
+use crate::open_meteo::{self, HourlyForecast};
+
+/// A coastal point near a location
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MarineConfig {
+    pub latitude: f64,
+    pub longitude: f64,
+    /// Months in which the water temperature is reported
+    #[serde(default = "default_months")]
+    pub months: Vec<u32>,
+}
+
+fn default_months() -> Vec<u32> {
+    (6..=8).collect()
+}
+
+impl MarineConfig {
+    pub fn in_season(&self, date: NaiveDate) -> bool {
+        self.months.contains(&date.month())
+    }
+}
+
+/// Conditions at sea during a day
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Marine {
+    /// Highest sea surface temperature in °C
+    pub water_temp_c: f32,
+    /// Highest wave height in meters
+    pub wave_height_m: Option<f32>,
+}
+
+pub async fn fetch(config: &MarineConfig, date: NaiveDate) -> Result<Option<Marine>> {
+    let forecast = open_meteo::fetch_marine(
+        config.latitude,
+        config.longitude,
+        &["sea_surface_temperature", "wave_height"],
+    )
+    .await?;
+    Ok(daily_max(&forecast, date))
+}
+
+/// The highest values in the forecast during the date, if it has the water
+/// temperature
+fn daily_max(forecast: &HourlyForecast, date: NaiveDate) -> Option<Marine> {
+    let hourly = &forecast.hourly;
+    hourly
+        .daily_max("sea_surface_temperature", date)
+        .map(|water_temp_c| Marine {
+            water_temp_c,
+            wave_height_m: hourly.daily_max("wave_height", date),
+        })
+}
+
+/// E.g. "Vattentemperatur: 18 °C, våghöjd upp till 0,5 m"
+pub fn report(marine: &Marine) -> String {
+    let mut s = format!(
+        "Vattentemperatur: {} °C",
+        marine.water_temp_c.round() as i32
+    );
+    if let Some(waves) = marine.wave_height_m {
+        s.push_str(&format!(
+            ", våghöjd upp till {} m",
+            format!("{:.1}", waves).replace('.', ",")
+        ));
+    }
+    s
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LOMMA: &str = include_str!("../tests/fixtures/marine/lomma.json");
+
+    fn marine(date: &str) -> Option<Marine> {
+        let forecast: HourlyForecast = serde_json::from_str(LOMMA).unwrap();
+        daily_max(&forecast, date.parse().unwrap())
+    }
+
+    #[test]
+    fn reads_the_daily_max_from_the_fixture() {
+        assert_eq!(
+            marine("2024-07-15"),
+            Some(Marine {
+                water_temp_c: 18.6,
+                wave_height_m: Some(0.53),
+            })
+        );
+        // No waves forecast for the second day
+        assert_eq!(
+            marine("2024-07-16"),
+            Some(Marine {
+                water_temp_c: 19.1,
+                wave_height_m: None,
+            })
+        );
+        assert_eq!(marine("2024-07-17"), None);
+    }
+
+    #[test]
+    fn reports_with_a_decimal_comma() {
+        assert_eq!(
+            report(&marine("2024-07-15").unwrap()),
+            "Vattentemperatur: 19 °C, våghöjd upp till 0,5 m"
+        );
+        assert_eq!(
+            report(&marine("2024-07-16").unwrap()),
+            "Vattentemperatur: 19 °C"
+        );
+        assert_eq!(
+            report(&Marine {
+                water_temp_c: -0.4,
+                wave_height_m: Some(1.25),
+            }),
+            "Vattentemperatur: 0 °C, våghöjd upp till 1,2 m"
+        );
+    }
+}
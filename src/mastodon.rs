@@ -0,0 +1,181 @@
+//! A small async client for the parts of the Mastodon API the bot uses
+use std::fmt;
+
+use anyhow::Result;
+use async_trait::async_trait;
+use elefren::data::Data;
+use serde::{Deserialize, Serialize};
+
+use crate::publisher::{check, Formatting, MarkdownFlavor, Markup, Publisher, MASTODON, TIMEOUT};
+
+/// Defaults following Mastodon's limit, `max_chars` in `[mastodon]` overrides it
+pub const FORMATTING: Formatting = Formatting {
+    markup: Markup::Plain,
+    max_chars: Some(500),
+    markdown: MarkdownFlavor::CommonMark,
+};
+
+/// A logged in Mastodon account
+#[derive(Debug, Clone)]
+pub struct Mastodon {
+    formatting: Formatting,
+    client: reqwest::Client,
+    base: String,
+    token: String,
+}
+
+#[derive(Debug, Serialize)]
+struct NewStatus<'a> {
+    status: &'a str,
+    visibility: &'a str,
+    language: &'a str,
+    /// Pleroma and Akkoma extension, ignored by Mastodon. Plain text, since
+    /// the post isn't escaped.
+    content_type: &'a str,
+}
+
+/// Error returned by the API
+#[derive(Debug, Deserialize)]
+struct ApiError {
+    error: String,
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.error)
+    }
+}
+
+impl Mastodon {
+    pub fn new(data: &Data, formatting: Formatting) -> Result<Self> {
+        Ok(Self {
+            formatting,
+            client: reqwest::Client::builder().timeout(TIMEOUT).build()?,
+            base: data.base.trim_end_matches('/').to_string(),
+            token: data.token.to_string(),
+        })
+    }
+
+    fn url(&self, path: &str) -> String {
+        format!("{}{}", self.base, path)
+    }
+}
+
+#[async_trait]
+impl Publisher for Mastodon {
+    fn name(&self) -> &str {
+        MASTODON
+    }
+
+    async fn verify(&self) -> Result<()> {
+        let response = self
+            .client
+            .get(self.url("/api/v1/accounts/verify_credentials"))
+            .bearer_auth(&self.token)
+            .send()
+            .await
+            .map_err(reqwest::Error::without_url)?;
+        check::<ApiError>(response).await?;
+        Ok(())
+    }
+
+    async fn publish(&self, post: &str) -> Result<()> {
+        let response = self
+            .client
+            .post(self.url("/api/v1/statuses"))
+            .bearer_auth(&self.token)
+            .json(&NewStatus {
+                status: &self.formatting.apply(post),
+                visibility: "unlisted",
+                language: "sv",
+                content_type: "text/plain",
+            })
+            .send()
+            .await
+            .map_err(reqwest::Error::without_url)?;
+        check::<ApiError>(response).await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::borrow::Cow;
+
+    use super::*;
+    use crate::stub::Stub;
+
+    fn mastodon(stub: &Stub, max_chars: Option<usize>) -> Mastodon {
+        let data = Data {
+            base: Cow::Owned(format!("{}/", stub.url)),
+            client_id: Cow::Borrowed("id"),
+            client_secret: Cow::Borrowed("secret"),
+            redirect: Cow::Borrowed("urn:ietf:wg:oauth:2.0:oob"),
+            token: Cow::Borrowed("token"),
+        };
+        let formatting = Formatting {
+            max_chars,
+            ..FORMATTING
+        };
+        Mastodon::new(&data, formatting).unwrap()
+    }
+
+    #[tokio::test]
+    async fn verifies_the_credentials() {
+        let stub = Stub::start(&[(200, r#"{"id":"1","username":"vader"}"#)]).await;
+        mastodon(&stub, Some(500)).verify().await.unwrap();
+        let requests = stub.requests();
+        assert_eq!(requests[0].method, "GET");
+        assert_eq!(requests[0].path, "/api/v1/accounts/verify_credentials");
+        assert_eq!(requests[0].header("authorization"), Some("Bearer token"));
+    }
+
+    #[tokio::test]
+    async fn posts_unlisted_plain_text() {
+        let stub = Stub::start(&[(200, r#"{"id":"1"}"#)]).await;
+        mastodon(&stub, Some(500))
+            .publish("Lund: Sol & <moln>")
+            .await
+            .unwrap();
+        let requests = stub.requests();
+        assert_eq!(requests[0].method, "POST");
+        assert_eq!(requests[0].path, "/api/v1/statuses");
+        assert_eq!(requests[0].header("authorization"), Some("Bearer token"));
+        assert_eq!(
+            requests[0].json(),
+            serde_json::json!({
+                "status": "Lund: Sol & <moln>",
+                "visibility": "unlisted",
+                "language": "sv",
+                "content_type": "text/plain",
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn shortens_to_max_chars() {
+        let stub = Stub::start(&[(200, r#"{"id":"1"}"#)]).await;
+        mastodon(&stub, Some(12))
+            .publish("Lund: Sol\nVind 5 m/s")
+            .await
+            .unwrap();
+        assert_eq!(stub.requests()[0].json()["status"], "Lund: Sol…");
+    }
+
+    #[tokio::test]
+    async fn fails_with_the_error() {
+        let stub = Stub::start(&[
+            (401, r#"{"error":"The access token is invalid"}"#),
+            (422, "<html>Unprocessable</html>"),
+        ])
+        .await;
+        let mastodon = mastodon(&stub, Some(500));
+        let e = mastodon.verify().await.unwrap_err();
+        assert_eq!(
+            e.to_string(),
+            "401 Unauthorized: The access token is invalid"
+        );
+        let e = mastodon.publish("Hej").await.unwrap_err();
+        assert_eq!(e.to_string(), "422 Unprocessable Entity");
+    }
+}
@@ -0,0 +1,239 @@
+//! Posting to a Matrix room
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+
+use crate::publisher::{check, Formatting, Markup, Publisher, TIMEOUT};
+
+pub struct Matrix {
+    name: String,
+    formatting: Formatting,
+    client: reqwest::Client,
+    homeserver: reqwest::Url,
+    room_id: String,
+    access_token: String,
+    /// Makes the transaction ids unique within the process
+    transactions: AtomicU64,
+}
+
+#[derive(Debug, Serialize)]
+struct Message<'a> {
+    msgtype: &'a str,
+    body: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    format: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    formatted_body: Option<String>,
+}
+
+/// Error returned by the client-server API
+#[derive(Debug, Deserialize)]
+struct MatrixError {
+    errcode: String,
+    #[serde(default)]
+    error: Option<String>,
+}
+
+impl fmt::Display for MatrixError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} {}",
+            self.errcode,
+            self.error.as_deref().unwrap_or_default()
+        )
+    }
+}
+
+impl Matrix {
+    pub fn new(
+        name: String,
+        formatting: Formatting,
+        homeserver: &str,
+        room_id: &str,
+        access_token: &str,
+    ) -> Result<Self> {
+        Ok(Self {
+            name,
+            formatting,
+            client: reqwest::Client::builder().timeout(TIMEOUT).build()?,
+            homeserver: reqwest::Url::parse(homeserver)?,
+            room_id: room_id.to_string(),
+            access_token: access_token.to_string(),
+            transactions: AtomicU64::new(0),
+        })
+    }
+
+    /// Url of an endpoint, with each segment escaped
+    fn url(&self, segments: &[&str]) -> Result<reqwest::Url> {
+        let mut url = self.homeserver.clone();
+        url.path_segments_mut()
+            .map_err(|_| anyhow!("Invalid homeserver url {}", self.homeserver))?
+            .pop_if_empty()
+            .extend(["_matrix", "client", "v3"])
+            .extend(segments);
+        Ok(url)
+    }
+}
+
+#[async_trait]
+impl Publisher for Matrix {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    async fn verify(&self) -> Result<()> {
+        let response = self
+            .client
+            .get(self.url(&["account", "whoami"])?)
+            .bearer_auth(&self.access_token)
+            .send()
+            .await
+            .map_err(reqwest::Error::without_url)?;
+        check::<MatrixError>(response).await?;
+        Ok(())
+    }
+
+    async fn publish(&self, post: &str) -> Result<()> {
+        // The body is always sent, for clients which don't show HTML. Markdown
+        // is only a convention there.
+        let body = match self.formatting.markup {
+            Markup::Markdown => self.formatting.apply(post),
+            Markup::Plain | Markup::Html => Formatting {
+                markup: Markup::Plain,
+                ..self.formatting
+            }
+            .apply(post),
+        };
+        let formatted = match self.formatting.markup {
+            Markup::Html => Some(self.formatting.apply(post).replace('\n', "<br>")),
+            Markup::Plain | Markup::Markdown => None,
+        };
+        let transaction = format!(
+            "vader_bot.{}.{}",
+            Utc::now().timestamp_millis(),
+            self.transactions.fetch_add(1, Ordering::Relaxed)
+        );
+        let url = self.url(&[
+            "rooms",
+            &self.room_id,
+            "send",
+            "m.room.message",
+            &transaction,
+        ])?;
+        let response = self
+            .client
+            .put(url)
+            .bearer_auth(&self.access_token)
+            .json(&Message {
+                msgtype: "m.text",
+                body: &body,
+                format: formatted.as_ref().map(|_| "org.matrix.custom.html"),
+                formatted_body: formatted,
+            })
+            .send()
+            .await
+            .map_err(reqwest::Error::without_url)?;
+        check::<MatrixError>(response).await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::publisher::MarkdownFlavor;
+    use crate::stub::Stub;
+
+    fn matrix(stub: &Stub, markup: Markup) -> Matrix {
+        let formatting = Formatting {
+            markup,
+            max_chars: None,
+            markdown: MarkdownFlavor::CommonMark,
+        };
+        Matrix::new(
+            "matrix".to_string(),
+            formatting,
+            &stub.url,
+            "!room:example.org",
+            "secret",
+        )
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn verifies_the_token() {
+        let stub = Stub::start(&[(200, r#"{"user_id":"@vader:example.org"}"#)]).await;
+        matrix(&stub, Markup::Html).verify().await.unwrap();
+        let requests = stub.requests();
+        assert_eq!(requests[0].method, "GET");
+        assert_eq!(requests[0].path, "/_matrix/client/v3/account/whoami");
+        assert_eq!(requests[0].header("authorization"), Some("Bearer secret"));
+    }
+
+    #[tokio::test]
+    async fn sends_html_with_a_plain_body() {
+        let stub =
+            Stub::start(&[(200, r#"{"event_id":"$1"}"#), (200, r#"{"event_id":"$2"}"#)]).await;
+        let matrix = matrix(&stub, Markup::Html);
+        matrix.publish("Sol & moln\n<b>").await.unwrap();
+        matrix.publish("Regn").await.unwrap();
+        let requests = stub.requests();
+        assert_eq!(requests[0].method, "PUT");
+        assert!(
+            requests[0].path.starts_with(
+                "/_matrix/client/v3/rooms/!room:example.org/send/m.room.message/vader_bot."
+            ),
+            "{}",
+            requests[0].path
+        );
+        assert_ne!(
+            requests[0].path, requests[1].path,
+            "Transaction ids are reused"
+        );
+        assert_eq!(requests[0].header("authorization"), Some("Bearer secret"));
+        assert_eq!(
+            requests[0].json(),
+            serde_json::json!({
+                "msgtype": "m.text",
+                "body": "Sol & moln\n<b>",
+                "format": "org.matrix.custom.html",
+                "formatted_body": "Sol &amp; moln<br>&lt;b&gt;",
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn sends_markdown_as_the_body() {
+        let stub = Stub::start(&[(200, r#"{"event_id":"$1"}"#)]).await;
+        matrix(&stub, Markup::Markdown)
+            .publish("*Sol*\nRegn")
+            .await
+            .unwrap();
+        assert_eq!(
+            stub.requests()[0].json(),
+            serde_json::json!({ "msgtype": "m.text", "body": "\\*Sol\\*  \nRegn" })
+        );
+    }
+
+    #[tokio::test]
+    async fn fails_with_the_error_code() {
+        let stub = Stub::start(&[(
+            403,
+            r#"{"errcode":"M_FORBIDDEN","error":"You are not in the room"}"#,
+        )])
+        .await;
+        let e = matrix(&stub, Markup::Html)
+            .publish("Hej")
+            .await
+            .unwrap_err();
+        assert_eq!(
+            e.to_string(),
+            "403 Forbidden: M_FORBIDDEN You are not in the room"
+        );
+    }
+}
@@ -0,0 +1,175 @@
+//! Prometheus metrics, served by the admin API on /metrics
+use std::future::Future;
+use std::sync::LazyLock;
+
+use anyhow::Result;
+use chrono::{DateTime, Local};
+use prometheus::{
+    histogram_opts, opts, Encoder, HistogramVec, IntCounterVec, Registry, TextEncoder,
+};
+
+use crate::publisher::TIMEOUT;
+
+static METRICS: LazyLock<Metrics> = LazyLock::new(|| Metrics::new().expect("Valid metrics"));
+
+/// Client for the fetches, with a timeout so that a hung request doesn't hold
+/// up the posts after it
+pub static CLIENT: LazyLock<reqwest::Client> = LazyLock::new(|| {
+    reqwest::Client::builder()
+        .timeout(TIMEOUT)
+        .build()
+        .expect("Valid client")
+});
+
+struct Metrics {
+    registry: Registry,
+    fetch_duration: HistogramVec,
+    fetch_errors: IntCounterVec,
+    posts: IntCounterVec,
+    retries: IntCounterVec,
+    schedule_lag: HistogramVec,
+    parse_failures: IntCounterVec,
+}
+
+impl Metrics {
+    fn new() -> Result<Self> {
+        let registry = Registry::new_custom(Some("vader".to_string()), None)?;
+        let metrics = Self {
+            fetch_duration: HistogramVec::new(
+                histogram_opts!("fetch_duration_seconds", "Time taken by fetches"),
+                &["provider"],
+            )?,
+            fetch_errors: IntCounterVec::new(
+                opts!("fetch_errors_total", "Failed fetches"),
+                &["provider"],
+            )?,
+            posts: IntCounterVec::new(
+                opts!("posts_total", "Posts by publisher and result"),
+                &["publisher", "result"],
+            )?,
+            retries: IntCounterVec::new(
+                opts!("retries_total", "Requests retried by publishers"),
+                &["publisher"],
+            )?,
+            schedule_lag: HistogramVec::new(
+                histogram_opts!(
+                    "schedule_lag_seconds",
+                    "Time from when a job was planned until it started posting",
+                    vec![1.0, 5.0, 15.0, 30.0, 60.0, 120.0, 300.0, 600.0, 1800.0]
+                ),
+                &["job"],
+            )?,
+            parse_failures: IntCounterVec::new(
+                opts!(
+                    "wttr_parse_failures_total",
+                    "Fields from wttr.in which couldn't be parsed"
+                ),
+                &["field"],
+            )?,
+            registry,
+        };
+        metrics
+            .registry
+            .register(Box::new(metrics.fetch_duration.clone()))?;
+        metrics
+            .registry
+            .register(Box::new(metrics.fetch_errors.clone()))?;
+        metrics.registry.register(Box::new(metrics.posts.clone()))?;
+        metrics
+            .registry
+            .register(Box::new(metrics.retries.clone()))?;
+        metrics
+            .registry
+            .register(Box::new(metrics.schedule_lag.clone()))?;
+        metrics
+            .registry
+            .register(Box::new(metrics.parse_failures.clone()))?;
+        Ok(metrics)
+    }
+}
+
+/// Time a fetch from `provider`, counting it if it fails
+pub async fn fetch<T>(provider: &str, fetch: impl Future<Output = Result<T>>) -> Result<T> {
+    let timer = METRICS
+        .fetch_duration
+        .with_label_values(&[provider])
+        .start_timer();
+    let result = fetch.await;
+    timer.observe_duration();
+    if result.is_err() {
+        METRICS.fetch_errors.with_label_values(&[provider]).inc();
+    }
+    result
+}
+
+pub fn published(publisher: &str, result: &Result<()>) {
+    let result = if result.is_ok() { "success" } else { "failure" };
+    METRICS.posts.with_label_values(&[publisher, result]).inc();
+}
+
+pub fn retried(publisher: &str) {
+    METRICS.retries.with_label_values(&[publisher]).inc();
+}
+
+/// A job planned for `planned` starts posting
+pub fn schedule_lag(job: &str, planned: DateTime<Local>) {
+    let lag = (Local::now() - planned).num_milliseconds() as f64 / 1000.0;
+    METRICS
+        .schedule_lag
+        .with_label_values(&[job])
+        .observe(lag.max(0.0));
+}
+
+pub fn parse_failed(field: &str) {
+    METRICS.parse_failures.with_label_values(&[field]).inc();
+}
+
+/// All metrics in the Prometheus text format
+pub fn render() -> Result<String> {
+    let mut buffer = Vec::new();
+    TextEncoder::new().encode(&METRICS.registry.gather(), &mut buffer)?;
+    Ok(String::from_utf8(buffer)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::anyhow;
+    use chrono::Duration;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn renders_the_recorded_metrics() {
+        fetch("test_ok", async { Ok(()) }).await.unwrap();
+        fetch::<()>("test_failing", async { Err(anyhow!("timed out")) })
+            .await
+            .unwrap_err();
+        published("test_publisher", &Ok(()));
+        published("test_publisher", &Err(anyhow!("refused")));
+        retried("test_publisher");
+        parse_failed("test_field");
+        schedule_lag("test_job", Local::now() - Duration::seconds(10));
+
+        let metrics = render().unwrap();
+        for line in [
+            "vader_fetch_duration_seconds_count{provider=\"test_ok\"} 1",
+            "vader_fetch_duration_seconds_count{provider=\"test_failing\"} 1",
+            "vader_fetch_errors_total{provider=\"test_failing\"} 1",
+            "vader_posts_total{publisher=\"test_publisher\",result=\"success\"} 1",
+            "vader_posts_total{publisher=\"test_publisher\",result=\"failure\"} 1",
+            "vader_retries_total{publisher=\"test_publisher\"} 1",
+            "vader_wttr_parse_failures_total{field=\"test_field\"} 1",
+            "vader_schedule_lag_seconds_bucket{job=\"test_job\",le=\"5\"} 0",
+            "vader_schedule_lag_seconds_bucket{job=\"test_job\",le=\"15\"} 1",
+            "vader_schedule_lag_seconds_count{job=\"test_job\"} 1",
+        ] {
+            assert!(
+                metrics.lines().any(|l| l == line),
+                "{} missing from\n{}",
+                line,
+                metrics
+            );
+        }
+        assert!(!metrics.contains("vader_fetch_errors_total{provider=\"test_ok\"}"));
+    }
+}
@@ -0,0 +1,225 @@
+//! Multi-day forecasts, air quality and marine data from https://open-meteo.com
+use std::collections::HashMap;
+
+use anyhow::{bail, Result};
+use chrono::NaiveDate;
+use serde::Deserialize;
+
+use crate::metrics;
+use crate::outlook::DayOutlook;
+use crate::vader::BaseWeather;
+
+/// Open-Meteo forecasts at most 16 days ahead
+pub const MAX_DAYS: u8 = 16;
+
+#[derive(Debug, Deserialize)]
+pub struct Forecast {
+    pub daily: Daily,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Daily {
+    pub time: Vec<NaiveDate>,
+    pub weather_code: Vec<Option<u8>>,
+    pub temperature_2m_min: Vec<Option<f32>>,
+    pub temperature_2m_max: Vec<Option<f32>>,
+    pub precipitation_sum: Vec<Option<f32>>,
+}
+
+/// Fetch the daily forecast for the coming `days`, starting today
+pub async fn fetch_daily(lat: f64, lon: f64, days: u8) -> Result<Forecast> {
+    Ok(serde_json::from_str(
+        &fetch_daily_json(lat, lon, days).await?,
+    )?)
+}
+
+/// Fetch the daily forecast as is, e.g. to save it for later
+pub async fn fetch_daily_json(lat: f64, lon: f64, days: u8) -> Result<String> {
+    if days == 0 || days > MAX_DAYS {
+        bail!(
+            "Open-Meteo can forecast 1 to {} days, not {}",
+            MAX_DAYS,
+            days
+        );
+    }
+    metrics::fetch("open_meteo", async {
+        Ok(metrics::CLIENT
+            .get("https://api.open-meteo.com/v1/forecast")
+            .query(&[
+                ("latitude", lat.to_string()),
+                ("longitude", lon.to_string()),
+                (
+                    "daily",
+                    "weather_code,temperature_2m_min,temperature_2m_max,precipitation_sum"
+                        .to_string(),
+                ),
+                ("timezone", "auto".to_string()),
+                ("forecast_days", days.to_string()),
+            ])
+            .send()
+            .await?
+            .error_for_status()?
+            .text()
+            .await?)
+    })
+    .await
+}
+
+impl Forecast {
+    /// Days with complete data
+    pub fn outlook(&self) -> Vec<DayOutlook> {
+        let daily = &self.daily;
+        (0..daily.time.len())
+            .filter_map(|i| {
+                Some(DayOutlook {
+                    date: daily.time[i],
+                    weather: wmo_to_base_weather(daily.weather_code.get(i).copied()??),
+                    min_temp_c: daily.temperature_2m_min.get(i).copied()??,
+                    max_temp_c: daily.temperature_2m_max.get(i).copied()??,
+                    precip_mm: daily.precipitation_sum.get(i).copied()?.unwrap_or_default(),
+                })
+            })
+            .collect()
+    }
+}
+
+/// Map a WMO weather interpretation code to our simplified weather
+pub fn wmo_to_base_weather(code: u8) -> BaseWeather {
+    match code {
+        0 | 1 => BaseWeather::Sunny,
+        2 | 3 => BaseWeather::Cloudy,
+        45 | 48 => BaseWeather::Fog,
+        66 | 67 => BaseWeather::Sleet,
+        71..=77 | 85 | 86 => BaseWeather::Snow,
+        95..=99 => BaseWeather::Thunder,
+        _ => BaseWeather::Rain,
+    }
+}
+
+/// Hourly values from the air quality and marine APIs
+#[derive(Debug, Deserialize)]
+pub struct HourlyForecast {
+    pub hourly: HourlyValues,
+}
+
+/// Hourly values keyed by variable name
+#[derive(Debug, Deserialize)]
+pub struct HourlyValues {
+    /// Local time, e.g. "2023-06-12T14:00"
+    pub time: Vec<String>,
+    #[serde(flatten)]
+    pub values: HashMap<String, Vec<Option<f32>>>,
+}
+
+/// Fetch hourly air quality `variables`, such as "birch_pollen", for today and tomorrow
+pub async fn fetch_air_quality(lat: f64, lon: f64, variables: &[&str]) -> Result<HourlyForecast> {
+    fetch_hourly(
+        "open_meteo_air_quality",
+        "https://air-quality-api.open-meteo.com/v1/air-quality",
+        lat,
+        lon,
+        variables,
+    )
+    .await
+}
+
+/// Fetch hourly marine `variables`, such as "wave_height", for today and tomorrow
+pub async fn fetch_marine(lat: f64, lon: f64, variables: &[&str]) -> Result<HourlyForecast> {
+    fetch_hourly(
+        "open_meteo_marine",
+        "https://marine-api.open-meteo.com/v1/marine",
+        lat,
+        lon,
+        variables,
+    )
+    .await
+}
+
+async fn fetch_hourly(
+    provider: &str,
+    url: &str,
+    lat: f64,
+    lon: f64,
+    variables: &[&str],
+) -> Result<HourlyForecast> {
+    metrics::fetch(provider, async {
+        Ok(metrics::CLIENT
+            .get(url)
+            .query(&[
+                ("latitude", lat.to_string()),
+                ("longitude", lon.to_string()),
+                ("hourly", variables.join(",")),
+                ("timezone", "auto".to_string()),
+                ("forecast_days", "2".to_string()),
+            ])
+            .send()
+            .await?
+            .error_for_status()?
+            .json::<HourlyForecast>()
+            .await?)
+    })
+    .await
+}
+
+impl HourlyValues {
+    /// The highest value of a variable during the date
+    pub fn daily_max(&self, variable: &str, date: NaiveDate) -> Option<f32> {
+        let date = date.format("%Y-%m-%d").to_string();
+        self.time
+            .iter()
+            .zip(self.values.get(variable)?)
+            .filter(|(time, _)| time.starts_with(&date))
+            .filter_map(|(_, value)| *value)
+            .reduce(f32::max)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn maps_wmo_codes() {
+        for (codes, weather) in [
+            (&[0, 1][..], BaseWeather::Sunny),
+            (&[2, 3], BaseWeather::Cloudy),
+            (&[45, 48], BaseWeather::Fog),
+            (&[51, 55, 61, 65, 80, 82], BaseWeather::Rain),
+            (&[56, 57], BaseWeather::Rain),
+            (&[66, 67], BaseWeather::Sleet),
+            (&[71, 75, 77, 85, 86], BaseWeather::Snow),
+            (&[95, 96, 99], BaseWeather::Thunder),
+        ] {
+            for &code in codes {
+                assert_eq!(wmo_to_base_weather(code), weather, "WMO code {}", code);
+            }
+        }
+    }
+
+    #[test]
+    fn skips_days_with_missing_values() {
+        let forecast: Forecast = serde_json::from_str(
+            r#"{"daily": {
+                "time": ["2023-06-12", "2023-06-13", "2023-06-14", "2023-06-15", "2023-06-16"],
+                "weather_code": [0, null, 61, 3, 71],
+                "temperature_2m_min": [11.2, 10.0, null, 9.6, -1.0],
+                "temperature_2m_max": [20.4, 18.0, 17.0, 15.1],
+                "precipitation_sum": [0.0, 1.0, 2.0, null, 3.5]
+            }}"#,
+        )
+        .unwrap();
+        let days: Vec<_> = forecast
+            .outlook()
+            .into_iter()
+            .map(|day| (day.date.to_string(), day.weather, day.precip_mm))
+            .collect();
+        // A missing precipitation sum is taken as none, the other values are needed
+        assert_eq!(
+            days,
+            [
+                ("2023-06-12".to_string(), BaseWeather::Sunny, 0.0),
+                ("2023-06-15".to_string(), BaseWeather::Cloudy, 0.0),
+            ]
+        );
+    }
+}
@@ -0,0 +1,183 @@
+//! Compact outlooks for several days
+use chrono::{Datelike, NaiveDate, NaiveTime, Weekday};
+use serde::{Deserialize, Serialize};
+
+use crate::schedule::{self, Schedule};
+use crate::vader::Wttr;
+
+/// Summary of a single day
+#[derive(Debug, Clone, PartialEq)]
+pub struct DayOutlook {
+    pub date: NaiveDate,
+    pub weather: crate::vader::BaseWeather,
+    pub min_temp_c: f32,
+    pub max_temp_c: f32,
+    pub precip_mm: f32,
+}
+
+/// Config for an outlook post
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(from = "RawOutlookConfig")]
+pub struct OutlookConfig {
+    #[serde(default)]
+    pub kind: OutlookKind,
+    /// Number of days in a weekly outlook
+    #[serde(default = "default_days")]
+    pub days: u8,
+    #[serde(flatten)]
+    pub schedule: Schedule,
+}
+
+/// An outlook as written, without `#[serde(flatten)]` so that errors in the
+/// schedule keep their path
+#[derive(Deserialize)]
+struct RawOutlookConfig {
+    #[serde(default)]
+    kind: OutlookKind,
+    #[serde(default = "default_days")]
+    days: u8,
+    #[serde(with = "schedule::hh_mm")]
+    time: NaiveTime,
+    #[serde(default)]
+    weekdays: Vec<Weekday>,
+}
+
+impl From<RawOutlookConfig> for OutlookConfig {
+    fn from(raw: RawOutlookConfig) -> Self {
+        Self {
+            kind: raw.kind,
+            days: raw.days,
+            schedule: Schedule {
+                time: raw.time,
+                weekdays: raw.weekdays,
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OutlookKind {
+    /// The three days wttr.in forecasts
+    ThreeDay,
+    /// A longer outlook from Open-Meteo
+    #[default]
+    Weekly,
+}
+
+fn default_days() -> u8 {
+    7
+}
+
+/// The days forecasted by wttr.in after `today`
+pub fn from_wttr(wttr: &Wttr, today: NaiveDate) -> Vec<DayOutlook> {
+    wttr.weather
+        .iter()
+        .filter_map(|w| {
+            Some(DayOutlook {
+                date: NaiveDate::parse_from_str(&w.date, "%Y-%m-%d")
+                    .ok()
+                    .filter(|&date| date > today)?,
+                weather: w.dominant_weather()?,
+                min_temp_c: w.min_temp_c.parse().ok()?,
+                max_temp_c: w.max_temp_c.parse().ok()?,
+                precip_mm: w
+                    .hourly
+                    .iter()
+                    .filter_map(|h| h.precip_mm.parse::<f32>().ok())
+                    .sum(),
+            })
+        })
+        .collect()
+}
+
+fn weekday_to_swedish(weekday: Weekday) -> &'static str {
+    match weekday {
+        Weekday::Mon => "Mån",
+        Weekday::Tue => "Tis",
+        Weekday::Wed => "Ons",
+        Weekday::Thu => "Tor",
+        Weekday::Fri => "Fre",
+        Weekday::Sat => "Lör",
+        Weekday::Sun => "Sön",
+    }
+}
+
+/// One line per day, e.g. "Mån 12/6 ☀️ 11/20°C 2mm"
+pub fn render(title: &str, days: &[DayOutlook]) -> String {
+    let mut s = title.to_string();
+    for day in days {
+        s.push_str(&format!(
+            "\n{} {} {} {}/{}°C",
+            weekday_to_swedish(day.date.weekday()),
+            day.date.format("%-d/%-m"),
+            day.weather.to_emoji(),
+            day.min_temp_c.round() as i32,
+            day.max_temp_c.round() as i32
+        ));
+        if day.precip_mm >= 0.5 {
+            s.push_str(&format!(" {}mm", day.precip_mm.round() as i32));
+        }
+    }
+    s
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vader::{testing, BaseWeather};
+
+    fn day(date: &str, weather: BaseWeather, min: f32, max: f32, precip: f32) -> DayOutlook {
+        DayOutlook {
+            date: date.parse().unwrap(),
+            weather,
+            min_temp_c: min,
+            max_temp_c: max,
+            precip_mm: precip,
+        }
+    }
+
+    #[test]
+    fn renders_a_line_per_day() {
+        let days = [
+            day("2023-06-12", BaseWeather::Sunny, 10.6, 20.4, 0.4),
+            day("2023-06-13", BaseWeather::Rain, 9.4, 15.5, 0.5),
+            day("2023-06-18", BaseWeather::Snow, -3.6, -0.4, 12.3),
+        ];
+        assert_eq!(
+            render("Veckan i Lund", &days),
+            "Veckan i Lund\n\
+             Mån 12/6 ☀️ 11/20°C\n\
+             Tis 13/6 🌧️ 9/16°C 1mm\n\
+             Sön 18/6 🌨️ -4/0°C 12mm"
+        );
+        assert_eq!(render("Veckan i Lund", &[]), "Veckan i Lund");
+    }
+
+    #[test]
+    fn starts_from_tomorrow() {
+        let weather = |date: &str| {
+            let hour: &[(&str, &str)] = &[("weatherDesc", "Sunny"), ("precipMM", "0.2")];
+            testing::weather(date, &[("mintempC", "8"), ("maxtempC", "17")], &[hour; 4])
+        };
+        let wttr = Wttr {
+            current_condition: Vec::new(),
+            nearest_area: Vec::new(),
+            request: Vec::new(),
+            weather: vec![
+                weather("2023-06-12"),
+                weather("2023-06-13"),
+                weather("2023-06-14"),
+            ],
+        };
+        assert_eq!(
+            from_wttr(&wttr, "2023-06-12".parse().unwrap()),
+            vec![
+                day("2023-06-13", BaseWeather::Sunny, 8.0, 17.0, 0.8),
+                day("2023-06-14", BaseWeather::Sunny, 8.0, 17.0, 0.8),
+            ]
+        );
+        // Still yesterday at the location
+        assert_eq!(from_wttr(&wttr, "2023-06-11".parse().unwrap()).len(), 3);
+    }
+}
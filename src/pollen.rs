@@ -0,0 +1,306 @@
+//! Pollen forecasts, from Open-Meteo or a configurable JSON endpoint
+use std::collections::HashMap;
+
+use anyhow::{bail, Result};
+use chrono::{Datelike, NaiveDate};
+use serde::{Deserialize, Serialize};
+
+use crate::metrics;
+use crate::open_meteo::{self, HourlyForecast};
+
+/// Config for pollen forecasts
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PollenConfig {
+    #[serde(default)]
+    pub provider: PollenProvider,
+    /// Endpoint of the JSON provider. `{lat}`, `{lon}` and `{date}` are replaced
+    /// with the location and the day of the forecast.
+    #[serde(default)]
+    pub url: Option<String>,
+    /// Months in which pollen are reported
+    #[serde(default = "default_months")]
+    pub months: Vec<u32>,
+}
+
+/// Where to get pollen forecasts from
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PollenProvider {
+    /// Open-Meteo, which lacks hazel
+    #[default]
+    OpenMeteo,
+    /// A JSON object with grains per m³ keyed by the Swedish name of the pollen,
+    /// e.g. `{"björk": 120, "gräs": 4}`
+    Json,
+}
+
+/// From when hazel and alder flower in southern Sweden until mugwort is over
+fn default_months() -> Vec<u32> {
+    (2..=9).collect()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Pollen {
+    Hazel,
+    Alder,
+    Birch,
+    Grass,
+    Mugwort,
+}
+
+const ALL_POLLEN: [Pollen; 5] = [
+    Pollen::Hazel,
+    Pollen::Alder,
+    Pollen::Birch,
+    Pollen::Grass,
+    Pollen::Mugwort,
+];
+
+impl Pollen {
+    pub fn to_swedish(self) -> &'static str {
+        match self {
+            Pollen::Hazel => "hassel",
+            Pollen::Alder => "al",
+            Pollen::Birch => "björk",
+            Pollen::Grass => "gräs",
+            Pollen::Mugwort => "gråbo",
+        }
+    }
+
+    fn open_meteo_variable(self) -> Option<&'static str> {
+        match self {
+            Pollen::Hazel => None,
+            Pollen::Alder => Some("alder_pollen"),
+            Pollen::Birch => Some("birch_pollen"),
+            Pollen::Grass => Some("grass_pollen"),
+            Pollen::Mugwort => Some("mugwort_pollen"),
+        }
+    }
+
+    /// Grains per m³ from which the levels are moderate, high and very high
+    fn thresholds(self) -> [f32; 3] {
+        match self {
+            Pollen::Hazel | Pollen::Alder => [10.0, 50.0, 500.0],
+            Pollen::Birch => [10.0, 100.0, 1000.0],
+            Pollen::Grass | Pollen::Mugwort => [10.0, 30.0, 100.0],
+        }
+    }
+
+    pub fn level(self, grains: f32) -> PollenLevel {
+        let [moderate, high, very_high] = self.thresholds();
+        match grains {
+            g if g >= very_high => PollenLevel::VeryHigh,
+            g if g >= high => PollenLevel::High,
+            g if g >= moderate => PollenLevel::Moderate,
+            _ => PollenLevel::Low,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum PollenLevel {
+    Low,
+    Moderate,
+    High,
+    VeryHigh,
+}
+
+impl PollenLevel {
+    pub fn to_swedish(self) -> &'static str {
+        match self {
+            PollenLevel::Low => "låga halter",
+            PollenLevel::Moderate => "måttliga halter",
+            PollenLevel::High => "höga halter",
+            PollenLevel::VeryHigh => "mycket höga halter",
+        }
+    }
+}
+
+/// Highest amount of each pollen during the date, in grains per m³
+pub async fn fetch(
+    config: &PollenConfig,
+    lat: f64,
+    lon: f64,
+    date: NaiveDate,
+) -> Result<Vec<(Pollen, f32)>> {
+    match config.provider {
+        PollenProvider::OpenMeteo => {
+            let variables: Vec<_> = ALL_POLLEN
+                .iter()
+                .filter_map(|p| p.open_meteo_variable())
+                .collect();
+            let air_quality = open_meteo::fetch_air_quality(lat, lon, &variables).await?;
+            Ok(daily_max(&air_quality, date))
+        }
+        PollenProvider::Json => {
+            let Some(url) = config.url.as_ref() else {
+                bail!("The JSON pollen provider needs a url");
+            };
+            let url = url
+                .replace("{lat}", &lat.to_string())
+                .replace("{lon}", &lon.to_string())
+                .replace("{date}", &date.format("%Y-%m-%d").to_string());
+            let grains = metrics::fetch("pollen", async {
+                Ok(metrics::CLIENT
+                    .get(url)
+                    .send()
+                    .await?
+                    .error_for_status()?
+                    .json::<HashMap<String, f32>>()
+                    .await?)
+            })
+            .await?;
+            Ok(from_grains(&grains))
+        }
+    }
+}
+
+/// Highest amount of each pollen in the Open-Meteo forecast during the date
+fn daily_max(air_quality: &HourlyForecast, date: NaiveDate) -> Vec<(Pollen, f32)> {
+    ALL_POLLEN
+        .iter()
+        .filter_map(|&p| {
+            let grains = air_quality
+                .hourly
+                .daily_max(p.open_meteo_variable()?, date)?;
+            Some((p, grains))
+        })
+        .collect()
+}
+
+/// The pollen in a response of the JSON provider, ignoring any it doesn't know
+fn from_grains(grains: &HashMap<String, f32>) -> Vec<(Pollen, f32)> {
+    ALL_POLLEN
+        .iter()
+        .filter_map(|&p| Some((p, *grains.get(p.to_swedish())?)))
+        .collect()
+}
+
+/// E.g. "Pollen: björk höga halter, gräs måttliga halter", if any level is moderate or higher
+pub fn report(pollen: &[(Pollen, f32)]) -> Option<String> {
+    let mut notable: Vec<_> = pollen
+        .iter()
+        .map(|&(p, grains)| (p, p.level(grains)))
+        .filter(|&(_, level)| level >= PollenLevel::Moderate)
+        .collect();
+    if notable.is_empty() {
+        return None;
+    }
+    notable.sort_by_key(|&(_, level)| std::cmp::Reverse(level));
+    let levels: Vec<_> = notable
+        .iter()
+        .map(|(p, level)| format!("{} {}", p.to_swedish(), level.to_swedish()))
+        .collect();
+    Some(format!("Pollen: {}", levels.join(", ")))
+}
+
+impl PollenConfig {
+    pub fn in_season(&self, date: NaiveDate) -> bool {
+        self.months.contains(&date.month())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LUND: &str = include_str!("../tests/fixtures/pollen/lund.json");
+    const GRAINS: &str = include_str!("../tests/fixtures/pollen/grains.json");
+
+    fn open_meteo(date: &str) -> Vec<(Pollen, f32)> {
+        let air_quality: HourlyForecast = serde_json::from_str(LUND).unwrap();
+        daily_max(&air_quality, date.parse().unwrap())
+    }
+
+    #[test]
+    fn levels_at_the_thresholds() {
+        for (pollen, thresholds) in [
+            (Pollen::Hazel, [10.0, 50.0, 500.0]),
+            (Pollen::Alder, [10.0, 50.0, 500.0]),
+            (Pollen::Birch, [10.0, 100.0, 1000.0]),
+            (Pollen::Grass, [10.0, 30.0, 100.0]),
+            (Pollen::Mugwort, [10.0, 30.0, 100.0]),
+        ] {
+            let levels = [
+                PollenLevel::Moderate,
+                PollenLevel::High,
+                PollenLevel::VeryHigh,
+            ];
+            assert_eq!(pollen.level(0.0), PollenLevel::Low);
+            let mut below = PollenLevel::Low;
+            for (threshold, level) in thresholds.into_iter().zip(levels) {
+                assert_eq!(pollen.level(threshold - 0.1), below, "{:?}", pollen);
+                assert_eq!(pollen.level(threshold), level, "{:?}", pollen);
+                below = level;
+            }
+        }
+    }
+
+    #[test]
+    fn reads_the_daily_max_from_open_meteo() {
+        // Open-Meteo has no hazel, and no mugwort this early in the year
+        assert_eq!(
+            open_meteo("2024-04-10"),
+            vec![
+                (Pollen::Alder, 12.0),
+                (Pollen::Birch, 140.0),
+                (Pollen::Grass, 0.0),
+            ]
+        );
+        assert_eq!(
+            open_meteo("2024-04-11"),
+            vec![
+                (Pollen::Alder, 4.0),
+                (Pollen::Birch, 1200.0),
+                (Pollen::Grass, 9.9),
+            ]
+        );
+        assert_eq!(open_meteo("2024-04-12"), vec![]);
+    }
+
+    #[test]
+    fn reads_the_json_provider_by_swedish_name() {
+        let grains: HashMap<String, f32> = serde_json::from_str(GRAINS).unwrap();
+        assert_eq!(
+            from_grains(&grains),
+            vec![
+                (Pollen::Hazel, 55.0),
+                (Pollen::Alder, 9.9),
+                (Pollen::Birch, 100.0),
+                (Pollen::Grass, 0.0),
+            ]
+        );
+    }
+
+    #[test]
+    fn reports_the_highest_levels_first() {
+        assert_eq!(
+            report(&open_meteo("2024-04-10")).unwrap(),
+            "Pollen: björk höga halter, al måttliga halter"
+        );
+        assert_eq!(
+            report(&open_meteo("2024-04-11")).unwrap(),
+            "Pollen: björk mycket höga halter"
+        );
+        let grains: HashMap<String, f32> = serde_json::from_str(GRAINS).unwrap();
+        assert_eq!(
+            report(&from_grains(&grains)).unwrap(),
+            "Pollen: hassel höga halter, björk höga halter"
+        );
+        assert_eq!(report(&[(Pollen::Grass, 9.9)]), None);
+        assert_eq!(report(&[]), None);
+    }
+
+    #[test]
+    fn in_season_from_february() {
+        let config: PollenConfig = toml::from_str("").unwrap();
+        let in_season = |date: &str| config.in_season(date.parse().unwrap());
+        assert!(!in_season("2024-01-31"));
+        assert!(in_season("2024-02-01"));
+        assert!(in_season("2024-09-30"));
+        assert!(!in_season("2024-10-01"));
+        let config: PollenConfig = toml::from_str("months = [4, 5]").unwrap();
+        assert!(!config.in_season("2024-03-31".parse().unwrap()));
+        assert!(config.in_season("2024-05-31".parse().unwrap()));
+    }
+}
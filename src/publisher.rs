@@ -0,0 +1,551 @@
+//! Where posts are published
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{bail, Result};
+use async_trait::async_trait;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use tracing::{debug, error};
+
+use crate::activitypub::ActivityPub;
+use crate::bluesky::Bluesky;
+use crate::config::{Config, Location};
+use crate::mastodon::Mastodon;
+use crate::matrix::Matrix;
+use crate::metrics;
+use crate::telegram::Telegram;
+use crate::webhook::Webhook;
+
+/// Name of the publisher configured with `[mastodon]`
+pub const MASTODON: &str = "mastodon";
+
+/// Requests taking longer than this are abandoned
+pub const TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The response if the request succeeded, otherwise the error `E` from the
+/// service, or only the status if it sent something else
+pub async fn check<E: DeserializeOwned + fmt::Display>(
+    response: reqwest::Response,
+) -> Result<reqwest::Response> {
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+    match response.json::<E>().await {
+        Ok(e) => bail!("{}: {}", status, e),
+        Err(_) => bail!("{}", status),
+    }
+}
+
+/// A service the bot posts to
+#[async_trait]
+pub trait Publisher: Send + Sync {
+    /// Name in the config and the logs
+    fn name(&self) -> &str;
+    /// Check that posting will work, e.g. that the credentials are valid
+    async fn verify(&self) -> Result<()>;
+    /// Publish a post
+    async fn publish(&self, post: &str) -> Result<()>;
+}
+
+/// How markup in posts is written
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Markup {
+    Plain,
+    Markdown,
+    Html,
+}
+
+/// The Markdown a publisher understands, when posting with `Markup::Markdown`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MarkdownFlavor {
+    CommonMark,
+    /// Telegram's MarkdownV2, which reserves more characters and keeps line breaks
+    Telegram,
+}
+
+/// How posts are adapted to a publisher
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Formatting {
+    pub markup: Markup,
+    /// Longest post allowed, in characters
+    pub max_chars: Option<usize>,
+    pub markdown: MarkdownFlavor,
+}
+
+impl Formatting {
+    /// The post shortened to fit and with the markup escaped
+    pub fn apply(&self, post: &str) -> String {
+        let post = match self.max_chars {
+            Some(max) => shorten(post, max),
+            None => post.to_string(),
+        };
+        match self.markup {
+            Markup::Plain => post,
+            Markup::Html => escape_html(&post),
+            Markup::Markdown => match self.markdown {
+                MarkdownFlavor::CommonMark => post
+                    .lines()
+                    .map(escape_markdown)
+                    // Trailing spaces make a line break instead of joining the lines
+                    .collect::<Vec<_>>()
+                    .join("  \n"),
+                MarkdownFlavor::Telegram => escape_telegram_markdown(&post),
+            },
+        }
+    }
+}
+
+/// Drop lines from the end until the post fits, ending it with an ellipsis
+fn shorten(post: &str, max_chars: usize) -> String {
+    if post.chars().count() <= max_chars {
+        return post.to_string();
+    }
+    let mut lines: Vec<&str> = post.lines().collect();
+    while lines.len() > 1 && lines.join("\n").chars().count() + 1 > max_chars {
+        lines.pop();
+    }
+    let post = lines.join("\n");
+    let mut shortened: String = post.chars().take(max_chars.saturating_sub(1)).collect();
+    shortened.push('…');
+    shortened
+}
+
+pub fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+fn escape_markdown(line: &str) -> String {
+    let mut escaped = String::with_capacity(line.len());
+    for c in line.chars() {
+        if matches!(
+            c,
+            '\\' | '*' | '_' | '`' | '[' | ']' | '<' | '>' | '#' | '|' | '~'
+        ) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// Escape every character MarkdownV2 reserves, see
+/// https://core.telegram.org/bots/api#markdownv2-style
+fn escape_telegram_markdown(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if matches!(
+            c,
+            '\\' | '_'
+                | '*'
+                | '['
+                | ']'
+                | '('
+                | ')'
+                | '~'
+                | '`'
+                | '>'
+                | '#'
+                | '+'
+                | '-'
+                | '='
+                | '|'
+                | '{'
+                | '}'
+                | '.'
+                | '!'
+        ) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// A publisher besides Mastodon
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(try_from = "RawPublisherConfig")]
+pub struct PublisherConfig {
+    /// Referred to by the `publishers` of locations
+    pub name: String,
+    #[serde(flatten)]
+    pub kind: PublisherKind,
+    /// Overrides the default markup of the kind
+    #[serde(default)]
+    pub markup: Option<Markup>,
+    /// Overrides the default length limit of the kind
+    #[serde(default)]
+    pub max_chars: Option<usize>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum PublisherKind {
+    /// A Bluesky account, or any other AT Protocol service
+    Bluesky {
+        /// Handle or DID of the account
+        identifier: String,
+        /// An app password
+        password: String,
+        #[serde(default = "default_bluesky_service")]
+        service: String,
+    },
+    /// A Matrix room
+    Matrix {
+        homeserver: String,
+        room_id: String,
+        access_token: String,
+    },
+    /// A Telegram channel or chat
+    Telegram {
+        bot_token: String,
+        /// Id of the chat or @name of the channel
+        chat_id: String,
+        #[serde(default = "default_telegram_api")]
+        api_url: String,
+    },
+    /// Any HTTP endpoint accepting `{"text": "..."}`
+    Webhook {
+        url: String,
+        /// Extra headers, e.g. for authentication
+        #[serde(default)]
+        headers: BTreeMap<String, String>,
+    },
+}
+
+/// A publisher as written, with the fields of every kind. Flattening
+/// `PublisherKind` would hide unknown keys and the paths of errors.
+#[derive(Deserialize)]
+struct RawPublisherConfig {
+    name: String,
+    kind: KindName,
+    #[serde(default)]
+    markup: Option<Markup>,
+    #[serde(default)]
+    max_chars: Option<usize>,
+    identifier: Option<String>,
+    password: Option<String>,
+    service: Option<String>,
+    homeserver: Option<String>,
+    room_id: Option<String>,
+    access_token: Option<String>,
+    bot_token: Option<String>,
+    chat_id: Option<String>,
+    api_url: Option<String>,
+    url: Option<String>,
+    headers: Option<BTreeMap<String, String>>,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum KindName {
+    Bluesky,
+    Matrix,
+    Telegram,
+    Webhook,
+}
+
+impl TryFrom<RawPublisherConfig> for PublisherConfig {
+    type Error = String;
+
+    fn try_from(raw: RawPublisherConfig) -> std::result::Result<Self, Self::Error> {
+        let mut fields = BTreeMap::from([
+            ("identifier", raw.identifier),
+            ("password", raw.password),
+            ("service", raw.service),
+            ("homeserver", raw.homeserver),
+            ("room_id", raw.room_id),
+            ("access_token", raw.access_token),
+            ("bot_token", raw.bot_token),
+            ("chat_id", raw.chat_id),
+            ("api_url", raw.api_url),
+            ("url", raw.url),
+        ]);
+        let mut take = |field: &str| fields.remove(field).flatten();
+        let required = |value: Option<String>, field: &str| {
+            value.ok_or_else(|| format!("missing field `{}`", field))
+        };
+        let mut headers = raw.headers;
+        let kind = match raw.kind {
+            KindName::Bluesky => PublisherKind::Bluesky {
+                identifier: required(take("identifier"), "identifier")?,
+                password: required(take("password"), "password")?,
+                service: take("service").unwrap_or_else(default_bluesky_service),
+            },
+            KindName::Matrix => PublisherKind::Matrix {
+                homeserver: required(take("homeserver"), "homeserver")?,
+                room_id: required(take("room_id"), "room_id")?,
+                access_token: required(take("access_token"), "access_token")?,
+            },
+            KindName::Telegram => PublisherKind::Telegram {
+                bot_token: required(take("bot_token"), "bot_token")?,
+                chat_id: required(take("chat_id"), "chat_id")?,
+                api_url: take("api_url").unwrap_or_else(default_telegram_api),
+            },
+            KindName::Webhook => PublisherKind::Webhook {
+                url: required(take("url"), "url")?,
+                headers: headers.take().unwrap_or_default(),
+            },
+        };
+        let other = fields
+            .into_iter()
+            .find_map(|(field, value)| value.map(|_| field))
+            .or(headers.map(|_| "headers"));
+        if let Some(field) = other {
+            return Err(format!(
+                "`{}` isn't a setting of {} publishers",
+                field,
+                format!("{:?}", raw.kind).to_lowercase()
+            ));
+        }
+        Ok(Self {
+            name: raw.name,
+            kind,
+            markup: raw.markup,
+            max_chars: raw.max_chars,
+        })
+    }
+}
+
+fn default_bluesky_service() -> String {
+    "https://bsky.social".to_string()
+}
+
+fn default_telegram_api() -> String {
+    "https://api.telegram.org".to_string()
+}
+
+impl PublisherKind {
+    /// Formatting unless overridden, following the limits of each service
+    fn default_formatting(&self) -> Formatting {
+        let (markup, max_chars) = match self {
+            PublisherKind::Bluesky { .. } => (Markup::Plain, Some(300)),
+            PublisherKind::Matrix { .. } => (Markup::Html, None),
+            PublisherKind::Telegram { .. } => (Markup::Html, Some(4096)),
+            PublisherKind::Webhook { .. } => (Markup::Plain, None),
+        };
+        let markdown = match self {
+            PublisherKind::Telegram { .. } => MarkdownFlavor::Telegram,
+            _ => MarkdownFlavor::CommonMark,
+        };
+        Formatting {
+            markup,
+            max_chars,
+            markdown,
+        }
+    }
+
+    /// Urls which must be valid, by key
+    pub fn urls(&self) -> Vec<(&'static str, &str)> {
+        match self {
+            PublisherKind::Bluesky { service, .. } => vec![("service", service)],
+            PublisherKind::Matrix { homeserver, .. } => vec![("homeserver", homeserver)],
+            PublisherKind::Telegram { api_url, .. } => vec![("api_url", api_url)],
+            PublisherKind::Webhook { url, .. } => vec![("url", url)],
+        }
+    }
+}
+
+impl PublisherConfig {
+    pub fn formatting(&self) -> Formatting {
+        let default = self.kind.default_formatting();
+        Formatting {
+            markup: self.markup.unwrap_or(default.markup),
+            max_chars: self.max_chars.or(default.max_chars),
+            markdown: default.markdown,
+        }
+    }
+
+    fn build(&self) -> Result<Arc<dyn Publisher>> {
+        let name = self.name.clone();
+        let formatting = self.formatting();
+        Ok(match &self.kind {
+            PublisherKind::Bluesky {
+                identifier,
+                password,
+                service,
+            } => Arc::new(Bluesky::new(
+                name, formatting, service, identifier, password,
+            )?),
+            PublisherKind::Matrix {
+                homeserver,
+                room_id,
+                access_token,
+            } => Arc::new(Matrix::new(
+                name,
+                formatting,
+                homeserver,
+                room_id,
+                access_token,
+            )?),
+            PublisherKind::Telegram {
+                bot_token,
+                chat_id,
+                api_url,
+            } => Arc::new(Telegram::new(
+                name, formatting, api_url, bot_token, chat_id,
+            )?),
+            PublisherKind::Webhook { url, headers } => {
+                Arc::new(Webhook::new(name, formatting, url, headers)?)
+            }
+        })
+    }
+}
+
+/// All configured publishers
+#[derive(Clone, Default)]
+pub struct Publishers(Vec<Arc<dyn Publisher>>);
+
+impl Publishers {
+    /// Connect to every configured publisher, checking that posting will work
+    pub async fn connect(config: &Config) -> Result<Self> {
+        let mut publishers: Vec<Arc<dyn Publisher>> = Vec::new();
+        if let Some(mastodon) = config.mastodon.as_ref() {
+            let Some(data) = mastodon.credentials.as_ref() else {
+                bail!("No Mastodon credentials, only settings in [mastodon]");
+            };
+            publishers.push(Arc::new(Mastodon::new(data, mastodon.formatting())?));
+        }
+        if let Some(activitypub) = config.activitypub.as_ref() {
+            publishers.push(Arc::new(ActivityPub::new(activitypub)?));
+        }
+        for publisher in &config.publishers {
+            publishers.push(publisher.build()?);
+        }
+        if publishers.is_empty() {
+            bail!("No publishers configured, add [mastodon], [activitypub] or [[publishers]]");
+        }
+        for publisher in &publishers {
+            if let Err(e) = publisher.verify().await {
+                bail!("Error verifying {}: {}", publisher.name(), e);
+            }
+        }
+        Ok(Self(publishers))
+    }
+
+    /// The publishers posting for `location`, all of them unless it lists some
+    pub fn for_location<'a>(
+        &'a self,
+        location: &'a Location,
+    ) -> impl Iterator<Item = &'a Arc<dyn Publisher>> {
+        self.0.iter().filter(|publisher| {
+            location.publishers.is_empty()
+                || location
+                    .publishers
+                    .iter()
+                    .any(|name| name == publisher.name())
+        })
+    }
+
+    /// Publish the post for `location` everywhere it should go, failing only if
+    /// it couldn't be published anywhere
+    pub async fn publish(&self, location: &Location, post: &str) -> Result<()> {
+        let mut published = false;
+        let mut last_error = None;
+        for publisher in self.for_location(location) {
+            debug!("Publishing to {}", publisher.name());
+            let result = publisher.publish(post).await;
+            metrics::published(publisher.name(), &result);
+            match result {
+                Ok(()) => published = true,
+                Err(e) => {
+                    error!("Error publishing to {}: {}", publisher.name(), e);
+                    last_error = Some(e);
+                }
+            }
+        }
+        match last_error {
+            Some(e) if !published => Err(e),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const POST: &str =
+        "Lund: Det blir *sol* (mest).\nTemp min/max: -1/6°C\nVind: 5-8 m/s, byar [10] m/s!";
+
+    fn formatting(markdown: MarkdownFlavor) -> Formatting {
+        Formatting {
+            markup: Markup::Markdown,
+            max_chars: None,
+            markdown,
+        }
+    }
+
+    #[test]
+    fn escapes_commonmark() {
+        assert_eq!(
+            formatting(MarkdownFlavor::CommonMark).apply(POST),
+            "Lund: Det blir \\*sol\\* (mest).  \n\
+             Temp min/max: -1/6°C  \n\
+             Vind: 5-8 m/s, byar \\[10\\] m/s!"
+        );
+    }
+
+    #[test]
+    fn escapes_telegram_markdown() {
+        assert_eq!(
+            formatting(MarkdownFlavor::Telegram).apply(POST),
+            "Lund: Det blir \\*sol\\* \\(mest\\)\\.\n\
+             Temp min/max: \\-1/6°C\n\
+             Vind: 5\\-8 m/s, byar \\[10\\] m/s\\!"
+        );
+        assert_eq!(
+            escape_telegram_markdown("a+b=c {x} #1 > ~_`|\\"),
+            "a\\+b\\=c \\{x\\} \\#1 \\> \\~\\_\\`\\|\\\\"
+        );
+    }
+
+    #[test]
+    fn shortens_from_the_last_line() {
+        let post = "Lund: Sol\nVarning: Kuling\nSoluppgång 05:12";
+        assert_eq!(shorten(post, 30), "Lund: Sol\nVarning: Kuling…");
+        assert_eq!(shorten(post, 100), post);
+    }
+
+    #[test]
+    fn telegram_defaults_to_its_own_markdown() {
+        let config: PublisherConfig = toml::from_str(
+            "name = \"t\"\nkind = \"telegram\"\nbot_token = \"x\"\nchat_id = \"@c\"\nmarkup = \"markdown\"",
+        )
+        .unwrap();
+        assert_eq!(
+            config.formatting(),
+            Formatting {
+                max_chars: Some(4096),
+                ..formatting(MarkdownFlavor::Telegram)
+            }
+        );
+    }
+
+    #[test]
+    fn takes_only_the_fields_of_the_kind() {
+        let parse = |toml: &str| {
+            toml::from_str::<PublisherConfig>(&format!(
+                "name = \"w\"\nkind = \"webhook\"\n{}",
+                toml
+            ))
+            .map_err(|e| e.message().to_string())
+        };
+        let config = parse("url = \"https://example.com\"\nheaders = { A = \"b\" }").unwrap();
+        assert!(matches!(
+            config.kind,
+            PublisherKind::Webhook { url, headers } if url == "https://example.com" && headers.len() == 1
+        ));
+        assert_eq!(parse("").unwrap_err(), "missing field `url`");
+        assert_eq!(
+            parse("url = \"https://example.com\"\nchat_id = \"@c\"").unwrap_err(),
+            "`chat_id` isn't a setting of webhook publishers"
+        );
+    }
+}
@@ -0,0 +1,193 @@
+//! Registering the bot as an app on a Mastodon instance
+use std::fs::OpenOptions;
+use std::io::{BufRead, Write};
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Result};
+use elefren::scopes::{Read, Scopes, Write as WriteScope};
+use elefren::Registration;
+use toml_edit::{DocumentMut, Item};
+
+use crate::config::{self, CONFIG_FILE};
+use crate::loader;
+use crate::secrets::{Secrets, OOB_REDIRECT};
+
+/// Register the app on the instance and authorize it, asking the user for the code
+pub fn register(config: Option<&Path>, instance: &str, in_config: bool, force: bool) -> Result<()> {
+    let config_path = loader::find(config).unwrap_or_else(|_| PathBuf::from(CONFIG_FILE));
+    let secrets_path = config::secrets_path(&config_path);
+    let config = match std::fs::read_to_string(&config_path) {
+        Ok(config) => config,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(e.into()),
+    };
+    let in_config_already = has_credentials(&config)?;
+    if !in_config && in_config_already {
+        bail!(
+            "{} has Mastodon credentials, which are used instead of those in {}. \
+             Use --in-config to replace them",
+            config_path.display(),
+            secrets_path.display()
+        );
+    }
+    if !force {
+        let existing = if in_config {
+            in_config_already
+        } else {
+            Secrets::load(&secrets_path)?.mastodon.is_some()
+        };
+        if existing {
+            bail!("There already are Mastodon credentials, use --force to replace them");
+        }
+    }
+    let registration = Registration::new(instance)
+        .client_name("vader_bot")
+        .redirect_uris(OOB_REDIRECT)
+        .scopes(Scopes::read(Read::Accounts).and(Scopes::write(WriteScope::Statuses)))
+        .build();
+    let registration = match registration {
+        Ok(registration) => registration,
+        Err(e) => bail!("Error registering the app: {}", e),
+    };
+    let url = match registration.authorize_url() {
+        Ok(url) => url,
+        Err(e) => bail!("Error creating the authorization url: {}", e),
+    };
+    println!(
+        "Open this url, log in as the bot and authorize the app:\n\n{}\n",
+        url
+    );
+    print!("Paste the code here: ");
+    std::io::stdout().flush()?;
+    let mut code = String::new();
+    std::io::stdin().lock().read_line(&mut code)?;
+    let masto = match registration.complete(code.trim()) {
+        Ok(masto) => masto,
+        Err(e) => bail!("Error authorizing the app: {}", e),
+    };
+    let secrets = toml::to_string(&Secrets {
+        mastodon: Some(masto.data.clone()),
+    })?;
+    if in_config {
+        write_private(&config_path, &with_mastodon(&config, &secrets)?)?;
+        println!("Saved the credentials to {}", config_path.display());
+    } else {
+        write_private(&secrets_path, &secrets)?;
+        println!("Saved the credentials to {}", secrets_path.display());
+    }
+    Ok(())
+}
+
+/// Whether `[mastodon]` in the config has credentials, not only settings
+fn has_credentials(config: &str) -> Result<bool> {
+    let config = toml::from_str::<toml::Table>(config)?;
+    Ok(config
+        .get("mastodon")
+        .and_then(toml::Value::as_table)
+        .is_some_and(|mastodon| mastodon.keys().any(|key| key != "max_chars")))
+}
+
+/// The config with `[mastodon]` from the secrets, replacing any there was
+/// while keeping the rest of the file as it was written
+fn with_mastodon(config: &str, secrets: &str) -> Result<String> {
+    let mut config: DocumentMut = config.parse()?;
+    let secrets: DocumentMut = secrets.parse()?;
+    let Some(mut mastodon) = secrets.get("mastodon").and_then(Item::as_table).cloned() else {
+        bail!("No Mastodon credentials to save");
+    };
+    match config.get_mut("mastodon").and_then(Item::as_table_mut) {
+        Some(existing) => {
+            // Keep the comments above the table and its place in the file
+            *mastodon.decor_mut() = existing.decor().clone();
+            mastodon.set_position(existing.position().unwrap_or_default());
+            if let Some(max_chars) = existing.get("max_chars") {
+                mastodon.insert("max_chars", max_chars.clone());
+            }
+            *existing = mastodon;
+        }
+        None => {
+            config.remove("mastodon");
+            if !config.to_string().trim().is_empty() {
+                mastodon.decor_mut().set_prefix("\n");
+            }
+            config.insert("mastodon", Item::Table(mastodon));
+        }
+    }
+    Ok(config.to_string())
+}
+
+/// Write a file only the owner can read
+pub fn write_private(path: &Path, contents: &str) -> Result<()> {
+    let mut options = OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+        options.mode(0o600);
+        // The mode only applies to new files
+        if path.exists() {
+            std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600))?;
+        }
+    }
+    options.open(path)?.write_all(contents.as_bytes())?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECRETS: &str = "[mastodon]\nbase = \"https://new.example\"\ntoken = \"new\"\n";
+
+    #[test]
+    fn replaces_only_the_mastodon_table() {
+        let config = "\
+# Where to post
+[[locations]]
+name = \"Lund\" # home
+
+# The bot's account
+[mastodon]
+token = \"old\"
+base = \"https://old.example\"
+max_chars = 1000
+
+[forecast]
+time = \"20:00\"
+";
+        assert_eq!(
+            with_mastodon(config, SECRETS).unwrap(),
+            "\
+# Where to post
+[[locations]]
+name = \"Lund\" # home
+
+# The bot's account
+[mastodon]
+base = \"https://new.example\"
+token = \"new\"
+max_chars = 1000
+
+[forecast]
+time = \"20:00\"
+"
+        );
+    }
+
+    #[test]
+    fn appends_a_missing_mastodon_table() {
+        let config = "# Where to post\n[[locations]]\nname = \"Lund\"\n";
+        assert_eq!(
+            with_mastodon(config, SECRETS).unwrap(),
+            format!("{}\n{}", config, SECRETS)
+        );
+        assert_eq!(with_mastodon("", SECRETS).unwrap(), SECRETS);
+    }
+
+    #[test]
+    fn finds_credentials_in_the_config() {
+        assert!(has_credentials("[mastodon]\ntoken = \"old\"\n").unwrap());
+        assert!(!has_credentials("[mastodon]\nmax_chars = 1000\n").unwrap());
+        assert!(!has_credentials("location = \"Lund\"\n").unwrap());
+    }
+}
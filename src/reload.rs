@@ -0,0 +1,148 @@
+//! Reloading the config on SIGHUP or when the config files change
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use anyhow::Result;
+use notify::{EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+#[cfg(unix)]
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::mpsc;
+use tokio::time::Instant;
+use tracing::{debug, info, warn};
+
+use crate::config::SECRETS_FILE;
+
+/// Time to wait for more changes, since editors often write a file in several steps
+const DEBOUNCE: Duration = Duration::from_millis(500);
+
+/// Requests to reload the config
+pub struct Reloads {
+    receiver: mpsc::Receiver<()>,
+    /// When to reload after a request, kept here so that dropping `next` while
+    /// it waits doesn't lose the request
+    pending: Option<Instant>,
+    // Stops watching when dropped
+    _watcher: Option<RecommendedWatcher>,
+}
+
+impl Reloads {
+    /// Watch for SIGHUP and changes to the config file at `config`, its drop-ins
+    /// and the secrets file
+    pub fn watch(config: &Path) -> Result<Self> {
+        // A single slot is enough, since one reload covers all changes before it
+        let (sender, receiver) = mpsc::channel(1);
+        #[cfg(unix)]
+        {
+            let mut hangups = signal(SignalKind::hangup())?;
+            let sender = sender.clone();
+            tokio::spawn(async move {
+                while hangups.recv().await.is_some() {
+                    info!("Got SIGHUP");
+                    let _ = sender.try_send(());
+                }
+            });
+        }
+        let watcher = match watch_files(config, sender) {
+            Ok(watcher) => Some(watcher),
+            Err(e) => {
+                warn!("Not watching the config files, reload with SIGHUP: {}", e);
+                None
+            }
+        };
+        Ok(Self {
+            receiver,
+            pending: None,
+            _watcher: watcher,
+        })
+    }
+
+    /// Wait until the config should be reloaded. Safe to cancel, e.g. in
+    /// `tokio::select!`: a request that was already received is kept.
+    pub async fn next(&mut self) {
+        let deadline = match self.pending {
+            Some(deadline) => deadline,
+            None => {
+                if self.receiver.recv().await.is_none() {
+                    // Nothing can send any more requests
+                    std::future::pending::<()>().await;
+                }
+                *self.pending.insert(Instant::now() + DEBOUNCE)
+            }
+        };
+        tokio::time::sleep_until(deadline).await;
+        self.pending = None;
+        while self.receiver.try_recv().is_ok() {}
+    }
+}
+
+fn watch_files(config: &Path, sender: mpsc::Sender<()>) -> notify::Result<RecommendedWatcher> {
+    let dir = match config.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir.to_path_buf(),
+        _ => PathBuf::from("."),
+    };
+    // The watcher reports absolute paths
+    let dir = std::path::absolute(&dir).unwrap_or(dir);
+    let drop_ins = dir.join("conf.d");
+    let names = [
+        config.file_name().map(|name| name.to_owned()),
+        Some(SECRETS_FILE.into()),
+    ];
+    let watched_drop_ins = drop_ins.clone();
+    let mut watcher = notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+        let Ok(event) = event else {
+            return;
+        };
+        if !matches!(
+            event.kind,
+            EventKind::Create(_) | EventKind::Modify(_) | EventKind::Remove(_)
+        ) {
+            return;
+        }
+        let relevant = event.paths.iter().any(|path| {
+            let in_drop_ins = path.parent() == Some(watched_drop_ins.as_path())
+                && path.extension().is_some_and(|ext| ext == "toml");
+            in_drop_ins
+                || names
+                    .iter()
+                    .flatten()
+                    .any(|name| path.file_name() == Some(name))
+        });
+        if relevant {
+            debug!("Config changed: {:?}", event.paths);
+            let _ = sender.try_send(());
+        }
+    })?;
+    // Watching the directories catches editors replacing the files
+    watcher.watch(&dir, RecursiveMode::NonRecursive)?;
+    if drop_ins.is_dir() {
+        watcher.watch(&drop_ins, RecursiveMode::NonRecursive)?;
+    }
+    Ok(watcher)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn keeps_a_request_when_dropped_while_debouncing() {
+        let (sender, receiver) = mpsc::channel(1);
+        let mut reloads = Reloads {
+            receiver,
+            pending: None,
+            _watcher: None,
+        };
+        sender.send(()).await.unwrap();
+        // Dropped in the debounce sleep, as when another branch of a select wins
+        assert!(tokio::time::timeout(DEBOUNCE / 5, reloads.next())
+            .await
+            .is_err());
+        assert!(tokio::time::timeout(DEBOUNCE * 2, reloads.next())
+            .await
+            .is_ok());
+        // Nothing more to reload
+        assert!(tokio::time::timeout(DEBOUNCE * 2, reloads.next())
+            .await
+            .is_err());
+    }
+}
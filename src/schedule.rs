@@ -0,0 +1,142 @@
+//! When to post
+use chrono::{DateTime, Datelike, Duration, Local, NaiveTime, TimeZone, Weekday};
+use serde::{Deserialize, Serialize};
+use tracing::debug;
+
+/// A time of day, optionally restricted to some weekdays
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Schedule {
+    /// Local time, "HH:MM"
+    #[serde(with = "hh_mm")]
+    pub time: NaiveTime,
+    /// Days to post on, every day if empty
+    #[serde(default)]
+    pub weekdays: Vec<Weekday>,
+}
+
+impl Schedule {
+    pub fn daily(hour: u32, minute: u32) -> Self {
+        Self {
+            time: NaiveTime::from_hms_opt(hour, minute, 0).expect("valid time"),
+            weekdays: Vec::new(),
+        }
+    }
+
+    /// The first scheduled time after `now`, in the time zone of `now`
+    pub fn next_after<Tz: TimeZone>(&self, now: DateTime<Tz>) -> DateTime<Tz> {
+        let mut date = now.date_naive();
+        loop {
+            if self.weekdays.is_empty() || self.weekdays.contains(&date.weekday()) {
+                // Skip times that don't exist due to daylight saving time
+                if let Some(time) = now
+                    .timezone()
+                    .from_local_datetime(&date.and_time(self.time))
+                    .earliest()
+                {
+                    if time > now {
+                        return time;
+                    }
+                }
+            }
+            date += Duration::days(1);
+        }
+    }
+}
+
+/// Sleep until the given time
+pub async fn sleep_until(time: DateTime<Local>) {
+    let duration = (time - Local::now()).to_std().unwrap_or_default();
+    debug!("Waiting for {} seconds", duration.as_secs());
+    tokio::time::sleep(duration).await
+}
+
+pub(crate) mod hh_mm {
+    use chrono::NaiveTime;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    const FORMAT: &str = "%H:%M";
+
+    pub fn serialize<S: Serializer>(time: &NaiveTime, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&time.format(FORMAT).to_string())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<NaiveTime, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        NaiveTime::parse_from_str(&s, FORMAT).map_err(|e| {
+            serde::de::Error::custom(format!("invalid time \"{}\", expected HH:MM: {}", s, e))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono_tz::Europe::Stockholm;
+
+    use super::*;
+
+    fn at(time: &str) -> DateTime<chrono_tz::Tz> {
+        Stockholm
+            .from_local_datetime(&time.parse().unwrap())
+            .unwrap()
+    }
+
+    fn schedule(time: &str, weekdays: &[Weekday]) -> Schedule {
+        Schedule {
+            time: NaiveTime::parse_from_str(time, "%H:%M").unwrap(),
+            weekdays: weekdays.to_vec(),
+        }
+    }
+
+    #[test]
+    fn next_after_a_time_at_or_before_now() {
+        let evening = schedule("20:00", &[]);
+        assert_eq!(
+            evening.next_after(at("2024-06-12T19:59:59")),
+            at("2024-06-12T20:00:00")
+        );
+        assert_eq!(
+            evening.next_after(at("2024-06-12T20:00:00")),
+            at("2024-06-13T20:00:00")
+        );
+        assert_eq!(
+            evening.next_after(at("2024-06-12T23:00:00")),
+            at("2024-06-13T20:00:00")
+        );
+    }
+
+    #[test]
+    fn next_after_on_the_weekdays() {
+        // Wednesday the 12th, on Sunday evenings
+        let weekly = schedule("18:00", &[Weekday::Sun]);
+        assert_eq!(
+            weekly.next_after(at("2024-06-12T12:00:00")),
+            at("2024-06-16T18:00:00")
+        );
+        assert_eq!(
+            weekly.next_after(at("2024-06-16T18:00:00")),
+            at("2024-06-23T18:00:00")
+        );
+        let weekend = schedule("09:00", &[Weekday::Sat, Weekday::Sun]);
+        assert_eq!(
+            weekend.next_after(at("2024-06-15T09:30:00")),
+            at("2024-06-16T09:00:00")
+        );
+    }
+
+    #[test]
+    fn skips_times_which_daylight_saving_time_skips() {
+        // 02:30 doesn't exist on the last Sunday of March
+        let night = schedule("02:30", &[]);
+        let next = night.next_after(at("2024-03-30T03:00:00"));
+        assert_eq!(next, at("2024-04-01T02:30:00"));
+        let sundays = schedule("02:30", &[Weekday::Sun]);
+        assert_eq!(
+            sundays.next_after(at("2024-03-30T03:00:00")),
+            at("2024-04-07T02:30:00")
+        );
+        // and exists twice on the last Sunday of October, posting the first
+        let next = night.next_after(at("2024-10-27T00:00:00"));
+        assert_eq!(next.naive_local(), "2024-10-27T02:30:00".parse().unwrap());
+        assert_eq!(next.offset().to_string(), "CEST");
+    }
+}
@@ -0,0 +1,253 @@
+//! Seasonal notices, such as the first night frost and the first snow
+use chrono::{Datelike, NaiveDate};
+
+use crate::config::Location;
+use crate::state::LocationState;
+use crate::vader::{Hourly, Weather};
+
+/// Chance of frost, in percent, from which we consider it a frosty night
+const FROST_CHANCE: u8 = 50;
+
+/// The season a date belongs to, named after the year it starts in.
+/// Seasons start in July so that a winter is not split in two.
+pub fn season(date: NaiveDate) -> i32 {
+    if date.month() >= 7 {
+        date.year()
+    } else {
+        date.year() - 1
+    }
+}
+
+fn hour(hourly: &Hourly) -> u16 {
+    hourly.time.parse::<u16>().unwrap_or_default() / 100
+}
+
+/// Lowest night temperature if there will be frost during the night from
+/// the `evening` before into the `morning`
+pub fn night_frost(evening: &Weather, morning: &Weather) -> Option<i32> {
+    let night = evening
+        .hourly
+        .iter()
+        .filter(|h| hour(h) >= 21)
+        .chain(morning.hourly.iter().filter(|h| hour(h) <= 6));
+    let mut frost = false;
+    let mut min_temp: Option<i32> = None;
+    for hourly in night {
+        let temp = hourly.temp_c.parse::<i32>().ok();
+        let chance = hourly.chance_of_frost.parse::<u8>().unwrap_or_default();
+        if chance >= FROST_CHANCE || matches!(temp, Some(t) if t <= 0) {
+            frost = true;
+        }
+        min_temp = match (min_temp, temp) {
+            (Some(min), Some(t)) => Some(min.min(t)),
+            (min, t) => min.or(t),
+        };
+    }
+    frost.then(|| min_temp.unwrap_or_default())
+}
+
+/// Amount of snow, if any
+pub fn snow(weather: &Weather) -> Option<f32> {
+    weather
+        .total_snow_cm
+        .parse::<f32>()
+        .ok()
+        .filter(|&snow| snow > 0.0)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    FirstFrost,
+    FirstSnow,
+}
+
+/// A notice to post, which is only marked as posted once it is
+#[derive(Debug)]
+pub struct Notice {
+    kind: Kind,
+    season: i32,
+    pub text: String,
+}
+
+impl Notice {
+    /// Don't post the notice again this season
+    pub fn posted(&self, state: &mut LocationState) {
+        match self.kind {
+            Kind::FirstFrost => state.first_frost_season = Some(self.season),
+            Kind::FirstSnow => state.first_snow_season = Some(self.season),
+        }
+    }
+}
+
+/// Notices for the season's first frost and snow on the day after `previous`,
+/// not yet posted for the location
+pub fn notices(
+    location: &Location,
+    previous: &Weather,
+    weather: &Weather,
+    state: &LocationState,
+) -> Vec<Notice> {
+    let mut notices = Vec::new();
+    let Ok(date) = NaiveDate::parse_from_str(&weather.date, "%Y-%m-%d") else {
+        return notices;
+    };
+    let season = season(date);
+    if location.first_frost_notice && state.first_frost_season != Some(season) {
+        if let Some(temp) = night_frost(previous, weather) {
+            notices.push(Notice {
+                kind: Kind::FirstFrost,
+                season,
+                text: format!(
+                    "Säsongens första nattfrost väntas i {} natten mot {}, ner mot {}°C. \
+                 Dags att skydda känsliga växter!",
+                    location.name,
+                    date.format("%d/%m"),
+                    temp
+                ),
+            });
+        }
+    }
+    if location.first_snow_notice && state.first_snow_season != Some(season) {
+        if let Some(snow) = snow(weather) {
+            notices.push(Notice {
+                kind: Kind::FirstSnow,
+                season,
+                text: format!(
+                    "Säsongens första snö väntas i {} den {}, {}cm.",
+                    location.name,
+                    date.format("%d/%m"),
+                    snow
+                ),
+            });
+        }
+    }
+    notices
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vader::testing::weather;
+
+    /// A day with the temperature and chance of frost every three hours
+    fn day(date: &str, hours: [(&str, &str); 8]) -> Weather {
+        let hours: Vec<[(&str, &str); 3]> = hours
+            .iter()
+            .enumerate()
+            .map(|(i, &(temp, frost))| {
+                [
+                    (
+                        "time",
+                        ["0", "300", "600", "900", "1200", "1500", "1800", "2100"][i],
+                    ),
+                    ("tempC", temp),
+                    ("chanceoffrost", frost),
+                ]
+            })
+            .collect();
+        let hours: Vec<&[(&str, &str)]> = hours.iter().map(|hour| &hour[..]).collect();
+        weather(date, &[], &hours)
+    }
+
+    const MILD: [(&str, &str); 8] = [("5", "0"); 8];
+
+    fn frosty_location() -> Location {
+        Location {
+            first_frost_notice: true,
+            first_snow_notice: true,
+            ..Location::new("Kiruna")
+        }
+    }
+
+    #[test]
+    fn finds_frost_after_midnight() {
+        let evening = day("2023-10-01", MILD);
+        let mut hours = MILD;
+        hours[1] = ("-2", "0");
+        // Only the night counts, not the cold morning after 06
+        let mut late = MILD;
+        late[3] = ("-4", "0");
+        assert_eq!(night_frost(&evening, &day("2023-10-02", hours)), Some(-2));
+        assert_eq!(night_frost(&evening, &day("2023-10-02", late)), None);
+    }
+
+    #[test]
+    fn finds_frost_before_midnight() {
+        let mut hours = MILD;
+        hours[7] = ("-1", "0");
+        assert_eq!(
+            night_frost(&day("2023-10-01", hours), &day("2023-10-02", MILD)),
+            Some(-1)
+        );
+    }
+
+    #[test]
+    fn finds_frost_by_its_chance_alone() {
+        let mut hours = MILD;
+        hours[0] = ("1", "50");
+        assert_eq!(
+            night_frost(&day("2023-10-01", MILD), &day("2023-10-02", hours)),
+            Some(1)
+        );
+        hours[0] = ("1", "49");
+        assert_eq!(
+            night_frost(&day("2023-10-01", MILD), &day("2023-10-02", hours)),
+            None
+        );
+    }
+
+    #[test]
+    fn posts_a_notice_once_a_season() {
+        let mut hours = MILD;
+        hours[1] = ("-3", "80");
+        let previous = day("2023-10-01", MILD);
+        let weather = day("2023-10-02", hours);
+        let mut state = LocationState::default();
+        let found = notices(&frosty_location(), &previous, &weather, &state);
+        assert_eq!(found.len(), 1);
+        assert_eq!(
+            found[0].text,
+            "Säsongens första nattfrost väntas i Kiruna natten mot 02/10, ner mot -3°C. \
+             Dags att skydda känsliga växter!"
+        );
+        // Until posted it's tried again
+        assert_eq!(
+            notices(&frosty_location(), &previous, &weather, &state).len(),
+            1
+        );
+        found[0].posted(&mut state);
+        assert_eq!(state.first_frost_season, Some(2023));
+        assert!(notices(&frosty_location(), &previous, &weather, &state).is_empty());
+        // The next season it's first again
+        let next_season = day("2024-09-15", hours);
+        assert_eq!(
+            notices(&frosty_location(), &previous, &next_season, &state).len(),
+            1
+        );
+    }
+
+    #[test]
+    fn posts_the_first_snow() {
+        let weather = weather("2023-11-20", &[("totalSnow_cm", "2.5")], &[]);
+        let mut state = LocationState::default();
+        let found = notices(&frosty_location(), &weather, &weather, &state);
+        assert_eq!(found.len(), 1);
+        assert_eq!(
+            found[0].text,
+            "Säsongens första snö väntas i Kiruna den 20/11, 2.5cm."
+        );
+        found[0].posted(&mut state);
+        assert_eq!(state.first_snow_season, Some(2023));
+        assert!(notices(&frosty_location(), &weather, &weather, &state).is_empty());
+    }
+
+    #[test]
+    fn starts_seasons_in_july() {
+        let date = |s| NaiveDate::parse_from_str(s, "%Y-%m-%d").unwrap();
+        assert_eq!(season(date("2023-06-30")), 2022);
+        assert_eq!(season(date("2023-07-01")), 2023);
+        assert_eq!(season(date("2023-12-31")), 2023);
+        assert_eq!(season(date("2024-01-01")), 2023);
+        assert_eq!(season(date("2024-06-30")), 2023);
+    }
+}
@@ -0,0 +1,218 @@
+//! Mastodon credentials, layered from the environment and the config files
+//!
+//! Each field of the `[mastodon]` table is taken from the first of
+//! 1. the variable `VADER_MASTODON_<FIELD>`, e.g. `VADER_MASTODON_TOKEN`
+//! 2. the file named by `VADER_MASTODON_<FIELD>_FILE`, e.g. a Docker secret
+//! 3. the systemd credential `vader_mastodon_<field>` in `$CREDENTIALS_DIRECTORY`
+//! 4. the config files
+//! 5. `secrets.toml` next to the config file
+use std::borrow::Cow;
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use elefren::data::Data;
+use serde::{Deserialize, Serialize};
+
+use crate::files;
+
+const ENV_PREFIX: &str = "VADER_MASTODON_";
+/// Out of band redirect, the instance shows the code for the user to copy
+pub const OOB_REDIRECT: &str = "urn:ietf:wg:oauth:2.0:oob";
+
+/// Credentials kept apart from the rest of the config
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Secrets {
+    #[serde(default)]
+    pub mastodon: Option<Data>,
+}
+
+impl Secrets {
+    /// Load the secrets, if there are any
+    pub fn load(path: &Path) -> Result<Self> {
+        files::load_or_default(path).with_context(|| format!("Failed to read {}", path.display()))
+    }
+}
+
+/// The credentials from the config with the other sources layered on top
+pub fn mastodon(from_config: Option<Data>, secrets: &Path) -> Result<Option<Data>> {
+    layered(from_config, secrets, |var| std::env::var(var).ok())
+}
+
+/// The credentials with the variables from `env` layered on top
+fn layered(
+    from_config: Option<Data>,
+    secrets: &Path,
+    env: impl Fn(&str) -> Option<String>,
+) -> Result<Option<Data>> {
+    let mut data = match from_config {
+        Some(data) => Some(data),
+        None => Secrets::load(secrets)?.mastodon,
+    };
+    for field in ["base", "client_id", "client_secret", "redirect", "token"] {
+        let Some(value) = from_env(field, &env)? else {
+            continue;
+        };
+        let data = data.get_or_insert(Data {
+            base: Cow::Borrowed(""),
+            client_id: Cow::Borrowed(""),
+            client_secret: Cow::Borrowed(""),
+            redirect: Cow::Borrowed(OOB_REDIRECT),
+            token: Cow::Borrowed(""),
+        });
+        let value = Cow::Owned(value);
+        match field {
+            "base" => data.base = value,
+            "client_id" => data.client_id = value,
+            "client_secret" => data.client_secret = value,
+            "redirect" => data.redirect = value,
+            _ => data.token = value,
+        }
+    }
+    Ok(data)
+}
+
+/// A field from the environment, a file it points to or a systemd credential
+fn from_env(field: &str, env: impl Fn(&str) -> Option<String>) -> Result<Option<String>> {
+    let var = format!("{}{}", ENV_PREFIX, field.to_uppercase());
+    if let Some(value) = env(&var) {
+        return Ok(Some(value));
+    }
+    let file_var = format!("{}_FILE", var);
+    if let Some(path) = env(&file_var) {
+        return read_secret(Path::new(&path))
+            .with_context(|| format!("Failed to read {} from {}", file_var, path))
+            .map(Some);
+    }
+    if let Some(dir) = env("CREDENTIALS_DIRECTORY") {
+        let path = Path::new(&dir).join(var.to_lowercase());
+        if path.exists() {
+            return read_secret(&path)
+                .with_context(|| format!("Failed to read the credential {}", path.display()))
+                .map(Some);
+        }
+    }
+    Ok(None)
+}
+
+/// Read a secret from a file, without the trailing newline most tools add
+fn read_secret(path: &Path) -> Result<String> {
+    let secret = std::fs::read_to_string(path)?;
+    Ok(secret.trim_end_matches(['\r', '\n']).to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::path::PathBuf;
+
+    use super::*;
+
+    fn data(base: &str, token: &str) -> Data {
+        Data {
+            base: Cow::Owned(base.to_string()),
+            client_id: Cow::Borrowed("id"),
+            client_secret: Cow::Borrowed("secret"),
+            redirect: Cow::Borrowed(OOB_REDIRECT),
+            token: Cow::Owned(token.to_string()),
+        }
+    }
+
+    /// A directory of its own for each test, since they run in parallel
+    fn dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("vader_bot-secrets-{}-{}", name, std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn layered_with(
+        from_config: Option<Data>,
+        secrets: &Path,
+        vars: &[(&str, &str)],
+    ) -> Option<Data> {
+        let vars: HashMap<String, String> = vars
+            .iter()
+            .map(|&(var, value)| (var.to_string(), value.to_string()))
+            .collect();
+        layered(from_config, secrets, |var| vars.get(var).cloned()).unwrap()
+    }
+
+    #[test]
+    fn overrides_one_field_of_the_config() {
+        let data = layered_with(
+            Some(data("https://config.example", "config")),
+            Path::new("/nonexistent/secrets.toml"),
+            &[("VADER_MASTODON_TOKEN", "env")],
+        )
+        .unwrap();
+        assert_eq!(data.base, "https://config.example");
+        assert_eq!(data.client_id, "id");
+        assert_eq!(data.token, "env");
+    }
+
+    #[test]
+    fn reads_files_without_the_trailing_newline() {
+        let dir = dir("file");
+        let path = dir.join("token");
+        std::fs::write(&path, "from-file\n").unwrap();
+        let data = layered_with(
+            None,
+            &dir.join("secrets.toml"),
+            &[
+                ("VADER_MASTODON_BASE", "https://env.example"),
+                ("VADER_MASTODON_TOKEN_FILE", path.to_str().unwrap()),
+            ],
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+        let data = data.unwrap();
+        assert_eq!(data.base, "https://env.example");
+        assert_eq!(data.token, "from-file");
+        assert_eq!(data.redirect, OOB_REDIRECT);
+    }
+
+    #[test]
+    fn reads_systemd_credentials() {
+        let dir = dir("credentials");
+        std::fs::write(dir.join("vader_mastodon_token"), "credential\r\n").unwrap();
+        let from_credentials = layered_with(
+            Some(data("https://config.example", "config")),
+            &dir.join("secrets.toml"),
+            &[("CREDENTIALS_DIRECTORY", dir.to_str().unwrap())],
+        );
+        // The variables come before the credentials
+        let from_env = layered_with(
+            None,
+            &dir.join("secrets.toml"),
+            &[
+                ("CREDENTIALS_DIRECTORY", dir.to_str().unwrap()),
+                ("VADER_MASTODON_TOKEN", "env"),
+            ],
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(from_credentials.unwrap().token, "credential");
+        assert_eq!(from_env.unwrap().token, "env");
+    }
+
+    #[test]
+    fn prefers_the_config_to_the_secrets_file() {
+        let dir = dir("file-order");
+        let secrets = dir.join("secrets.toml");
+        std::fs::write(
+            &secrets,
+            toml::to_string(&Secrets {
+                mastodon: Some(data("https://secrets.example", "secrets")),
+            })
+            .unwrap(),
+        )
+        .unwrap();
+        let from_config = layered_with(
+            Some(data("https://config.example", "config")),
+            &secrets,
+            &[],
+        );
+        let from_secrets = layered_with(None, &secrets, &[]);
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(from_config.unwrap().token, "config");
+        assert_eq!(from_secrets.unwrap().token, "secrets");
+    }
+}
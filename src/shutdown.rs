@@ -0,0 +1,53 @@
+//! Stopping cleanly on SIGTERM or SIGINT
+use std::time::Duration;
+
+use anyhow::Result;
+#[cfg(unix)]
+use tokio::signal::unix::{signal, SignalKind};
+
+/// How long a post in progress gets to finish before it's abandoned
+pub const GRACE_PERIOD: Duration = Duration::from_secs(15);
+
+/// Signals asking the bot to stop
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Signal {
+    Terminate,
+    Interrupt,
+}
+
+impl Signal {
+    /// Exit code after stopping, 128 plus the signal number like a shell
+    pub fn exit_code(self) -> i32 {
+        match self {
+            Signal::Terminate => 128 + 15,
+            Signal::Interrupt => 128 + 2,
+        }
+    }
+}
+
+/// Listens for the signals asking the bot to stop
+pub struct Shutdown {
+    #[cfg(unix)]
+    terminate: tokio::signal::unix::Signal,
+}
+
+impl Shutdown {
+    pub fn listen() -> Result<Self> {
+        Ok(Self {
+            #[cfg(unix)]
+            terminate: signal(SignalKind::terminate())?,
+        })
+    }
+
+    /// Wait for a signal
+    pub async fn recv(&mut self) -> Signal {
+        #[cfg(unix)]
+        let terminate = self.terminate.recv();
+        #[cfg(not(unix))]
+        let terminate = std::future::pending::<Option<()>>();
+        tokio::select! {
+            _ = terminate => Signal::Terminate,
+            _ = tokio::signal::ctrl_c() => Signal::Interrupt,
+        }
+    }
+}
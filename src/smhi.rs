@@ -0,0 +1,420 @@
+//! Official weather warnings from SMHI, published in the CAP XML format
+use std::fmt;
+
+use anyhow::Result;
+use chrono::{DateTime, FixedOffset};
+use serde::Deserialize;
+
+use crate::metrics;
+
+/// SMHI's warnings as CAP XML, used unless another feed is configured
+pub const CAP_URL: &str =
+    "https://opendata-download-warnings.smhi.se/ibww/api/version/1/warning.cap.xml";
+
+/// A single CAP alert
+#[derive(Debug, Deserialize)]
+pub struct Alert {
+    pub identifier: String,
+    pub status: String,
+    #[serde(rename = "msgType")]
+    pub msg_type: String,
+    #[serde(default)]
+    pub info: Vec<Info>,
+}
+
+/// A document containing several alerts
+#[derive(Debug, Deserialize)]
+struct AlertList {
+    #[serde(rename = "alert", default)]
+    alerts: Vec<Alert>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Info {
+    #[serde(default)]
+    pub language: Option<String>,
+    pub event: String,
+    pub severity: Severity,
+    #[serde(default)]
+    pub onset: Option<String>,
+    #[serde(default)]
+    pub expires: Option<String>,
+    #[serde(default)]
+    pub headline: Option<String>,
+    #[serde(default)]
+    pub parameter: Vec<Parameter>,
+    #[serde(default)]
+    pub area: Vec<Area>,
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Extreme,
+    Severe,
+    Moderate,
+    Minor,
+    Unknown,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Parameter {
+    #[serde(rename = "valueName")]
+    pub value_name: String,
+    pub value: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Area {
+    #[serde(rename = "areaDesc")]
+    pub area_desc: String,
+    #[serde(default)]
+    pub polygon: Vec<String>,
+    #[serde(default)]
+    pub circle: Vec<String>,
+}
+
+/// SMHI warning levels
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum WarningLevel {
+    Yellow,
+    Orange,
+    Red,
+}
+
+impl WarningLevel {
+    pub fn to_swedish(self) -> &'static str {
+        match self {
+            WarningLevel::Yellow => "gul",
+            WarningLevel::Orange => "orange",
+            WarningLevel::Red => "röd",
+        }
+    }
+
+    fn from_severity(severity: Severity) -> Option<Self> {
+        match severity {
+            Severity::Moderate => Some(WarningLevel::Yellow),
+            Severity::Severe => Some(WarningLevel::Orange),
+            Severity::Extreme => Some(WarningLevel::Red),
+            Severity::Minor | Severity::Unknown => None,
+        }
+    }
+
+    /// Parse the level from a CAP parameter, e.g. "YELLOW" or "2; yellow; Moderate"
+    fn from_parameter(value: &str) -> Option<Self> {
+        let value = value.to_lowercase();
+        if value.contains("red") {
+            Some(WarningLevel::Red)
+        } else if value.contains("orange") {
+            Some(WarningLevel::Orange)
+        } else if value.contains("yellow") {
+            Some(WarningLevel::Yellow)
+        } else {
+            None
+        }
+    }
+}
+
+/// A warning relevant for a location
+#[derive(Debug, Clone, PartialEq)]
+pub struct Warning {
+    pub identifier: String,
+    pub update: bool,
+    pub level: WarningLevel,
+    pub event: String,
+    pub area: String,
+    pub onset: Option<DateTime<FixedOffset>>,
+    pub expires: Option<DateTime<FixedOffset>>,
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let level = self.level.to_swedish();
+        let mut s = format!(
+            "{}{} varning: {} ({})",
+            level[..1].to_uppercase(),
+            &level[1..],
+            self.event,
+            self.area
+        );
+        if let Some(onset) = self.onset {
+            s.push_str(&format!(", från {}", onset.format("%d/%m %H:%M")));
+        }
+        if let Some(expires) = self.expires {
+            s.push_str(&format!(" till {}", expires.format("%d/%m %H:%M")));
+        }
+        if self.update {
+            s = format!("Uppdaterad {}", s[..1].to_lowercase() + &s[1..]);
+        }
+        write!(f, "{}", s)
+    }
+}
+
+/// Parse a CAP document, containing either a single `<alert>` or a list of them
+pub fn parse_cap(xml: &str) -> Result<Vec<Alert>> {
+    match quick_xml::de::from_str::<Alert>(xml) {
+        Ok(alert) => Ok(vec![alert]),
+        Err(_) => Ok(quick_xml::de::from_str::<AlertList>(xml)?.alerts),
+    }
+}
+
+/// Fetch and parse the CAP document at `url`
+pub async fn fetch_alerts(url: &str) -> Result<Vec<Alert>> {
+    let xml = metrics::fetch("smhi", async {
+        Ok(metrics::CLIENT
+            .get(url)
+            .send()
+            .await?
+            .error_for_status()?
+            .text()
+            .await?)
+    })
+    .await?;
+    parse_cap(&xml)
+}
+
+/// Warnings covering the point at `lat`/`lon` which have not yet expired at `now`
+pub fn warnings_for(
+    alerts: &[Alert],
+    lat: f64,
+    lon: f64,
+    now: DateTime<FixedOffset>,
+) -> Vec<Warning> {
+    let mut warnings: Vec<Warning> = alerts
+        .iter()
+        .filter(|a| a.status == "Actual" && a.msg_type != "Cancel")
+        .filter_map(|alert| {
+            let info = alert
+                .info
+                .iter()
+                .find(|i| matches!(&i.language, Some(l) if l.starts_with("sv")))
+                .or_else(|| alert.info.first())?;
+            let level = info
+                .parameter
+                .iter()
+                .filter(|p| {
+                    let name = p.value_name.to_lowercase();
+                    name.contains("awareness_level") || name.contains("warninglevel")
+                })
+                .find_map(|p| WarningLevel::from_parameter(&p.value))
+                .or_else(|| WarningLevel::from_severity(info.severity))?;
+            let area = info.area.iter().find(|a| area_contains(a, lat, lon))?;
+            let expires = info.expires.as_deref().and_then(parse_time);
+            if matches!(expires, Some(expires) if expires < now) {
+                return None;
+            }
+            Some(Warning {
+                identifier: alert.identifier.clone(),
+                update: alert.msg_type == "Update",
+                level,
+                event: info.headline.clone().unwrap_or_else(|| info.event.clone()),
+                area: area.area_desc.clone(),
+                onset: info.onset.as_deref().and_then(parse_time),
+                expires,
+            })
+        })
+        .collect();
+    warnings.sort_by_key(|w| std::cmp::Reverse(w.level));
+    warnings
+}
+
+fn parse_time(time: &str) -> Option<DateTime<FixedOffset>> {
+    DateTime::parse_from_rfc3339(time.trim()).ok()
+}
+
+/// Whether any polygon or circle of the area covers the point
+pub fn area_contains(area: &Area, lat: f64, lon: f64) -> bool {
+    area.polygon
+        .iter()
+        .filter_map(|p| parse_polygon(p))
+        .any(|polygon| polygon_contains(&polygon, lat, lon))
+        || area
+            .circle
+            .iter()
+            .filter_map(|c| parse_circle(c))
+            .any(|((c_lat, c_lon), radius)| distance_km(c_lat, c_lon, lat, lon) <= radius)
+}
+
+/// Parse a CAP polygon, a whitespace separated list of "lat,lon" pairs
+fn parse_polygon(polygon: &str) -> Option<Vec<(f64, f64)>> {
+    polygon.split_whitespace().map(parse_point).collect()
+}
+
+/// Parse a CAP circle, "lat,lon radius" with the radius in kilometers
+fn parse_circle(circle: &str) -> Option<((f64, f64), f64)> {
+    let mut parts = circle.split_whitespace();
+    let center = parse_point(parts.next()?)?;
+    let radius = parts.next()?.parse().ok()?;
+    Some((center, radius))
+}
+
+fn parse_point(point: &str) -> Option<(f64, f64)> {
+    let (lat, lon) = point.split_once(',')?;
+    Some((lat.trim().parse().ok()?, lon.trim().parse().ok()?))
+}
+
+/// Ray casting point in polygon test
+fn polygon_contains(polygon: &[(f64, f64)], lat: f64, lon: f64) -> bool {
+    let mut inside = false;
+    let mut j = polygon.len().wrapping_sub(1);
+    for i in 0..polygon.len() {
+        let (lat_i, lon_i) = polygon[i];
+        let (lat_j, lon_j) = polygon[j];
+        if (lat_i > lat) != (lat_j > lat)
+            && lon < (lon_j - lon_i) * (lat - lat_i) / (lat_j - lat_i) + lon_i
+        {
+            inside = !inside;
+        }
+        j = i;
+    }
+    inside
+}
+
+/// Great circle distance using the haversine formula
+fn distance_km(lat1: f64, lon1: f64, lat2: f64, lon2: f64) -> f64 {
+    const EARTH_RADIUS_KM: f64 = 6371.0;
+    let d_lat = (lat2 - lat1).to_radians();
+    let d_lon = (lon2 - lon1).to_radians();
+    let a = (d_lat / 2.0).sin().powi(2)
+        + lat1.to_radians().cos() * lat2.to_radians().cos() * (d_lon / 2.0).sin().powi(2);
+    2.0 * EARTH_RADIUS_KM * a.sqrt().asin()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SINGLE: &str = include_str!("../tests/fixtures/smhi/single_alert.xml");
+    const LIST: &str = include_str!("../tests/fixtures/smhi/alerts.xml");
+    const LUND: (f64, f64) = (55.70, 13.19);
+    const KIRUNA: (f64, f64) = (67.86, 20.23);
+    const STOCKHOLM: (f64, f64) = (59.33, 18.07);
+
+    fn now() -> DateTime<FixedOffset> {
+        DateTime::parse_from_rfc3339("2024-10-20T20:00:00+02:00").unwrap()
+    }
+
+    fn area(polygon: &[&str], circle: &[&str]) -> Area {
+        Area {
+            area_desc: "Test".to_string(),
+            polygon: polygon.iter().map(|p| p.to_string()).collect(),
+            circle: circle.iter().map(|c| c.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn parses_a_single_alert() {
+        let alerts = parse_cap(SINGLE).unwrap();
+        assert_eq!(alerts.len(), 1);
+        let alert = &alerts[0];
+        assert_eq!(alert.identifier, "urn:oid:2.49.0.1.752.0.2024.10.20.1");
+        assert_eq!(alert.status, "Actual");
+        assert_eq!(alert.msg_type, "Alert");
+        assert_eq!(alert.info.len(), 2);
+        assert_eq!(alert.info[1].severity, Severity::Moderate);
+        assert_eq!(alert.info[1].parameter[0].value, "2; yellow; Moderate");
+        assert_eq!(alert.info[1].area[0].polygon.len(), 1);
+    }
+
+    #[test]
+    fn parses_a_list_of_alerts() {
+        let alerts = parse_cap(LIST).unwrap();
+        let ids: Vec<&str> = alerts.iter().map(|a| a.identifier.as_str()).collect();
+        assert_eq!(
+            ids,
+            [
+                "snow-kiruna",
+                "wind-lund",
+                "rain-lund",
+                "flood-lund",
+                "exercise-lund"
+            ]
+        );
+        assert_eq!(alerts[0].info[0].area[0].circle, ["67.85,20.22 30"]);
+    }
+
+    #[test]
+    fn prefers_the_swedish_info() {
+        let alerts = parse_cap(SINGLE).unwrap();
+        let warnings = warnings_for(&alerts, LUND.0, LUND.1, now());
+        assert_eq!(
+            warnings,
+            [Warning {
+                identifier: "urn:oid:2.49.0.1.752.0.2024.10.20.1".to_string(),
+                update: false,
+                level: WarningLevel::Yellow,
+                event: "Mycket hårda vindbyar".to_string(),
+                area: "Skåne".to_string(),
+                onset: parse_time("2024-10-20T12:00:00+02:00"),
+                expires: parse_time("2024-10-21T06:00:00+02:00"),
+            }]
+        );
+        assert_eq!(
+            warnings[0].to_string(),
+            "Gul varning: Mycket hårda vindbyar (Skåne), från 20/10 12:00 till 21/10 06:00"
+        );
+    }
+
+    #[test]
+    fn skips_cancelled_expired_and_exercises() {
+        let alerts = parse_cap(LIST).unwrap();
+        let warnings = warnings_for(&alerts, LUND.0, LUND.1, now());
+        let ids: Vec<&str> = warnings.iter().map(|w| w.identifier.as_str()).collect();
+        assert_eq!(ids, ["wind-lund"]);
+        // Level from the severity without an awareness level parameter
+        assert_eq!(warnings[0].level, WarningLevel::Yellow);
+        assert!(warnings[0].update);
+        assert!(warnings[0]
+            .to_string()
+            .starts_with("Uppdaterad gul varning: Vind"));
+    }
+
+    #[test]
+    fn matches_circles() {
+        let alerts = parse_cap(LIST).unwrap();
+        let warnings = warnings_for(&alerts, KIRUNA.0, KIRUNA.1, now());
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(warnings[0].identifier, "snow-kiruna");
+        assert_eq!(warnings[0].level, WarningLevel::Orange);
+        assert!(warnings_for(&alerts, STOCKHOLM.0, STOCKHOLM.1, now()).is_empty());
+    }
+
+    #[test]
+    fn sorts_by_level() {
+        let alerts = parse_cap(LIST).unwrap();
+        let before_expiry = DateTime::parse_from_rfc3339("2024-10-19T06:00:00+02:00").unwrap();
+        let warnings = warnings_for(&alerts, LUND.0, LUND.1, before_expiry);
+        let levels: Vec<WarningLevel> = warnings.iter().map(|w| w.level).collect();
+        assert_eq!(levels, [WarningLevel::Red, WarningLevel::Yellow]);
+    }
+
+    #[test]
+    fn polygon_contains_points_inside() {
+        let skane = area(&["55.3,12.8 55.3,14.4 56.5,14.4 56.5,12.8 55.3,12.8"], &[]);
+        assert!(area_contains(&skane, LUND.0, LUND.1));
+        assert!(!area_contains(&skane, STOCKHOLM.0, STOCKHOLM.1));
+        // A triangle, where the bounding box isn't enough
+        let triangle = area(&["55.0,13.0 57.0,13.0 55.0,15.0"], &[]);
+        assert!(area_contains(&triangle, 55.5, 13.5));
+        assert!(!area_contains(&triangle, 56.8, 14.8));
+    }
+
+    #[test]
+    fn circle_contains_points_within_the_radius() {
+        let kiruna = area(&[], &["67.85,20.22 30"]);
+        assert!(area_contains(&kiruna, KIRUNA.0, KIRUNA.1));
+        // Abisko is about 70 km away
+        assert!(!area_contains(&kiruna, 68.35, 18.83));
+        assert!(area_contains(
+            &area(&[], &["67.85,20.22 100"]),
+            68.35,
+            18.83
+        ));
+    }
+
+    #[test]
+    fn ignores_malformed_areas() {
+        let broken = area(&["55.3,12.8 nonsense"], &["67.85,20.22"]);
+        assert!(!area_contains(&broken, LUND.0, LUND.1));
+        assert!(!area_contains(&broken, KIRUNA.0, KIRUNA.1));
+    }
+}
@@ -0,0 +1,51 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::path::Path;
+
+use anyhow::Result;
+use chrono::NaiveDate;
+use serde::{Deserialize, Serialize};
+
+use crate::climatology::DayRecord;
+use crate::files;
+
+const STATE_FILE: &str = "state.toml";
+
+/// State kept between restarts
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct State {
+    #[serde(default)]
+    pub locations: HashMap<String, LocationState>,
+}
+
+/// State for a single location
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct LocationState {
+    /// Season in which the first night frost was announced
+    #[serde(default)]
+    pub first_frost_season: Option<i32>,
+    /// Season in which the first snow was announced
+    #[serde(default)]
+    pub first_snow_season: Option<i32>,
+    /// Forecasted temperatures of posted days
+    #[serde(default)]
+    pub history: BTreeMap<NaiveDate, DayRecord>,
+    /// Identifiers of the warnings which have been posted and are still active
+    #[serde(default)]
+    pub posted_warnings: BTreeSet<String>,
+}
+
+impl State {
+    /// Load the state, or start over if there is none
+    pub fn load() -> Result<Self> {
+        files::load_or_default(Path::new(STATE_FILE))
+    }
+
+    /// Save the state, replacing the previous file atomically
+    pub fn save(&self) -> Result<()> {
+        files::write_atomic(Path::new(STATE_FILE), &toml::to_string(self)?)
+    }
+
+    pub fn location(&mut self, name: &str) -> &mut LocationState {
+        self.locations.entry(name.to_string()).or_default()
+    }
+}
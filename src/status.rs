@@ -0,0 +1,82 @@
+//! What the bot is doing, for the admin API
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+
+use anyhow::Result;
+use chrono::{DateTime, Local};
+use serde::Serialize;
+
+static STATUS: Mutex<Status> = Mutex::new(Status {
+    ready: false,
+    next: Vec::new(),
+    locations: BTreeMap::new(),
+});
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Status {
+    /// Logged in and posting on schedule
+    pub ready: bool,
+    /// The next time of each scheduled job
+    pub next: Vec<NextPost>,
+    pub locations: BTreeMap<String, LocationStatus>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct NextPost {
+    pub job: String,
+    pub time: DateTime<Local>,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct LocationStatus {
+    /// When the forecast was last fetched
+    pub last_fetch: Option<DateTime<Local>>,
+    pub last_post: Option<LastPost>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct LastPost {
+    pub job: String,
+    pub time: DateTime<Local>,
+    pub error: Option<String>,
+}
+
+fn update(f: impl FnOnce(&mut Status)) {
+    f(&mut STATUS.lock().unwrap());
+}
+
+pub fn get() -> Status {
+    STATUS.lock().unwrap().clone()
+}
+
+pub fn set_ready(ready: bool) {
+    update(|status| status.ready = ready);
+}
+
+pub fn set_next(next: Vec<NextPost>) {
+    update(|status| status.next = next);
+}
+
+pub fn fetched(location: &str) {
+    update(|status| {
+        status
+            .locations
+            .entry(location.to_string())
+            .or_default()
+            .last_fetch = Some(Local::now())
+    });
+}
+
+pub fn posted(location: &str, job: String, result: &Result<()>) {
+    update(|status| {
+        status
+            .locations
+            .entry(location.to_string())
+            .or_default()
+            .last_post = Some(LastPost {
+            job,
+            time: Local::now(),
+            error: result.as_ref().err().map(|e| e.to_string()),
+        })
+    });
+}
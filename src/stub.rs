@@ -0,0 +1,97 @@
+//! A stub HTTP server for testing the publishers, answering each request with
+//! the next of the given responses and recording what it got
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+
+use axum::body::Bytes;
+use axum::extract::State;
+use axum::http::{header, HeaderMap, Method, StatusCode, Uri};
+use axum::response::IntoResponse;
+use axum::Router;
+
+/// A request the stub got
+#[derive(Debug, Clone)]
+pub struct Request {
+    pub method: Method,
+    /// Path and query, as sent
+    pub path: String,
+    pub headers: HeaderMap,
+    pub body: String,
+}
+
+impl Request {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(name)?.to_str().ok()
+    }
+
+    pub fn json(&self) -> serde_json::Value {
+        serde_json::from_str(&self.body).expect("JSON body")
+    }
+}
+
+#[derive(Clone, Default)]
+struct Shared {
+    responses: Arc<Mutex<VecDeque<(u16, String)>>>,
+    requests: Arc<Mutex<Vec<Request>>>,
+}
+
+pub struct Stub {
+    pub url: String,
+    shared: Shared,
+}
+
+impl Stub {
+    /// Serve the JSON `responses` in order on an ephemeral port, then 404s
+    pub async fn start(responses: &[(u16, &str)]) -> Self {
+        let shared = Shared::default();
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let router = Router::new().fallback(respond).with_state(shared.clone());
+        tokio::spawn(async move { axum::serve(listener, router).await });
+        let stub = Self { url, shared };
+        stub.respond(responses);
+        stub
+    }
+
+    /// Answer with `responses` after those already given
+    pub fn respond(&self, responses: &[(u16, &str)]) {
+        self.shared.responses.lock().unwrap().extend(
+            responses
+                .iter()
+                .map(|&(status, body)| (status, body.to_string())),
+        );
+    }
+
+    pub fn requests(&self) -> Vec<Request> {
+        self.shared.requests.lock().unwrap().clone()
+    }
+}
+
+async fn respond(
+    State(shared): State<Shared>,
+    method: Method,
+    uri: Uri,
+    headers: HeaderMap,
+    body: Bytes,
+) -> impl IntoResponse {
+    shared.requests.lock().unwrap().push(Request {
+        method,
+        path: uri
+            .path_and_query()
+            .map(|p| p.to_string())
+            .unwrap_or_default(),
+        headers,
+        body: String::from_utf8_lossy(&body).into_owned(),
+    });
+    let (status, body) = shared
+        .responses
+        .lock()
+        .unwrap()
+        .pop_front()
+        .unwrap_or((404, "{}".to_string()));
+    (
+        StatusCode::from_u16(status).unwrap(),
+        [(header::CONTENT_TYPE, "application/json")],
+        body,
+    )
+}
@@ -0,0 +1,232 @@
+//! Posting to a Telegram channel or chat with the Bot API
+use anyhow::{bail, Result};
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+
+use crate::publisher::{Formatting, Markup, Publisher, TIMEOUT};
+
+pub struct Telegram {
+    name: String,
+    formatting: Formatting,
+    client: reqwest::Client,
+    api_url: String,
+    bot_token: String,
+    chat_id: String,
+}
+
+#[derive(Debug, Serialize)]
+struct SendMessage<'a> {
+    chat_id: &'a str,
+    text: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    parse_mode: Option<&'a str>,
+}
+
+/// Every response of the Bot API
+#[derive(Debug, Deserialize)]
+struct Response {
+    ok: bool,
+    #[serde(default)]
+    description: Option<String>,
+}
+
+impl Telegram {
+    pub fn new(
+        name: String,
+        formatting: Formatting,
+        api_url: &str,
+        bot_token: &str,
+        chat_id: &str,
+    ) -> Result<Self> {
+        Ok(Self {
+            name,
+            formatting,
+            client: reqwest::Client::builder().timeout(TIMEOUT).build()?,
+            api_url: api_url.trim_end_matches('/').to_string(),
+            bot_token: bot_token.to_string(),
+            chat_id: chat_id.to_string(),
+        })
+    }
+
+    fn url(&self, method: &str) -> String {
+        format!("{}/bot{}/{}", self.api_url, self.bot_token, method)
+    }
+}
+
+/// Fail unless the Bot API says the request succeeded
+async fn check(response: reqwest::Response) -> Result<()> {
+    let status = response.status();
+    match response.json::<Response>().await {
+        Ok(Response { ok: true, .. }) => Ok(()),
+        Ok(Response { description, .. }) => {
+            bail!("{}: {}", status, description.unwrap_or_default())
+        }
+        Err(_) => bail!("{}", status),
+    }
+}
+
+#[async_trait]
+impl Publisher for Telegram {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    async fn verify(&self) -> Result<()> {
+        let response = self
+            .client
+            .get(self.url("getMe"))
+            .send()
+            .await
+            .map_err(reqwest::Error::without_url)?;
+        check(response).await
+    }
+
+    async fn publish(&self, post: &str) -> Result<()> {
+        let parse_mode = match self.formatting.markup {
+            Markup::Plain => None,
+            Markup::Html => Some("HTML"),
+            Markup::Markdown => Some("MarkdownV2"),
+        };
+        let response = self
+            .client
+            .post(self.url("sendMessage"))
+            .json(&SendMessage {
+                chat_id: &self.chat_id,
+                text: &self.formatting.apply(post),
+                parse_mode,
+            })
+            .send()
+            .await
+            .map_err(reqwest::Error::without_url)?;
+        check(response).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::publisher::MarkdownFlavor;
+    use crate::stub::Stub;
+
+    fn telegram(stub: &Stub, markup: Markup, markdown: MarkdownFlavor) -> Telegram {
+        let formatting = Formatting {
+            markup,
+            max_chars: Some(4096),
+            markdown,
+        };
+        Telegram::new(
+            "telegram".to_string(),
+            formatting,
+            &stub.url,
+            "123:abc",
+            "@vader",
+        )
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn verifies_the_token() {
+        let stub = Stub::start(&[(200, r#"{"ok":true,"result":{"id":1}}"#)]).await;
+        telegram(&stub, Markup::Html, MarkdownFlavor::Telegram)
+            .verify()
+            .await
+            .unwrap();
+        let requests = stub.requests();
+        assert_eq!(requests[0].method, "GET");
+        assert_eq!(requests[0].path, "/bot123:abc/getMe");
+    }
+
+    #[tokio::test]
+    async fn sends_html() {
+        let stub = Stub::start(&[(200, r#"{"ok":true}"#)]).await;
+        telegram(&stub, Markup::Html, MarkdownFlavor::Telegram)
+            .publish("Sol & <moln>")
+            .await
+            .unwrap();
+        let requests = stub.requests();
+        assert_eq!(requests[0].method, "POST");
+        assert_eq!(requests[0].path, "/bot123:abc/sendMessage");
+        assert_eq!(
+            requests[0].json(),
+            serde_json::json!({
+                "chat_id": "@vader",
+                "text": "Sol &amp; &lt;moln&gt;",
+                "parse_mode": "HTML",
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn sends_markdown_v2() {
+        let stub = Stub::start(&[(200, r#"{"ok":true}"#)]).await;
+        telegram(&stub, Markup::Markdown, MarkdownFlavor::Telegram)
+            .publish("Temp: -1.5°C\nSol!")
+            .await
+            .unwrap();
+        let body = stub.requests()[0].json();
+        assert_eq!(body["parse_mode"], "MarkdownV2");
+        assert_eq!(body["text"], "Temp: \\-1\\.5°C\nSol\\!");
+    }
+
+    #[tokio::test]
+    async fn sends_plain_text_without_parse_mode() {
+        let stub = Stub::start(&[(200, r#"{"ok":true}"#)]).await;
+        telegram(&stub, Markup::Plain, MarkdownFlavor::Telegram)
+            .publish("Sol & moln")
+            .await
+            .unwrap();
+        assert_eq!(
+            stub.requests()[0].json(),
+            serde_json::json!({ "chat_id": "@vader", "text": "Sol & moln" })
+        );
+    }
+
+    #[tokio::test]
+    async fn fails_with_the_description() {
+        let stub = Stub::start(&[(
+            400,
+            r#"{"ok":false,"error_code":400,"description":"Bad Request: chat not found"}"#,
+        )])
+        .await;
+        let e = telegram(&stub, Markup::Html, MarkdownFlavor::Telegram)
+            .publish("Hej")
+            .await
+            .unwrap_err();
+        assert_eq!(
+            e.to_string(),
+            "400 Bad Request: Bad Request: chat not found"
+        );
+    }
+
+    #[tokio::test]
+    async fn fails_on_unexpected_responses() {
+        let stub = Stub::start(&[(502, "Bad gateway")]).await;
+        let e = telegram(&stub, Markup::Html, MarkdownFlavor::Telegram)
+            .verify()
+            .await
+            .unwrap_err();
+        assert_eq!(e.to_string(), "502 Bad Gateway");
+    }
+
+    #[tokio::test]
+    async fn keeps_the_token_out_of_errors() {
+        // Nothing listens on the port once the listener is dropped
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        drop(listener);
+        let telegram = Telegram::new(
+            "telegram".to_string(),
+            Formatting {
+                markup: Markup::Plain,
+                max_chars: None,
+                markdown: MarkdownFlavor::Telegram,
+            },
+            &url,
+            "123:secret-token",
+            "@vader",
+        )
+        .unwrap();
+        let e = telegram.publish("Hej").await.unwrap_err();
+        assert!(!format!("{:#}", e).contains("secret-token"), "{:#}", e);
+    }
+}
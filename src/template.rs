@@ -0,0 +1,86 @@
+//! Simple templates for posts, with `{name}` placeholders
+
+/// Replace each `{name}` in the template with its value, in a single pass so
+/// that placeholders in the values are left as they are. Unknown placeholders
+/// are left as they are too, and lines left empty by empty values are removed.
+pub fn render(template: &str, values: &[(&str, &str)]) -> String {
+    template
+        .lines()
+        .filter_map(|line| {
+            let (rendered, replaced) = render_line(line, values);
+            if replaced && rendered.trim().is_empty() {
+                None
+            } else {
+                Some(rendered)
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+        .trim()
+        .to_string()
+}
+
+/// The line with its placeholders replaced, and whether there were any
+fn render_line(line: &str, values: &[(&str, &str)]) -> (String, bool) {
+    let mut rendered = String::new();
+    let mut replaced = false;
+    let mut rest = line;
+    while let Some(start) = rest.find('{') {
+        rendered.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let value = rest.find('}').and_then(|end| {
+            let name = &rest[1..end];
+            let (_, value) = values.iter().find(|(n, _)| *n == name)?;
+            Some((value, end))
+        });
+        match value {
+            Some((value, end)) => {
+                rendered.push_str(value);
+                replaced = true;
+                rest = &rest[end + 1..];
+            }
+            None => {
+                rendered.push('{');
+                rest = &rest[1..];
+            }
+        }
+    }
+    rendered.push_str(rest);
+    (rendered, replaced)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn drops_lines_left_empty() {
+        let values = [("forecast", "Sol"), ("pollen", ""), ("marine", " ")];
+        assert_eq!(
+            render("{forecast}\n{pollen}\n\n{marine}\nSlut", &values),
+            "Sol\n\nSlut"
+        );
+        assert_eq!(render("{pollen}\n{forecast}\n{pollen}", &values), "Sol");
+    }
+
+    #[test]
+    fn leaves_placeholders_in_values_as_they_are() {
+        let values = [
+            ("warnings", "Varning: {pollen} i {location"),
+            ("pollen", "Pollen: björk"),
+        ];
+        assert_eq!(
+            render("{warnings}\n{pollen}", &values),
+            "Varning: {pollen} i {location\nPollen: björk"
+        );
+    }
+
+    #[test]
+    fn leaves_unknown_placeholders_as_they_are() {
+        let values = [("location", "Lund")];
+        assert_eq!(
+            render("{ {location}} {unknown} {", &values),
+            "{ Lund} {unknown} {"
+        );
+    }
+}
@@ -1,6 +1,16 @@
+use std::collections::BTreeSet;
+use std::fmt;
+use std::str::FromStr;
+
+use anyhow::{anyhow, Result};
+use chrono::NaiveDate;
 use itertools::Itertools;
 use serde::{Deserialize, Serialize};
 
+use crate::apparent::{self, Apparent};
+use crate::astronomy;
+use crate::wind::{self, Wind};
+
 #[derive(Debug, Deserialize, Serialize)]
 pub struct Wttr {
     pub current_condition: Vec<CurrentCondition>,
@@ -9,6 +19,97 @@ pub struct Wttr {
     pub weather: Vec<Weather>,
 }
 
+impl Wttr {
+    /// The forecast for the day, an error if wttr.in didn't send it
+    pub fn day(&self, day: Day) -> Result<&Weather> {
+        self.weather
+            .get(day.index())
+            .ok_or_else(|| anyhow!("No forecast for {:?} from wttr.in", day))
+    }
+
+    /// The fields which are parsed when posting but don't parse, by their
+    /// names in the JSON
+    pub fn unparsable_fields(&self) -> BTreeSet<&'static str> {
+        fn parses<T: FromStr>(value: &str) -> bool {
+            value.parse::<T>().is_ok()
+        }
+        let mut fields = BTreeSet::new();
+        let mut check = |field: &'static str, ok: bool| {
+            if !ok {
+                fields.insert(field);
+            }
+        };
+        for current in &self.current_condition {
+            check("current_condition.temp_C", parses::<i32>(&current.temp_c));
+            check(
+                "current_condition.FeelsLikeC",
+                parses::<i32>(&current.feels_like_c),
+            );
+            check(
+                "current_condition.windspeedKmph",
+                parses::<f32>(&current.windspeed_kmph),
+            );
+        }
+        for area in &self.nearest_area {
+            check("nearest_area.latitude", parses::<f64>(&area.latitude));
+            check("nearest_area.longitude", parses::<f64>(&area.longitude));
+        }
+        for weather in &self.weather {
+            check(
+                "weather.date",
+                NaiveDate::parse_from_str(&weather.date, "%Y-%m-%d").is_ok(),
+            );
+            check("weather.avgtempC", parses::<f32>(&weather.avg_temp_c));
+            check("weather.maxtempC", parses::<f32>(&weather.max_temp_c));
+            check("weather.uvIndex", parses::<u8>(&weather.uv_index));
+            check(
+                "weather.totalSnow_cm",
+                parses::<f32>(&weather.total_snow_cm),
+            );
+            for astro in &weather.astronomy {
+                check(
+                    "weather.astronomy.sunrise",
+                    astronomy::parse_time(&astro.sunrise).is_some(),
+                );
+                check(
+                    "weather.astronomy.sunset",
+                    astronomy::parse_time(&astro.sunset).is_some(),
+                );
+                check(
+                    "weather.astronomy.moon_illumination",
+                    parses::<u8>(&astro.moon_illumination),
+                );
+            }
+            for hourly in &weather.hourly {
+                check("weather.hourly.time", parses::<u16>(&hourly.time));
+                check("weather.hourly.tempC", parses::<i32>(&hourly.temp_c));
+                check(
+                    "weather.hourly.FeelsLikeC",
+                    parses::<i32>(&hourly.feels_like_c),
+                );
+                check(
+                    "weather.hourly.HeatIndexC",
+                    parses::<i32>(&hourly.heat_index_c),
+                );
+                check(
+                    "weather.hourly.windspeedKmph",
+                    parses::<u16>(&hourly.windspeed_kmph),
+                );
+                check(
+                    "weather.hourly.WindGustKmph",
+                    parses::<f32>(&hourly.wind_gust_kmph),
+                );
+                check("weather.hourly.precipMM", parses::<f32>(&hourly.precip_mm));
+                check(
+                    "weather.hourly.chanceoffrost",
+                    parses::<u8>(&hourly.chance_of_frost),
+                );
+            }
+        }
+        fields
+    }
+}
+
 #[derive(Debug, Deserialize, Serialize)]
 pub struct CurrentCondition {
     #[serde(rename = "FeelsLikeC")]
@@ -55,6 +156,56 @@ pub struct CurrentCondition {
     pub windspeed_miles: String,
 }
 
+impl CurrentCondition {
+    /// E.g. "Just nu 3 °C, känns som -1 °C och måttlig västlig vind, 5 m/s."
+    pub fn describe(&self) -> String {
+        let mut s = format!("Just nu {} °C", self.temp_c);
+        if let (Ok(temp), Ok(feels_like)) =
+            (self.temp_c.parse::<i32>(), self.feels_like_c.parse::<i32>())
+        {
+            // Only worth mentioning if it makes a noticeable difference
+            if (temp - feels_like).abs() >= 2 {
+                s.push_str(&format!(", känns som {} °C", feels_like));
+            }
+        }
+        if let Ok(speed) = self.windspeed_kmph.parse::<f32>() {
+            let wind = Wind {
+                speed: wind::kmph_to_ms(speed),
+                gust: None,
+                direction: wind::direction_to_swedish(&self.wind_dir_16_point),
+            };
+            s.push_str(&format!(" och {}", wind));
+        }
+        s.push('.');
+        s
+    }
+}
+
+/// Which day a forecast is for
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Day {
+    Today,
+    Tomorrow,
+}
+
+impl Day {
+    /// Index of the day in wttr.in's forecast
+    pub fn index(self) -> usize {
+        match self {
+            Day::Today => 0,
+            Day::Tomorrow => 1,
+        }
+    }
+
+    pub fn to_swedish(self) -> &'static str {
+        match self {
+            Day::Today => "Idag",
+            Day::Tomorrow => "Imorgon",
+        }
+    }
+}
+
 #[derive(Debug, Deserialize, Serialize)]
 pub struct LanguageValue {
     pub value: String,
@@ -122,12 +273,51 @@ pub struct Weather {
 }
 
 impl Weather {
-    pub fn weather_report(&self) -> String {
-        // Conditions from 06 and forward
-        let conditions = self.hourly[2..]
+    /// The hours from 06 and forward, none if they are missing
+    fn daytime(&self) -> &[Hourly] {
+        self.hourly.get(2..).unwrap_or_default()
+    }
+
+    /// The conditions from 06 and forward
+    fn conditions(&self) -> Vec<BaseWeather> {
+        self.daytime()
+            .iter()
+            .filter_map(|h| Some(h.weather_desc.first()?.value.to_base_weather()))
+            .collect()
+    }
+
+    /// The strongest wind from 06 and forward, with the peak gusts
+    pub fn wind(&self) -> Option<Wind> {
+        let hourly = self.daytime();
+        let strongest = hourly
             .iter()
-            .map(|h| h.weather_desc[0].value.to_base_weather())
-            .collect::<Vec<_>>();
+            .max_by_key(|h| h.windspeed_kmph.parse::<u16>().unwrap_or_default())?;
+        let gust = hourly
+            .iter()
+            .filter_map(|h| h.wind_gust_kmph.parse::<f32>().ok())
+            .reduce(f32::max);
+        Some(Wind {
+            speed: wind::kmph_to_ms(strongest.windspeed_kmph.parse().ok()?),
+            gust: gust.map(wind::kmph_to_ms),
+            direction: wind::direction_to_swedish(&strongest.wind_dir_16_point),
+        })
+    }
+
+    /// The most common weather from 06 and forward
+    pub fn dominant_weather(&self) -> Option<BaseWeather> {
+        let conditions = self.conditions();
+        conditions
+            .iter()
+            .unique()
+            .min_by_key(|&c| std::cmp::Reverse(conditions.iter().filter(|&o| o == c).count()))
+            .copied()
+    }
+
+    pub fn weather_report(&self, day: Day) -> String {
+        let conditions = self.conditions();
+        if conditions.is_empty() {
+            return String::new();
+        }
         let unique_conditions: Vec<&BaseWeather> = conditions.iter().unique().collect();
 
         let mut weather_counts: Vec<_> = unique_conditions
@@ -166,7 +356,7 @@ impl Weather {
                 }
                 Rain => {
                     if i == 0 {
-                        report.push_str(&format!("Imorgon blir det mest {}", weather));
+                        report.push_str(&format!("{} blir det mest {}", day.to_swedish(), weather));
                     } else {
                         report.push_str(&format!("{} {}", separator, weather));
                     }
@@ -197,29 +387,57 @@ impl Weather {
     }
 }
 
-impl ToString for Weather {
-    fn to_string(&self) -> String {
-        let mut s = format!(
-            "{}\nTemp min/max: {}/{}°C\nSolnedgång: {}",
-            self.weather_report(),
-            self.min_temp_c,
-            self.max_temp_c,
-            twelve_hour_to_24(&self.astronomy[0].sunset)
-        );
-        if let Some(uv) = self.uv_index.parse::<u8>().ok() {
+impl fmt::Display for Weather {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.summary(Day::Tomorrow))
+    }
+}
+
+impl Weather {
+    /// The forecast for the day, as posted
+    pub fn summary(&self, day: Day) -> String {
+        let mut s = format!("Temp min/max: {}/{}°C", self.min_temp_c, self.max_temp_c);
+        let report = self.weather_report(day);
+        if !report.is_empty() {
+            s = format!("{}\n{}", report, s)
+        };
+        if let Some(wind) = self.wind() {
+            s = format!("{}\nVind: {}", s, wind)
+        };
+        match apparent::apparent(self) {
+            Some(Apparent::Cold(temp)) => {
+                s = format!(
+                    "{}\nKänns som lägst: {}°C\n{}",
+                    s,
+                    temp,
+                    apparent::explain_wind_chill(temp)
+                )
+            }
+            Some(Apparent::Heat(temp)) => {
+                s = format!(
+                    "{}\nVärmeindex: {}°C\n{}",
+                    s,
+                    temp,
+                    apparent::explain_heat_index(temp)
+                )
+            }
+            None => (),
+        };
+        if let Ok(uv) = self.uv_index.parse::<u8>() {
             if uv > 5 {
                 s = format!("{}\n{}", s, explain_uv_index(uv))
             }
         };
-        if let Some(snow) = self.total_snow_cm.parse::<f32>().ok() {
+        if let Ok(snow) = self.total_snow_cm.parse::<f32>() {
             if snow > 0.0 {
                 s = format!("{}\nSnö: {}cm", s, snow)
             }
         };
         // TODO: Make lib function
-        let rain: f32 = self.hourly[2..]
+        let rain: f32 = self
+            .daytime()
             .iter()
-            .map(|h| h.precip_mm.parse::<f32>().unwrap())
+            .filter_map(|h| h.precip_mm.parse::<f32>().ok())
             .sum();
         if rain > 0.0 {
             s = format!("{}\nNederbörd: {}mm", s, rain)
@@ -228,20 +446,6 @@ impl ToString for Weather {
     }
 }
 
-fn twelve_hour_to_24(time: &str) -> String {
-    let mut parts = time.split_whitespace();
-    let mut time_parts = parts.next().unwrap().split(':');
-    let hour = time_parts.next().unwrap().parse::<u8>().unwrap();
-    let minute = time_parts.next().unwrap().parse::<u8>().unwrap();
-    let am_pm = parts.next().unwrap();
-    let hour = match am_pm {
-        "AM" => hour,
-        "PM" => hour + 12,
-        _ => panic!("Unknown time format"),
-    };
-    format!("{:02}:{:02}", hour, minute)
-}
-
 fn explain_uv_index(uv_index: u8) -> &'static str {
     match uv_index {
         0..=2 => "Lågt UV-index. Minimalt solskydd krävs.",
@@ -450,8 +654,8 @@ pub enum WeatherCondition {
     Sleet,
 }
 
-#[derive(Debug, PartialEq, Eq, Hash)]
-enum BaseWeather {
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum BaseWeather {
     Rain,
     Clear,
     Cloudy,
@@ -463,7 +667,7 @@ enum BaseWeather {
 }
 
 impl BaseWeather {
-    pub fn to_swedish(&self) -> String {
+    pub fn to_swedish(self) -> String {
         let s = match self {
             BaseWeather::Rain => "regn",
             BaseWeather::Clear => "klar himmel",
@@ -476,6 +680,17 @@ impl BaseWeather {
         };
         s.to_string()
     }
+
+    pub fn to_emoji(self) -> &'static str {
+        match self {
+            BaseWeather::Rain => "🌧️",
+            BaseWeather::Clear | BaseWeather::Sunny => "☀️",
+            BaseWeather::Cloudy => "☁️",
+            BaseWeather::Snow | BaseWeather::Sleet => "🌨️",
+            BaseWeather::Fog => "🌫️",
+            BaseWeather::Thunder => "⛈️",
+        }
+    }
 }
 
 impl WeatherCondition {
@@ -543,3 +758,165 @@ impl WeatherCondition {
         }
     }
 }
+
+/// Forecasts built in tests
+#[cfg(test)]
+pub mod testing {
+    use super::{CurrentCondition, Weather};
+
+    const CURRENT_KEYS: [&str; 20] = [
+        "FeelsLikeC",
+        "FeelsLikeF",
+        "cloudcover",
+        "humidity",
+        "localObsDateTime",
+        "observation_time",
+        "precipInches",
+        "precipMM",
+        "pressure",
+        "pressureInches",
+        "temp_C",
+        "temp_F",
+        "uvIndex",
+        "visibility",
+        "visibilityMiles",
+        "weatherCode",
+        "winddir16Point",
+        "winddirDegree",
+        "windspeedKmph",
+        "windspeedMiles",
+    ];
+    const HOURLY_KEYS: [&str; 37] = [
+        "DewPointC",
+        "DewPointF",
+        "FeelsLikeC",
+        "FeelsLikeF",
+        "HeatIndexC",
+        "HeatIndexF",
+        "WindChillC",
+        "WindChillF",
+        "WindGustKmph",
+        "WindGustMiles",
+        "chanceoffog",
+        "chanceoffrost",
+        "chanceofhightemp",
+        "chanceofovercast",
+        "chanceofrain",
+        "chanceofremdry",
+        "chanceofsnow",
+        "chanceofsunshine",
+        "chanceofthunder",
+        "chanceofwindy",
+        "cloudcover",
+        "humidity",
+        "precipInches",
+        "precipMM",
+        "pressure",
+        "pressureInches",
+        "tempC",
+        "tempF",
+        "time",
+        "uvIndex",
+        "visibility",
+        "visibilityMiles",
+        "weatherCode",
+        "winddir16Point",
+        "winddirDegree",
+        "windspeedKmph",
+        "windspeedMiles",
+    ];
+    const DAILY_KEYS: [&str; 9] = [
+        "avgtempC",
+        "avgtempF",
+        "maxtempC",
+        "maxtempF",
+        "mintempC",
+        "mintempF",
+        "sunHour",
+        "totalSnow_cm",
+        "uvIndex",
+    ];
+
+    fn values(
+        keys: &[&str],
+        values: &[(&str, &str)],
+    ) -> serde_json::Map<String, serde_json::Value> {
+        keys.iter()
+            .map(|&key| (key.to_string(), "0".into()))
+            .chain(
+                values
+                    .iter()
+                    .map(|&(key, value)| (key.to_string(), value.into())),
+            )
+            .collect()
+    }
+
+    /// A day of wttr.in's forecast with every value 0 except those given in
+    /// `daily` and for each of the `hours`, by their names in wttr.in's JSON.
+    /// A `weatherDesc` given for an hour is its only description.
+    pub fn weather(date: &str, daily: &[(&str, &str)], hours: &[&[(&str, &str)]]) -> Weather {
+        let mut weather = values(&DAILY_KEYS, daily);
+        weather.insert("date".to_string(), date.into());
+        weather.insert("astronomy".to_string(), serde_json::json!([]));
+        let hourly = hours
+            .iter()
+            .map(|hour| {
+                let mut hourly = values(&HOURLY_KEYS, hour);
+                for key in ["lang_sv", "weatherDesc", "weatherIconUrl"] {
+                    let value = match hourly.remove(key) {
+                        Some(value) => serde_json::json!([{ "value": value }]),
+                        None => serde_json::json!([]),
+                    };
+                    hourly.insert(key.to_string(), value);
+                }
+                serde_json::Value::Object(hourly)
+            })
+            .collect();
+        weather.insert("hourly".to_string(), serde_json::Value::Array(hourly));
+        serde_json::from_value(serde_json::Value::Object(weather)).expect("A complete forecast")
+    }
+
+    /// wttr.in's current condition with every value 0 except those given
+    pub fn current_condition(values: &[(&str, &str)]) -> CurrentCondition {
+        let mut current = self::values(&CURRENT_KEYS, values);
+        for key in ["lang_sv", "weatherDesc", "weatherIconUrl"] {
+            current.insert(key.to_string(), serde_json::json!([]));
+        }
+        serde_json::from_value(serde_json::Value::Object(current))
+            .expect("A complete current condition")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::testing::current_condition;
+
+    #[test]
+    fn mentions_feels_like_from_two_degrees_off() {
+        let describe = |temp: &str, feels_like: &str| {
+            current_condition(&[
+                ("temp_C", temp),
+                ("FeelsLikeC", feels_like),
+                ("windspeedKmph", "18"),
+                ("winddir16Point", "W"),
+            ])
+            .describe()
+        };
+        assert_eq!(
+            describe("3", "-1"),
+            "Just nu 3 °C, känns som -1 °C och måttlig västlig vind, 5 m/s."
+        );
+        assert!(describe("3", "1").starts_with("Just nu 3 °C, känns som 1 °C och "));
+        assert!(describe("3", "5").starts_with("Just nu 3 °C, känns som 5 °C och "));
+        assert!(describe("3", "2").starts_with("Just nu 3 °C och "));
+        assert!(describe("3", "4").starts_with("Just nu 3 °C och "));
+        assert!(describe("3", "").starts_with("Just nu 3 °C och "));
+    }
+
+    #[test]
+    fn leaves_out_unparsable_wind() {
+        let current =
+            current_condition(&[("temp_C", "3"), ("FeelsLikeC", "3"), ("windspeedKmph", "")]);
+        assert_eq!(current.describe(), "Just nu 3 °C.");
+    }
+}
@@ -0,0 +1,140 @@
+//! Posting to any HTTP endpoint
+use std::collections::BTreeMap;
+
+use anyhow::Result;
+use async_trait::async_trait;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+
+use crate::publisher::{Formatting, Publisher, TIMEOUT};
+
+/// Posts `{"text": "..."}` to a url
+pub struct Webhook {
+    name: String,
+    formatting: Formatting,
+    client: reqwest::Client,
+    url: String,
+}
+
+impl Webhook {
+    pub fn new(
+        name: String,
+        formatting: Formatting,
+        url: &str,
+        headers: &BTreeMap<String, String>,
+    ) -> Result<Self> {
+        let mut default_headers = HeaderMap::new();
+        for (name, value) in headers {
+            default_headers.insert(
+                HeaderName::from_bytes(name.as_bytes())?,
+                HeaderValue::from_str(value)?,
+            );
+        }
+        Ok(Self {
+            name,
+            formatting,
+            client: reqwest::Client::builder()
+                .timeout(TIMEOUT)
+                .default_headers(default_headers)
+                .build()?,
+            url: url.to_string(),
+        })
+    }
+}
+
+#[async_trait]
+impl Publisher for Webhook {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// There's no way to check a webhook without posting to it
+    async fn verify(&self) -> Result<()> {
+        Ok(())
+    }
+
+    async fn publish(&self, post: &str) -> Result<()> {
+        self.client
+            .post(&self.url)
+            .json(&serde_json::json!({ "text": self.formatting.apply(post) }))
+            .send()
+            .await
+            .map_err(reqwest::Error::without_url)?
+            .error_for_status()
+            .map_err(reqwest::Error::without_url)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::publisher::{MarkdownFlavor, Markup};
+    use crate::stub::Stub;
+
+    const PLAIN: Formatting = Formatting {
+        markup: Markup::Plain,
+        max_chars: None,
+        markdown: MarkdownFlavor::CommonMark,
+    };
+
+    fn webhook(stub: &Stub, headers: &[(&str, &str)]) -> Webhook {
+        let headers = headers
+            .iter()
+            .map(|&(name, value)| (name.to_string(), value.to_string()))
+            .collect();
+        Webhook::new(
+            "hook".to_string(),
+            PLAIN,
+            &format!("{}/hook", stub.url),
+            &headers,
+        )
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn posts_the_text_with_the_headers() {
+        let stub = Stub::start(&[(204, "")]).await;
+        let webhook = webhook(
+            &stub,
+            &[("Authorization", "Bearer secret"), ("X-Bot", "vader")],
+        );
+        webhook.publish("Lund: Sol\nTemp 20°C").await.unwrap();
+        let requests = stub.requests();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].method, "POST");
+        assert_eq!(requests[0].path, "/hook");
+        assert_eq!(requests[0].header("authorization"), Some("Bearer secret"));
+        assert_eq!(requests[0].header("x-bot"), Some("vader"));
+        assert_eq!(
+            requests[0].json(),
+            serde_json::json!({ "text": "Lund: Sol\nTemp 20°C" })
+        );
+    }
+
+    #[tokio::test]
+    async fn fails_on_error_status() {
+        let stub = Stub::start(&[(500, "{}")]).await;
+        let e = webhook(&stub, &[]).publish("Hej").await.unwrap_err();
+        assert!(e.to_string().contains("500"), "{}", e);
+    }
+
+    #[tokio::test]
+    async fn keeps_the_url_out_of_errors() {
+        let stub = Stub::start(&[(401, "{}")]).await;
+        let webhook = Webhook::new(
+            "hook".to_string(),
+            PLAIN,
+            &format!("{}/hook?key=secret-key", stub.url),
+            &BTreeMap::new(),
+        )
+        .unwrap();
+        let e = webhook.publish("Hej").await.unwrap_err();
+        assert!(!format!("{:#}", e).contains("secret-key"), "{:#}", e);
+    }
+
+    #[test]
+    fn rejects_invalid_headers() {
+        let headers = BTreeMap::from([("Bad Header".to_string(), "x".to_string())]);
+        assert!(Webhook::new("hook".to_string(), PLAIN, "http://localhost", &headers).is_err());
+    }
+}
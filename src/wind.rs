@@ -0,0 +1,190 @@
+//! Wind in m/s, described by the Swedish wind scale
+use std::fmt;
+
+/// Wind speed and direction
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Wind {
+    /// Mean wind speed in m/s
+    pub speed: f32,
+    /// Peak gusts in m/s
+    pub gust: Option<f32>,
+    /// Direction the wind blows from, in Swedish
+    pub direction: Option<&'static str>,
+}
+
+/// Gusts from which they're worth mentioning, in m/s
+const NOTABLE_GUST: f32 = 14.0;
+
+/// Wind classes as used by SMHI
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum WindClass {
+    Calm,
+    Light,
+    Moderate,
+    Fresh,
+    Strong,
+    Storm,
+    Hurricane,
+}
+
+impl WindClass {
+    pub fn from_speed(speed: f32) -> Self {
+        match speed {
+            s if s < 0.3 => WindClass::Calm,
+            s if s < 3.4 => WindClass::Light,
+            s if s < 8.0 => WindClass::Moderate,
+            s if s < 13.9 => WindClass::Fresh,
+            s if s < 24.5 => WindClass::Strong,
+            s if s < 32.7 => WindClass::Storm,
+            _ => WindClass::Hurricane,
+        }
+    }
+
+    pub fn to_swedish(self) -> &'static str {
+        match self {
+            WindClass::Calm => "lugnt",
+            WindClass::Light => "svag vind",
+            WindClass::Moderate => "måttlig vind",
+            WindClass::Fresh => "frisk vind",
+            WindClass::Strong => "hård vind",
+            WindClass::Storm => "storm",
+            WindClass::Hurricane => "orkan",
+        }
+    }
+}
+
+pub fn kmph_to_ms(kmph: f32) -> f32 {
+    kmph / 3.6
+}
+
+/// Translate a 16-point compass direction, e.g. "SW" to "sydvästlig"
+pub fn direction_to_swedish(direction: &str) -> Option<&'static str> {
+    let s = match direction {
+        "N" => "nordlig",
+        "NNE" => "nordnordostlig",
+        "NE" => "nordostlig",
+        "ENE" => "ostnordostlig",
+        "E" => "ostlig",
+        "ESE" => "ostsydostlig",
+        "SE" => "sydostlig",
+        "SSE" => "sydsydostlig",
+        "S" => "sydlig",
+        "SSW" => "sydsydvästlig",
+        "SW" => "sydvästlig",
+        "WSW" => "västsydvästlig",
+        "W" => "västlig",
+        "WNW" => "västnordvästlig",
+        "NW" => "nordvästlig",
+        "NNW" => "nordnordvästlig",
+        _ => return None,
+    };
+    Some(s)
+}
+
+impl Wind {
+    pub fn class(&self) -> WindClass {
+        WindClass::from_speed(self.speed)
+    }
+
+    /// Gusts if they are strong enough to mention
+    pub fn notable_gust(&self) -> Option<f32> {
+        self.gust
+            .filter(|&gust| gust >= NOTABLE_GUST && gust >= self.speed * 1.5)
+    }
+}
+
+/// E.g. "frisk sydvästlig vind, 9 m/s, i byarna 17 m/s"
+impl fmt::Display for Wind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let class = self.class();
+        if class == WindClass::Calm {
+            return write!(f, "{}", class.to_swedish());
+        }
+        let description = match (self.direction, class.to_swedish().split_once(' ')) {
+            (Some(direction), Some((strength, wind))) => {
+                format!("{} {} {}", strength, direction, wind)
+            }
+            (Some(direction), None) => format!("{} {}", direction, class.to_swedish()),
+            (None, _) => class.to_swedish().to_string(),
+        };
+        write!(f, "{}, {} m/s", description, self.speed.round() as i32)?;
+        if let Some(gust) = self.notable_gust() {
+            write!(f, ", i byarna {} m/s", gust.round() as i32)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn classifies_by_the_smhi_scale() {
+        for (speed, class) in [
+            (0.0, WindClass::Calm),
+            (0.29, WindClass::Calm),
+            (0.3, WindClass::Light),
+            (3.39, WindClass::Light),
+            (3.4, WindClass::Moderate),
+            (7.99, WindClass::Moderate),
+            (8.0, WindClass::Fresh),
+            (13.89, WindClass::Fresh),
+            (13.9, WindClass::Strong),
+            (24.49, WindClass::Strong),
+            (24.5, WindClass::Storm),
+            (32.69, WindClass::Storm),
+            (32.7, WindClass::Hurricane),
+        ] {
+            assert_eq!(WindClass::from_speed(speed), class, "{} m/s", speed);
+        }
+    }
+
+    fn wind(speed: f32, gust: Option<f32>) -> Wind {
+        Wind {
+            speed,
+            gust,
+            direction: Some("sydvästlig"),
+        }
+    }
+
+    #[test]
+    fn mentions_strong_gusts_only() {
+        for (speed, gust, notable) in [
+            (5.0, None, None),
+            // Strong enough and well above the mean wind
+            (9.0, Some(14.0), Some(14.0)),
+            (9.0, Some(13.9), None),
+            // Not much above the mean wind
+            (10.0, Some(14.9), None),
+            (10.0, Some(15.0), Some(15.0)),
+        ] {
+            assert_eq!(
+                wind(speed, gust).notable_gust(),
+                notable,
+                "{} {:?}",
+                speed,
+                gust
+            );
+        }
+    }
+
+    #[test]
+    fn describes_the_wind() {
+        assert_eq!(
+            wind(9.2, Some(17.4)).to_string(),
+            "frisk sydvästlig vind, 9 m/s, i byarna 17 m/s"
+        );
+        assert_eq!(
+            wind(9.2, Some(12.0)).to_string(),
+            "frisk sydvästlig vind, 9 m/s"
+        );
+        assert_eq!(wind(26.0, None).to_string(), "sydvästlig storm, 26 m/s");
+        assert_eq!(wind(0.1, Some(1.0)).to_string(), "lugnt");
+        let unknown = Wind {
+            direction: direction_to_swedish("X"),
+            ..wind(4.0, None)
+        };
+        assert_eq!(unknown.to_string(), "måttlig vind, 4 m/s");
+    }
+}